@@ -96,6 +96,16 @@
               "8118/tcp" = { };
             };
           };
+          sink-sqlite = {
+            description = "Integration to populate a SQLite table with onchain data";
+            path = ./sinks/sink-sqlite;
+            volumes = {
+              "/data" = { };
+            };
+            ports = {
+              "8118/tcp" = { };
+            };
+          };
           cli = {
             description = "Apibara CLI tool";
             path = ./cli;
@@ -106,6 +116,7 @@
               "sink-postgres"
               "sink-mongo"
               "sink-parquet"
+              "sink-sqlite"
             ];
             volumes = {
               "/data" = { };
@@ -16,7 +16,9 @@ use tracing::Subscriber;
 pub use opentelemetry::metrics::{ObservableCounter, ObservableGauge};
 pub use opentelemetry::{Context, Key, KeyValue};
 use tracing_opentelemetry::MetricsLayer;
-use tracing_subscriber::{prelude::*, registry::LookupSpan, EnvFilter, Layer};
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, prelude::*, registry::LookupSpan, EnvFilter, Layer,
+};
 
 pub use opentelemetry::metrics::{Counter, Meter};
 
@@ -39,6 +41,19 @@ pub fn meter(name: &'static str) -> Meter {
 }
 
 pub fn init_opentelemetry() -> Result<(), OpenTelemetryInitError> {
+    init_opentelemetry_with_log_writer(BoxMakeWriter::new(std::io::stdout))
+}
+
+/// Same as [init_opentelemetry], but log messages are written to standard error.
+///
+/// Used by programs that write their data to standard output.
+pub fn init_opentelemetry_with_stderr_logs() -> Result<(), OpenTelemetryInitError> {
+    init_opentelemetry_with_log_writer(BoxMakeWriter::new(std::io::stderr))
+}
+
+fn init_opentelemetry_with_log_writer(
+    log_writer: BoxMakeWriter,
+) -> Result<(), OpenTelemetryInitError> {
     // The otel sdk doesn't follow the disabled env variable flag.
     // so we manually implement it to disable otel exports.
     // we diverge from the spec by defaulting to disabled.
@@ -50,7 +65,7 @@ pub fn init_opentelemetry() -> Result<(), OpenTelemetryInitError> {
         std::env::set_var("RUST_LOG", "info");
     }
 
-    let mut layers = vec![stdout()];
+    let mut layers = vec![logs(log_writer)];
 
     if !sdk_disabled {
         let otel_layer = otel()?;
@@ -102,7 +117,7 @@ where
     Ok(otel_layer)
 }
 
-fn stdout<S>() -> BoxedLayer<S>
+fn logs<S>(writer: BoxMakeWriter) -> BoxedLayer<S>
 where
     S: Subscriber,
     for<'a> S: LookupSpan<'a>,
@@ -116,6 +131,7 @@ where
 
     if json_fmt {
         tracing_subscriber::fmt::layer()
+            .with_writer(writer)
             .with_ansi(false)
             .with_target(true)
             .json()
@@ -123,6 +139,7 @@ where
             .boxed()
     } else {
         tracing_subscriber::fmt::layer()
+            .with_writer(writer)
             .with_ansi(true)
             .with_target(true)
             .with_filter(log_env_filter)
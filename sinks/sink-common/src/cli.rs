@@ -1,7 +1,9 @@
 use std::{fmt, fs, path::Path};
 
 use anstyle::{AnsiColor, Style};
-use apibara_observability::init_opentelemetry;
+use apibara_observability::{
+    init_opentelemetry, init_opentelemetry_with_stderr_logs, OpenTelemetryInitError,
+};
 use apibara_script::{Script, ScriptOptions};
 use clap::builder::Styles;
 use error_stack::{Result, ResultExt};
@@ -47,7 +49,21 @@ pub fn load_script(path: &str, options: ScriptOptions) -> Result<Script, LoadScr
 
 /// Initialize opentelemetry and the sigint (ctrl-c) handler.
 pub fn initialize_sink(ct: CancellationToken) -> Result<(), SinkConnectorError> {
-    init_opentelemetry()
+    initialize_sink_with_opentelemetry(init_opentelemetry, ct)
+}
+
+/// Same as [initialize_sink], but log messages are written to standard error.
+///
+/// Used by sinks that write data to standard output.
+pub fn initialize_sink_with_stderr_logs(ct: CancellationToken) -> Result<(), SinkConnectorError> {
+    initialize_sink_with_opentelemetry(init_opentelemetry_with_stderr_logs, ct)
+}
+
+fn initialize_sink_with_opentelemetry(
+    init: fn() -> Result<(), OpenTelemetryInitError>,
+    ct: CancellationToken,
+) -> Result<(), SinkConnectorError> {
+    init()
         .change_context(SinkConnectorError::Configuration)
         .attach_printable("failed to initialize opentelemetry")?;
 
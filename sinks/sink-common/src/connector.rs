@@ -16,8 +16,8 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, trace, warn};
 
 use crate::{
-    persistence::Persistence, status::StatusServer, DisplayCursor, PersistenceClient,
-    SinkConnectorError, StatusServerClient,
+    error::is_fatal_error, persistence::Persistence, status::StatusServer, DisplayCursor,
+    PersistenceClient, SinkConnectorError, StatusServerClient,
 };
 
 pub trait SinkOptions: DeserializeOwned {
@@ -30,6 +30,15 @@ pub enum CursorAction {
     Skip,
 }
 
+/// Health of the sink's connection to its backend.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum SinkHealth {
+    #[default]
+    Healthy,
+    /// The sink can't write data, for example because its connection was lost.
+    Unhealthy(String),
+}
+
 #[derive(Debug, Clone)]
 pub struct Context {
     pub cursor: Option<Cursor>,
@@ -54,6 +63,20 @@ pub trait Sink {
 
     async fn handle_invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), Self::Error>;
 
+    /// Returns `true` if the sink stores the cursor in the same transaction as the data.
+    ///
+    /// Transactional sinks are responsible for storing the cursor in `handle_data` and
+    /// `handle_invalidate`. The connector then reads the starting cursor from the sink
+    /// instead of the persistence backend.
+    fn is_transactional(&self) -> bool {
+        false
+    }
+
+    /// Reads the cursor stored by a transactional sink.
+    async fn get_cursor(&mut self) -> Result<Option<Cursor>, Self::Error> {
+        Ok(None)
+    }
+
     async fn cleanup(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -61,6 +84,11 @@ pub trait Sink {
     async fn handle_heartbeat(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    /// Returns the health of the sink, reported by the status server.
+    fn health(&self) -> SinkHealth {
+        SinkHealth::Healthy
+    }
 }
 
 #[derive(Debug)]
@@ -169,11 +197,19 @@ where
             }
         }
 
-        let starting_cursor = persistence
-            .get_cursor()
-            .await
-            .change_context(SinkConnectorError::Temporary)
-            .attach_printable("failed to get starting cursor")?;
+        let starting_cursor = if self.sink.is_transactional() {
+            self.sink
+                .get_cursor()
+                .await
+                .change_context(SinkConnectorError::Temporary)
+                .attach_printable("failed to get starting cursor from sink")?
+        } else {
+            persistence
+                .get_cursor()
+                .await
+                .change_context(SinkConnectorError::Temporary)
+                .attach_printable("failed to get starting cursor")?
+        };
 
         if starting_cursor.is_some() {
             info!(cursor = ?starting_cursor, "restarting from last cursor");
@@ -309,6 +345,12 @@ where
                 }
                 Err(err) => {
                     warn!(err = ?err, "handle_invalidate error");
+                    if is_fatal_error(&err) {
+                        return Err(err)
+                            .change_context(SinkConnectorError::Fatal)
+                            .attach_printable("handle invalidate failed with a fatal error");
+                    }
+                    self.report_health(status_client).await;
                     if ct.is_cancelled() {
                         return Err(err)
                             .change_context(SinkConnectorError::Fatal)
@@ -381,6 +423,12 @@ where
                 }
                 Err(err) => {
                     warn!(err = ?err, "handle_data error");
+                    if is_fatal_error(&err) {
+                        return Err(err)
+                            .change_context(SinkConnectorError::Fatal)
+                            .attach_printable("handle data failed with a fatal error");
+                    }
+                    self.report_health(status_client).await;
                     if ct.is_cancelled() {
                         return Err(err)
                             .change_context(SinkConnectorError::Fatal)
@@ -410,7 +458,7 @@ where
         B: Message + Default + Serialize,
         P: PersistenceClient + Send,
     {
-        match message {
+        let result = match message {
             DataMessage::Data {
                 cursor,
                 end_cursor,
@@ -442,6 +490,18 @@ where
                     .attach_printable("failed to update status server after heartbeat")?;
                 Ok(())
             }
+        };
+
+        self.report_health(status_client).await;
+        result
+    }
+
+    /// Reports the sink health to the status server.
+    ///
+    /// Failures are only logged, so that they don't hide the result of the handler.
+    async fn report_health(&self, status_client: &StatusServerClient) {
+        if let Err(err) = status_client.update_health(self.sink.health()).await {
+            warn!(err = ?err, "failed to update sink health");
         }
     }
 }
@@ -7,6 +7,9 @@ use error_stack::Result;
 /// Sink developers should default to returning `SinkError::Temporary` for all errors.
 /// `SinkError::Configuration` should be returned for configuration-related errors.
 /// `SinkError::Fatal` should only be returned from `Sink::handle_data` and `Sink::handle_invalidate`.
+///
+/// Sinks can attach `SinkConnectorError::Fatal` to the errors returned by
+/// `Sink::handle_data` and `Sink::handle_invalidate` to stop the connector from retrying.
 #[derive(Debug)]
 pub enum SinkConnectorError {
     /// Configuration error. Should not retry.
@@ -17,6 +20,14 @@ pub enum SinkConnectorError {
     Fatal,
 }
 
+/// Returns `true` if the error was marked as not retryable by the sink.
+pub fn is_fatal_error<C>(err: &error_stack::Report<C>) -> bool {
+    matches!(
+        err.downcast_ref::<SinkConnectorError>(),
+        Some(SinkConnectorError::Fatal)
+    )
+}
+
 pub trait ReportExt {
     fn to_exit_code(&self) -> ExitCode;
 }
@@ -4,6 +4,8 @@ use apibara_core::node;
 use error_stack::{Result, ResultExt};
 use tokio::sync::mpsc;
 
+use crate::SinkHealth;
+
 #[derive(Debug)]
 pub struct StatusServerClientError;
 impl error_stack::Context for StatusServerClientError {}
@@ -23,6 +25,8 @@ pub enum StatusMessage {
     UpdateCursor(Option<node::v1alpha2::Cursor>),
     /// Send a heartbeat to the status service.
     Heartbeat,
+    /// Update the health of the sink.
+    UpdateHealth(SinkHealth),
 }
 
 #[derive(Clone)]
@@ -70,4 +74,14 @@ impl StatusServerClient {
             .attach_printable("failed to send update cursor request")?;
         Ok(())
     }
+
+    /// Update the health of the sink.
+    pub async fn update_health(&self, health: SinkHealth) -> Result<(), StatusServerClientError> {
+        self.tx
+            .send(StatusMessage::UpdateHealth(health))
+            .await
+            .change_context(StatusServerClientError)
+            .attach_printable("failed to send update health request")?;
+        Ok(())
+    }
 }
@@ -2,6 +2,7 @@ use apibara_sdk::StreamClient;
 use tonic::async_trait;
 
 use super::service::StatusServiceClient;
+use crate::SinkHealth;
 
 pub mod proto {
     tonic::include_proto!("apibara.sink.v1");
@@ -45,6 +46,17 @@ impl proto::status_server::Status for Server {
             .await
             .map_err(|_| tonic::Status::internal("failed to get sink cursors"))?;
 
+        let health = self
+            .client
+            .get_health()
+            .await
+            .map_err(|_| tonic::Status::internal("failed to get sink health"))?;
+
+        let (status, reason) = match health {
+            SinkHealth::Healthy => (proto::SinkStatus::Running, None),
+            SinkHealth::Unhealthy(reason) => (proto::SinkStatus::Errored, Some(reason)),
+        };
+
         let dna_status = self
             .stream_client
             .clone()
@@ -53,11 +65,11 @@ impl proto::status_server::Status for Server {
             .map_err(|_| tonic::Status::internal("failed to get status from dna server"))?;
 
         let response = proto::GetStatusResponse {
-            status: proto::SinkStatus::Running as i32,
+            status: status as i32,
             starting_block: cursors.starting.map(|cursor| cursor.order_key),
             current_block: cursors.current.map(|cursor| cursor.order_key),
             head_block: dna_status.current_head.map(|cursor| cursor.order_key),
-            reason: None,
+            reason,
         };
 
         Ok(tonic::Response::new(response))
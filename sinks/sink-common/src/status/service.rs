@@ -8,6 +8,7 @@ use tonic_health::pb::health_server::{Health, HealthServer};
 use tracing::info;
 
 use crate::status::server::StatusServer;
+use crate::SinkHealth;
 
 use super::client::{StatusMessage, StatusServerClient};
 
@@ -18,6 +19,8 @@ const MESSAGE_TIMEOUT: Duration = Duration::from_secs(60);
 enum RequestMessage {
     /// Request indexer cursors (starting, current)
     GetCursor(oneshot::Sender<Cursors>),
+    /// Request the sink health.
+    GetHealth(oneshot::Sender<SinkHealth>),
 }
 
 #[derive(Debug)]
@@ -84,8 +87,10 @@ impl StatusService {
         //  - Responds to requests by the status grpc service.
         //  - Sets the health status to not serving if no messages are received for a while.
         //  - Sets the health status to serving if a message is received.
+        //  - Sets the health status to not serving if the sink is unhealthy.
         let mut starting_cursor = None;
         let mut cursor = None;
+        let mut health = SinkHealth::Healthy;
         loop {
             tokio::select! {
                 msg = self.status_rx.recv() => {
@@ -100,6 +105,14 @@ impl StatusService {
                             self.health_reporter.set_serving::<StatusServer>().await;
                             starting_cursor = new_starting_cursor;
                         }
+                        StatusMessage::UpdateHealth(new_health) => {
+                            if new_health == SinkHealth::Healthy {
+                                self.health_reporter.set_serving::<StatusServer>().await;
+                            } else {
+                                self.health_reporter.set_not_serving::<StatusServer>().await;
+                            }
+                            health = new_health;
+                        }
                     }
                 }
                 msg = self.request_rx.recv() => {
@@ -114,6 +127,11 @@ impl StatusService {
                                 .map_err(|_| StatusServiceError)
                                 .attach_printable("failed to reply with cursor")?;
                         }
+                        RequestMessage::GetHealth(tx) => {
+                            tx.send(health.clone())
+                                .map_err(|_| StatusServiceError)
+                                .attach_printable("failed to reply with health")?;
+                        }
                     }
                 }
                 _ = tokio::time::sleep(MESSAGE_TIMEOUT) => {
@@ -155,4 +173,19 @@ impl StatusServiceClient {
             .attach_printable("failed to receive cursor response")?;
         Ok(cursors)
     }
+
+    /// Request the sink health from the status service.
+    pub async fn get_health(&self) -> Result<SinkHealth, StatusServiceClientError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(RequestMessage::GetHealth(tx))
+            .await
+            .change_context(StatusServiceClientError)
+            .attach_printable("failed to send get health request")?;
+        let health = rx
+            .await
+            .change_context(StatusServiceClientError)
+            .attach_printable("failed to receive health response")?;
+        Ok(health)
+    }
 }
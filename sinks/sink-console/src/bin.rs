@@ -1,8 +1,8 @@
 use std::process::ExitCode;
 
 use apibara_sink_common::{
-    apibara_cli_style, initialize_sink, run_sink_connector, OptionsFromCli, ReportExt,
-    SinkConnectorError,
+    apibara_cli_style, initialize_sink_with_stderr_logs, run_sink_connector, OptionsFromCli,
+    ReportExt, SinkConnectorError,
 };
 use apibara_sink_console::{ConsoleSink, SinkConsoleOptions};
 use clap::{Args, Parser, Subcommand};
@@ -43,7 +43,8 @@ async fn main() -> ExitCode {
 
 async fn run_with_args(args: Cli) -> Result<(), SinkConnectorError> {
     let ct = CancellationToken::new();
-    initialize_sink(ct.clone())?;
+    // Data is written to standard output, keep log messages separate.
+    initialize_sink_with_stderr_logs(ct.clone())?;
 
     match args.subcommand {
         Command::Run(args) => {
@@ -1,12 +1,94 @@
+use std::path::PathBuf;
+
 use apibara_sink_common::SinkOptions;
 use clap::Args;
+use error_stack::{Result, ResultExt};
+use serde::Deserialize;
+
+use crate::sink::SinkConsoleError;
+
+#[derive(Debug, Default)]
+pub struct SinkConsoleConfiguration {
+    pub format: OutputFormat,
+    pub output: Output,
+}
+
+/// How data is printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON, logged together with the other log messages.
+    #[default]
+    Log,
+    /// Pretty-printed JSON, one record for each batch.
+    Pretty,
+    /// Compact JSON, one line for each item in the batch.
+    JsonLines,
+    /// A table with one row for each item in the batch.
+    Table,
+}
+
+/// Where data is printed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Stdout,
+    /// Append to the given file.
+    File(PathBuf),
+}
 
 #[derive(Debug, Args, Default, SinkOptions)]
 #[sink_options(tag = "console")]
-pub struct SinkConsoleOptions {}
+pub struct SinkConsoleOptions {
+    /// How data is printed: `log`, `pretty`, `jsonl` or `table`.
+    ///
+    /// Defaults to `log`, which prints data together with the other log messages, or
+    /// to `jsonl` when writing to a file.
+    #[arg(long, env = "CONSOLE_FORMAT")]
+    format: Option<String>,
+
+    /// Write data to this file instead of standard output.
+    ///
+    /// Use `-` for standard output.
+    #[arg(long, env = "CONSOLE_OUTPUT")]
+    output: Option<String>,
+}
 
 impl SinkOptions for SinkConsoleOptions {
-    fn merge(self, _other: SinkConsoleOptions) -> Self {
-        SinkConsoleOptions::default()
+    fn merge(self, other: SinkConsoleOptions) -> Self {
+        Self {
+            format: self.format.or(other.format),
+            output: self.output.or(other.output),
+        }
+    }
+}
+
+impl SinkConsoleOptions {
+    pub fn to_console_configuration(self) -> Result<SinkConsoleConfiguration, SinkConsoleError> {
+        let output = match self.output.as_deref() {
+            None | Some("-") => Output::Stdout,
+            Some(path) => Output::File(PathBuf::from(path)),
+        };
+
+        let format = match self.format.as_deref().map(str::trim) {
+            None if output == Output::Stdout => OutputFormat::Log,
+            None => OutputFormat::JsonLines,
+            Some("log") => OutputFormat::Log,
+            Some("pretty") => OutputFormat::Pretty,
+            Some("jsonl") => OutputFormat::JsonLines,
+            Some("table") => OutputFormat::Table,
+            Some(format) => {
+                return Err(SinkConsoleError).attach_printable_lazy(|| {
+                    format!("unknown format {format}, expected log, pretty, jsonl or table")
+                });
+            }
+        };
+
+        // Log messages are always written to standard error.
+        if format == OutputFormat::Log && output != Output::Stdout {
+            return Err(SinkConsoleError)
+                .attach_printable("the log format can't be written to a file");
+        }
+
+        Ok(SinkConsoleConfiguration { format, output })
     }
 }
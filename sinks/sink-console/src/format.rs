@@ -0,0 +1,277 @@
+use std::fmt::Write;
+
+use apibara_core::node::v1alpha2::Cursor;
+use apibara_sink_common::{Context, DisplayCursor};
+use error_stack::{Result, ResultExt};
+use serde_json::{json, Value};
+
+use crate::configuration::OutputFormat;
+use crate::sink::SinkConsoleError;
+
+/// Maximum number of characters in a table cell.
+const MAX_CELL_WIDTH: usize = 40;
+
+/// Formats the data in a batch, including its cursor and finality.
+///
+/// The returned string ends with a newline.
+pub fn format_data(
+    format: OutputFormat,
+    ctx: &Context,
+    batch: &Value,
+) -> Result<String, SinkConsoleError> {
+    match format {
+        OutputFormat::Log => to_pretty_json(batch),
+        OutputFormat::Pretty => to_pretty_json(&data_record(ctx, batch)),
+        OutputFormat::JsonLines => {
+            let mut output = String::new();
+            for item in batch_items(batch) {
+                output.push_str(&to_json_line(&data_record(ctx, item))?);
+            }
+            Ok(output)
+        }
+        OutputFormat::Table => Ok(format_table(ctx, batch)),
+    }
+}
+
+/// Formats an invalidation record.
+///
+/// The returned string ends with a newline.
+pub fn format_invalidate(
+    format: OutputFormat,
+    cursor: &Option<Cursor>,
+) -> Result<String, SinkConsoleError> {
+    let record = json!({
+        "type": "invalidate",
+        "cursor": cursor,
+    });
+
+    match format {
+        OutputFormat::Log | OutputFormat::Pretty => to_pretty_json(&record),
+        OutputFormat::JsonLines => to_json_line(&record),
+        OutputFormat::Table => Ok(format!("invalidate after {}\n", DisplayCursor(cursor))),
+    }
+}
+
+fn data_record(ctx: &Context, data: &Value) -> Value {
+    json!({
+        "type": "data",
+        "cursor": ctx.cursor,
+        "end_cursor": ctx.end_cursor,
+        "finality": ctx.finality,
+        "data": data,
+    })
+}
+
+/// Returns the items in the batch, or the batch itself if it's not an array.
+fn batch_items(batch: &Value) -> Vec<&Value> {
+    match batch {
+        Value::Array(items) => items.iter().collect(),
+        batch => vec![batch],
+    }
+}
+
+fn to_pretty_json(value: &Value) -> Result<String, SinkConsoleError> {
+    let mut output = serde_json::to_string_pretty(value)
+        .change_context(SinkConsoleError)
+        .attach_printable("failed to serialize batch data")?;
+    output.push('\n');
+    Ok(output)
+}
+
+fn to_json_line(value: &Value) -> Result<String, SinkConsoleError> {
+    let mut output = serde_json::to_string(value)
+        .change_context(SinkConsoleError)
+        .attach_printable("failed to serialize batch data")?;
+    output.push('\n');
+    Ok(output)
+}
+
+/// Formats the batch as a table, with one column for each field of the items.
+///
+/// Items that are not objects are shown in the `value` column.
+fn format_table(ctx: &Context, batch: &Value) -> String {
+    let mut output = format!(
+        "data {} - {} ({:?})\n",
+        DisplayCursor(&ctx.cursor),
+        ctx.end_cursor,
+        ctx.finality
+    );
+
+    let items = batch_items(batch);
+    if items.is_empty() {
+        output.push_str("(no data)\n");
+        return output;
+    }
+
+    let mut columns: Vec<&str> = Vec::new();
+    for item in &items {
+        match item {
+            Value::Object(fields) => {
+                for key in fields.keys() {
+                    if !columns.contains(&key.as_str()) {
+                        columns.push(key);
+                    }
+                }
+            }
+            _ => {
+                if !columns.contains(&"value") {
+                    columns.push("value");
+                }
+            }
+        }
+    }
+
+    let rows = items
+        .iter()
+        .map(|item| {
+            columns
+                .iter()
+                .map(|column| match item {
+                    Value::Object(fields) => fields.get(*column).map(cell).unwrap_or_default(),
+                    item if *column == "value" => cell(item),
+                    _ => String::new(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            rows.iter()
+                .map(|row| row[index].chars().count())
+                .chain([column.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let header = columns
+        .iter()
+        .map(|column| column.to_string())
+        .collect::<Vec<_>>();
+    write_row(&mut output, &header, &widths);
+    let separator = widths
+        .iter()
+        .map(|width| "-".repeat(*width))
+        .collect::<Vec<_>>();
+    write_row(&mut output, &separator, &widths);
+    for row in &rows {
+        write_row(&mut output, row, &widths);
+    }
+
+    output
+}
+
+fn write_row(output: &mut String, row: &[String], widths: &[usize]) {
+    let cells = row
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{cell:<width$}"))
+        .collect::<Vec<_>>();
+    // Writing to a string never fails.
+    let _ = writeln!(output, "{}", cells.join(" | ").trim_end());
+}
+
+/// Returns the text of a table cell, truncated to fit in the table.
+fn cell(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.replace('\n', " "),
+        value => value.to_string(),
+    };
+
+    if text.chars().count() <= MAX_CELL_WIDTH {
+        return text;
+    }
+
+    let mut truncated = text.chars().take(MAX_CELL_WIDTH - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::node::v1alpha2::{Cursor, DataFinality};
+    use apibara_sink_common::Context;
+    use serde_json::{json, Value};
+
+    use super::{format_data, format_invalidate};
+    use crate::configuration::OutputFormat;
+
+    fn new_context() -> Context {
+        Context {
+            cursor: Some(Cursor {
+                order_key: 10,
+                unique_key: vec![0xca, 0xfe],
+            }),
+            end_cursor: Cursor {
+                order_key: 11,
+                unique_key: vec![0xbe, 0xef],
+            },
+            finality: DataFinality::DataStatusAccepted,
+        }
+    }
+
+    #[test]
+    pub fn test_format_json_lines() {
+        let ctx = new_context();
+        let batch = json!([{ "block": 10, "amount": "1" }, { "block": 10, "amount": "2" }]);
+
+        let output = format_data(OutputFormat::JsonLines, &ctx, &batch).unwrap();
+        let lines = output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "data");
+        assert_eq!(lines[0]["cursor"]["orderKey"], 10);
+        assert_eq!(lines[0]["end_cursor"]["orderKey"], 11);
+        assert_eq!(lines[0]["finality"], json!(ctx.finality));
+        assert_eq!(lines[1]["data"], batch[1]);
+
+        let output = format_invalidate(OutputFormat::JsonLines, &None).unwrap();
+        assert!(output.ends_with('\n') && output.lines().count() == 1);
+        assert_eq!(
+            serde_json::from_str::<Value>(&output).unwrap(),
+            json!({ "type": "invalidate", "cursor": null })
+        );
+    }
+
+    #[test]
+    pub fn test_format_pretty() {
+        let ctx = new_context();
+        let batch = json!([{ "block": 10 }]);
+
+        let output = format_data(OutputFormat::Pretty, &ctx, &batch).unwrap();
+        let record = serde_json::from_str::<Value>(&output).unwrap();
+        assert_eq!(record["type"], "data");
+        assert_eq!(record["data"], batch);
+
+        let output = format_data(OutputFormat::Log, &ctx, &batch).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&output).unwrap(), batch);
+    }
+
+    #[test]
+    pub fn test_format_table() {
+        let ctx = new_context();
+        let batch = json!([
+            { "block": 10, "from": "0xabc" },
+            { "block": 10, "to": "0x".to_string() + &"f".repeat(64) },
+            "transfer",
+        ]);
+
+        let output = format_data(OutputFormat::Table, &ctx, &batch).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("data Cursor(10, 0xcafe) - Cursor(11, 0xbeef)"));
+        assert_eq!(lines[1], format!("block | from  | {:<40} | value", "to"));
+        assert_eq!(lines[3], "10    | 0xabc |");
+        assert!(lines[4].ends_with("ffff… |"));
+        assert!(lines[5].ends_with("| transfer"));
+
+        let output = format_data(OutputFormat::Table, &ctx, &json!([])).unwrap();
+        assert!(output.ends_with("(no data)\n"));
+    }
+}
@@ -1,5 +1,6 @@
 mod configuration;
+mod format;
 mod sink;
 
-pub use self::configuration::SinkConsoleOptions;
+pub use self::configuration::{Output, OutputFormat, SinkConsoleConfiguration, SinkConsoleOptions};
 pub use self::sink::{ConsoleSink, SinkConsoleError};
@@ -5,9 +5,13 @@ use apibara_sink_common::{Context, CursorAction, DisplayCursor, Sink};
 use async_trait::async_trait;
 use error_stack::{Result, ResultExt};
 use serde_json::Value;
+use tokio::fs::OpenOptions;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
 use tracing::{debug, info, instrument};
 
-use crate::configuration::SinkConsoleOptions;
+use crate::configuration::{Output, OutputFormat, SinkConsoleConfiguration, SinkConsoleOptions};
+use crate::format::{format_data, format_invalidate};
 
 #[derive(Debug)]
 pub struct SinkConsoleError;
@@ -19,18 +23,58 @@ impl fmt::Display for SinkConsoleError {
     }
 }
 
-#[derive(Default)]
-pub struct ConsoleSink {}
+pub struct ConsoleSink {
+    format: OutputFormat,
+    /// Where data is written.
+    /// Notice that the writer is not `Sync` so we need to wrap it in a mutex.
+    writer: Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+}
+
+impl ConsoleSink {
+    pub async fn new(config: SinkConsoleConfiguration) -> Result<Self, SinkConsoleError> {
+        let writer: Box<dyn AsyncWrite + Send + Unpin> = match &config.output {
+            Output::Stdout => Box::new(io::stdout()),
+            Output::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .change_context(SinkConsoleError)
+                    .attach_printable_lazy(|| format!("failed to open output file {path:?}"))?;
+                Box::new(file)
+            }
+        };
 
-impl ConsoleSink {}
+        Ok(ConsoleSink {
+            format: config.format,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    async fn write(&self, output: &str) -> Result<(), SinkConsoleError> {
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(output.as_bytes())
+            .await
+            .change_context(SinkConsoleError)
+            .attach_printable("failed to write output")?;
+        writer
+            .flush()
+            .await
+            .change_context(SinkConsoleError)
+            .attach_printable("failed to flush output")
+    }
+}
 
 #[async_trait]
 impl Sink for ConsoleSink {
     type Options = SinkConsoleOptions;
     type Error = SinkConsoleError;
 
-    async fn from_options(_options: Self::Options) -> Result<Self, Self::Error> {
-        Ok(ConsoleSink::default())
+    async fn from_options(options: Self::Options) -> Result<Self, Self::Error> {
+        let config = options.to_console_configuration()?;
+        ConsoleSink::new(config).await
     }
 
     #[instrument(skip(self, batch), err(Debug), level = "DEBUG")]
@@ -41,18 +85,24 @@ impl Sink for ConsoleSink {
     ) -> Result<CursorAction, Self::Error> {
         debug!(ctx = %ctx, "handle data");
 
-        let pretty = serde_json::to_string_pretty(batch)
-            .change_context(SinkConsoleError)
-            .attach_printable("failed to serialize batch data")?;
-
-        info!("{}", pretty);
+        let output = format_data(self.format, ctx, batch)?;
+        if self.format == OutputFormat::Log {
+            info!("{}", output.trim_end());
+        } else {
+            self.write(&output).await?;
+        }
 
         Ok(CursorAction::Persist)
     }
 
     #[instrument(skip(self), err(Debug))]
     async fn handle_invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), Self::Error> {
-        info!(cursor = %DisplayCursor(cursor), "invalidating cursor");
-        Ok(())
+        if self.format == OutputFormat::Log {
+            info!(cursor = %DisplayCursor(cursor), "invalidating cursor");
+            return Ok(());
+        }
+
+        let output = format_invalidate(self.format, cursor)?;
+        self.write(&output).await
     }
 }
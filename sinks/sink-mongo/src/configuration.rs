@@ -15,10 +15,53 @@ pub struct SinkMongoOptions {
     /// The collection where to store the data.
     #[arg(long, env = "MONGO_COLLECTION_NAME")]
     pub collection_name: Option<String>,
+    /// The collections where to store the data, used to write to more than one collection.
+    ///
+    /// Each item returned by the transform step specifies its target collection.
+    /// Mutually exclusive with `collection_name`.
+    #[arg(long, env = "MONGO_COLLECTION_NAMES", value_delimiter = ',')]
+    pub collection_names: Option<Vec<String>>,
     /// Enable storing records as entities.
     pub entity_mode: Option<bool>,
+    /// Maximum number of entities updated with a single command. Defaults to 1000.
+    #[arg(long, env = "MONGO_ENTITY_CHUNK_SIZE")]
+    pub entity_chunk_size: Option<usize>,
     #[clap(skip)]
     pub invalidate: Option<Document>,
+    /// Additional indexes created on the collections.
+    #[clap(skip)]
+    pub indexes: Option<Vec<CollectionIndex>>,
+    /// Store the cursor in the `_apibara_cursors` collection, in the same transaction as the data.
+    ///
+    /// When enabled, the starting cursor is read from the database instead of the
+    /// persistence backend. Requires a MongoDB deployment that supports transactions.
+    #[arg(long, env = "MONGO_TRANSACTIONAL")]
+    pub transactional: Option<bool>,
+    /// Identifier of the cursor document in the `_apibara_cursors` collection. Defaults to `default`.
+    ///
+    /// Indexers writing to the same database must use different identifiers.
+    #[arg(long, env = "MONGO_CURSOR_ID")]
+    pub cursor_id: Option<String>,
+    /// Convert the cursor of documents written by older versions of the sink to 64-bit integers.
+    #[arg(long, env = "MONGO_MIGRATE_CURSOR_FORMAT")]
+    pub migrate_cursor_format: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionIndex {
+    /// Collection where the index is created. Defaults to all collections.
+    pub collection: Option<String>,
+    /// Index keys, for example `{ address: 1 }`.
+    pub keys: Document,
+    /// Index name. Defaults to the name generated by MongoDB.
+    pub name: Option<String>,
+    /// Create a unique index.
+    pub unique: Option<bool>,
+    /// Only index the current version of entities, that is documents with a null `_cursor.to`.
+    ///
+    /// Use this option to create unique indexes on entity keys.
+    pub current_only: Option<bool>,
 }
 
 impl SinkOptions for SinkMongoOptions {
@@ -27,8 +70,14 @@ impl SinkOptions for SinkMongoOptions {
             connection_string: self.connection_string.or(other.connection_string),
             database: self.database.or(other.database),
             collection_name: self.collection_name.or(other.collection_name),
+            collection_names: self.collection_names.or(other.collection_names),
             entity_mode: self.entity_mode.or(other.entity_mode),
+            entity_chunk_size: self.entity_chunk_size.or(other.entity_chunk_size),
             invalidate: self.invalidate.or(other.invalidate),
+            indexes: self.indexes.or(other.indexes),
+            transactional: self.transactional.or(other.transactional),
+            cursor_id: self.cursor_id.or(other.cursor_id),
+            migrate_cursor_format: self.migrate_cursor_format.or(other.migrate_cursor_format),
         }
     }
 }
@@ -1,5 +1,7 @@
 mod configuration;
+mod migration;
 mod sink;
 
-pub use self::configuration::SinkMongoOptions;
+pub use self::configuration::{CollectionIndex, SinkMongoOptions};
+pub use self::migration::migrate_cursor_format;
 pub use self::sink::{MongoSink, SinkMongoError};
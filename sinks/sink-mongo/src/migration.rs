@@ -0,0 +1,40 @@
+use error_stack::{Result, ResultExt};
+use mongodb::bson::{doc, Document};
+use mongodb::Collection;
+use tracing::info;
+
+use crate::sink::SinkMongoError;
+
+/// Converts the `_cursor` fields stored with a 32-bit (or floating point) type to 64-bit integers.
+///
+/// Older versions of the sink could store block numbers with a different numeric type. Queries
+/// compare numbers of different types correctly, but mixed types prevent the sink from relying on
+/// the stored format. This function is idempotent and returns the number of updated documents.
+pub async fn migrate_cursor_format(
+    collection: &Collection<Document>,
+) -> Result<u64, SinkMongoError> {
+    let mut modified_count = 0;
+
+    for field in ["_cursor.from", "_cursor.to"] {
+        let filter = doc! { field: { "$type": ["int", "double", "decimal"] } };
+        let update = vec![doc! { "$set": { field: { "$toLong": format!("${field}") } } }];
+
+        let result = collection
+            .update_many(filter, update, None)
+            .await
+            .change_context(SinkMongoError)
+            .attach_printable_lazy(|| {
+                format!("failed to migrate {field} in {}", collection.name())
+            })?;
+
+        modified_count += result.modified_count;
+    }
+
+    info!(
+        collection = %collection.name(),
+        count = modified_count,
+        "migrated cursor format"
+    );
+
+    Ok(modified_count)
+}
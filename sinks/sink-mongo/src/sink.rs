@@ -1,20 +1,25 @@
 use std::fmt;
 
-use apibara_core::node::v1alpha2::Cursor;
+use apibara_core::node::v1alpha2::{Cursor, DataFinality};
 use apibara_sink_common::{Context, CursorAction, DisplayCursor, Sink, ValueExt};
 use async_trait::async_trait;
 use error_stack::{Result, ResultExt};
 use futures_util::TryStreamExt;
-use mongodb::bson::{doc, to_document, Bson, Document};
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::{doc, to_document, Binary, Bson, Document};
 use mongodb::ClientSession;
 
-use mongodb::options::{UpdateModifications, UpdateOptions};
+use mongodb::options::{IndexOptions, UpdateOptions};
+use mongodb::IndexModel;
 use mongodb::{options::ClientOptions, Client, Collection};
 
 use serde_json::Value;
 use tracing::{debug, info, warn};
 
-use crate::configuration::SinkMongoOptions;
+use crate::configuration::{CollectionIndex, SinkMongoOptions};
+use crate::migration::migrate_cursor_format;
+
+const DEFAULT_ENTITY_CHUNK_SIZE: usize = 1_000;
 
 #[derive(Debug)]
 pub struct SinkMongoError;
@@ -27,12 +32,26 @@ impl fmt::Display for SinkMongoError {
 }
 
 pub struct MongoSink {
-    pub collection: Collection<Document>,
+    collections: Vec<Collection<Document>>,
+    /// If true, each item returned by the transform step specifies its target collection.
+    multi_collection: bool,
     invalidate: Option<Document>,
     client: Client,
     mode: Mode,
+    cursor: Option<CursorCollection>,
+    /// If true, writes are wrapped in a multi-document transaction.
+    transactions: bool,
+    /// Maximum number of entities updated with a single command.
+    entity_chunk_size: usize,
+}
+
+/// Collection used to store the cursor in the same transaction as the data.
+struct CursorCollection {
+    cursor_id: String,
+    collection: Collection<Document>,
 }
 
+#[derive(Debug, Clone, Copy)]
 enum Mode {
     /// Store entities as immutable documents.
     Logs,
@@ -55,10 +74,30 @@ impl Sink for MongoSink {
             .database
             .ok_or(SinkMongoError)
             .attach_printable("missing database name")?;
-        let collection_name = options
-            .collection_name
-            .ok_or(SinkMongoError)
-            .attach_printable("missing collection name")?;
+        let (collection_names, multi_collection) =
+            match (options.collection_name, options.collection_names) {
+                (Some(_), Some(_)) => {
+                    return Err(SinkMongoError).attach_printable(
+                        "collection name and collection names are mutually exclusive",
+                    );
+                }
+                (None, None) => {
+                    return Err(SinkMongoError).attach_printable("missing collection name");
+                }
+                (Some(collection_name), None) => (vec![collection_name], false),
+                (None, Some(collection_names)) => {
+                    if collection_names.is_empty() {
+                        return Err(SinkMongoError)
+                            .attach_printable("collection names must not be empty");
+                    }
+                    if options.entity_mode.is_some() {
+                        return Err(SinkMongoError).attach_printable(
+                        "entity mode is selected by each item when writing to multiple collections",
+                    );
+                    }
+                    (collection_names, true)
+                }
+            };
 
         let client_options = ClientOptions::parse(connection_string)
             .await
@@ -69,7 +108,18 @@ impl Sink for MongoSink {
             .change_context(SinkMongoError)
             .attach_printable("failed to create mongo client")?;
         let db = client.database(&db_name);
-        let collection = db.collection::<Document>(&collection_name);
+        let collections = collection_names
+            .iter()
+            .map(|name| db.collection::<Document>(name))
+            .collect::<Vec<_>>();
+
+        if options.migrate_cursor_format.unwrap_or(false) {
+            for collection in &collections {
+                migrate_cursor_format(collection).await?;
+            }
+        }
+
+        create_indexes(&collections, options.indexes.unwrap_or_default()).await?;
 
         let entity_mode = options.entity_mode.unwrap_or(false);
         let mode = if entity_mode {
@@ -78,11 +128,42 @@ impl Sink for MongoSink {
             Mode::Logs
         };
 
+        let entity_chunk_size = options
+            .entity_chunk_size
+            .unwrap_or(DEFAULT_ENTITY_CHUNK_SIZE);
+        if entity_chunk_size == 0 {
+            return Err(SinkMongoError)
+                .attach_printable("entity chunk size must be greater than 0");
+        }
+
+        let transactions = supports_transactions(&client).await?;
+        if !transactions {
+            if options.transactional.unwrap_or(false) {
+                return Err(SinkMongoError).attach_printable(
+                    "transactional mode requires a mongo deployment that supports transactions (replica set or sharded cluster)",
+                );
+            }
+            warn!("mongo deployment does not support transactions, writes and invalidations are not atomic");
+        }
+
+        let cursor = if options.transactional.unwrap_or(false) {
+            Some(CursorCollection {
+                cursor_id: options.cursor_id.unwrap_or_else(|| "default".to_string()),
+                collection: db.collection::<Document>("_apibara_cursors"),
+            })
+        } else {
+            None
+        };
+
         Ok(Self {
-            collection,
+            collections,
+            multi_collection,
             client,
             mode,
             invalidate: options.invalidate,
+            cursor,
+            transactions,
+            entity_chunk_size,
         })
     }
 
@@ -102,7 +183,38 @@ impl Sink for MongoSink {
             return Ok(CursorAction::Persist);
         }
 
-        self.insert_data(&ctx.end_cursor, values).await?;
+        // Group documents by collection and mode, so that each group is written at once.
+        let mut logs = vec![Vec::new(); self.collections.len()];
+        let mut entities = vec![Vec::new(); self.collections.len()];
+        for value in values {
+            let (collection_index, mode, doc) = self.route_value(value)?;
+            match mode {
+                Mode::Logs => logs[collection_index].push(doc),
+                Mode::Entity => entities[collection_index].push(doc),
+            }
+        }
+
+        let mut session = self.start_session().await?;
+
+        for ((collection, logs), entities) in self.collections.iter().zip(logs).zip(entities) {
+            if !logs.is_empty() {
+                self.insert_logs_data(collection, &ctx.end_cursor, logs, &mut session)
+                    .await?;
+            }
+            if !entities.is_empty() {
+                self.insert_entities_data(collection, &ctx.end_cursor, entities, &mut session)
+                    .await?;
+            }
+        }
+
+        // Pending data is invalidated by the next message, so its cursor is never stored.
+        if let Some(cursor) = &self.cursor {
+            if ctx.finality != DataFinality::DataStatusPending {
+                cursor.put(&ctx.end_cursor, &mut session).await?;
+            }
+        }
+
+        self.commit_session(&mut session).await?;
 
         Ok(CursorAction::Persist)
     }
@@ -110,16 +222,10 @@ impl Sink for MongoSink {
     async fn handle_invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), Self::Error> {
         debug!(cursor = %DisplayCursor(cursor), "handling invalidate");
 
-        let mut session = self
-            .client
-            .start_session(None)
-            .await
-            .change_context(SinkMongoError)
-            .attach_printable("failed to create mongo session")?;
+        let mut session = self.start_session().await?;
 
         let (mut delete_query, mut unclamp_query) = if let Some(cursor) = cursor {
-            // convert to u32 because that's the maximum bson can handle
-            let block_number = u32::try_from(cursor.order_key).unwrap();
+            let block_number = order_key_to_i64(cursor)?;
             let del = doc! { "_cursor.from": { "$gt": block_number } };
             let unclamp = doc! { "_cursor.to": { "$gt": block_number } };
             (del, unclamp)
@@ -136,34 +242,57 @@ impl Sink for MongoSink {
             unclamp_query.extend(invalidate.clone());
         }
 
-        self.collection
-            .delete_many_with_session(delete_query, None, &mut session)
-            .await
-            .change_context(SinkMongoError)
-            .attach_printable("failed to invalidate data (delete)")?;
-        self.collection
-            .update_many_with_session(unclamp_query, unset_cursor_to, None, &mut session)
-            .await
-            .change_context(SinkMongoError)
-            .attach_printable("failed to invalidate data (update)")?;
+        for collection in &self.collections {
+            collection
+                .delete_many_with_session(delete_query.clone(), None, &mut session)
+                .await
+                .change_context(SinkMongoError)
+                .attach_printable_lazy(|| {
+                    format!("failed to invalidate data (delete, {})", collection.name())
+                })?;
+            collection
+                .update_many_with_session(
+                    unclamp_query.clone(),
+                    unset_cursor_to.clone(),
+                    None,
+                    &mut session,
+                )
+                .await
+                .change_context(SinkMongoError)
+                .attach_printable_lazy(|| {
+                    format!("failed to invalidate data (update, {})", collection.name())
+                })?;
+        }
+
+        if let Some(cursor_collection) = &self.cursor {
+            match cursor {
+                None => cursor_collection.delete(&mut session).await?,
+                Some(cursor) => cursor_collection.put(cursor, &mut session).await?,
+            }
+        }
+
+        self.commit_session(&mut session).await?;
 
         Ok(())
     }
+
+    fn is_transactional(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    async fn get_cursor(&mut self) -> Result<Option<Cursor>, Self::Error> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+
+        cursor.get().await
+    }
 }
 
 impl MongoSink {
-    pub async fn insert_data(
-        &self,
-        end_cursor: &Cursor,
-        values: &[Value],
-    ) -> Result<(), SinkMongoError> {
-        let docs = values
-            .iter()
-            .map(to_document)
-            .collect::<std::result::Result<Vec<_>, _>>()
-            .change_context(SinkMongoError)
-            .attach_printable("failed to convert batch to mongo document")?;
-
+    /// Starts a new session. If the deployment supports it, the session also starts a
+    /// transaction.
+    async fn start_session(&self) -> Result<ClientSession, SinkMongoError> {
         let mut session = self
             .client
             .start_session(None)
@@ -171,28 +300,91 @@ impl MongoSink {
             .change_context(SinkMongoError)
             .attach_printable("failed to create mongo session")?;
 
-        match &self.mode {
-            Mode::Logs => self.insert_logs_data(end_cursor, docs, &mut session).await,
-            Mode::Entity => {
-                self.insert_entities_data(end_cursor, docs, &mut session)
-                    .await
-            }
+        if self.transactions {
+            session
+                .start_transaction(None)
+                .await
+                .change_context(SinkMongoError)
+                .attach_printable("failed to start mongo transaction")?;
+        }
+
+        Ok(session)
+    }
+
+    /// Commits the transaction started by `start_session`, if any.
+    async fn commit_session(&self, session: &mut ClientSession) -> Result<(), SinkMongoError> {
+        if self.transactions {
+            session
+                .commit_transaction()
+                .await
+                .change_context(SinkMongoError)
+                .attach_printable("failed to commit mongo transaction")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the collection with the given name.
+    pub fn collection(&self, name: &str) -> Option<&Collection<Document>> {
+        self.collections
+            .iter()
+            .find(|collection| collection.name() == name)
+    }
+
+    /// Returns the index of the collection targeted by the value, together with the write
+    /// mode and the document to write.
+    fn route_value(&self, value: &Value) -> Result<(usize, Mode, Document), SinkMongoError> {
+        let mut doc = to_document(value)
+            .change_context(SinkMongoError)
+            .attach_printable("failed to convert batch to mongo document")?;
+
+        if !self.multi_collection {
+            return Ok((0, self.mode, doc));
         }
+
+        let collection_name = doc
+            .get_str("collection")
+            .change_context(SinkMongoError)
+            .attach_printable("item missing collection key")?;
+
+        let collection_index = self
+            .collections
+            .iter()
+            .position(|collection| collection.name() == collection_name)
+            .ok_or(SinkMongoError)
+            .attach_printable_lazy(|| {
+                format!("collection {collection_name} is not in the sink collections")
+            })?;
+
+        if doc.contains_key("entity") {
+            return Ok((collection_index, Mode::Entity, doc));
+        }
+
+        let data = doc
+            .remove("data")
+            .ok_or(SinkMongoError)
+            .attach_printable("item missing data or entity key")?;
+        let Bson::Document(data) = data else {
+            return Err(SinkMongoError).attach_printable("data is not an object");
+        };
+
+        Ok((collection_index, Mode::Logs, data))
     }
 
     pub async fn insert_logs_data(
         &self,
+        collection: &Collection<Document>,
         end_cursor: &Cursor,
         mut docs: Vec<Document>,
         session: &mut ClientSession,
     ) -> Result<(), SinkMongoError> {
         let cursor = doc! {
-            "from": end_cursor.order_key as i64,
+            "from": order_key_to_i64(end_cursor)?,
         };
 
         docs.iter_mut().for_each(|doc| doc.add_cursor(&cursor));
 
-        self.collection
+        collection
             .insert_many_with_session(docs, None, session)
             .await
             .change_context(SinkMongoError)
@@ -201,14 +393,23 @@ impl MongoSink {
         Ok(())
     }
 
+    /// Updates the entities in the collection.
+    ///
+    /// Entities are processed in chunks. For each chunk, the current version of the entities is
+    /// closed and copied with a few bulk operations, then all updates are applied with a
+    /// single ordered `update` command.
     pub async fn insert_entities_data(
         &self,
+        collection: &Collection<Document>,
         end_cursor: &Cursor,
         docs: Vec<Document>,
         session: &mut ClientSession,
     ) -> Result<(), SinkMongoError> {
+        // Set `to` explicitly, so that the current version of entities can be indexed.
+        let end_block = order_key_to_i64(end_cursor)?;
         let new_cursor = doc! {
-            "from": end_cursor.order_key as i64,
+            "from": end_block,
+            "to": Bson::Null,
         };
 
         let entities_with_updates = docs
@@ -243,7 +444,7 @@ impl MongoSink {
                                 set.insert("_cursor", new_cursor.clone());
                             }
                         }
-                        UpdateModifications::Document(update)
+                        Bson::Document(update)
                     }
                     Bson::Array(pipeline) => {
                         let mut pipeline = pipeline
@@ -255,11 +456,13 @@ impl MongoSink {
                                     .attach_printable(
                                         "update is expected to be a document or pipeline",
                                     )
-                                    .map(|stage| stage.clone())
+                                    .map(|stage| Bson::Document(stage.clone()))
                             })
                             .collect::<Result<Vec<_>, SinkMongoError>>()?;
-                        pipeline.push(doc! { "$set": { "_cursor": new_cursor.clone() } });
-                        UpdateModifications::Pipeline(pipeline)
+                        pipeline.push(Bson::Document(
+                            doc! { "$set": { "_cursor": new_cursor.clone() } },
+                        ));
+                        Bson::Array(pipeline)
                     }
                     _ => {
                         return Err(SinkMongoError)
@@ -271,21 +474,38 @@ impl MongoSink {
             })
             .collect::<Result<Vec<_>, SinkMongoError>>()?;
 
+        for chunk in entities_with_updates.chunks(self.entity_chunk_size) {
+            self.insert_entities_chunk(collection, end_block, &new_cursor, chunk, session)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn insert_entities_chunk(
+        &self,
+        collection: &Collection<Document>,
+        end_block: i64,
+        new_cursor: &Document,
+        entities_with_updates: &[(Document, Bson)],
+        session: &mut ClientSession,
+    ) -> Result<(), SinkMongoError> {
         let entities_filter = entities_with_updates
             .iter()
             .map(|(entity, _)| entity)
             .collect::<Vec<_>>();
 
-        // get previous entities values, if any
+        // get previous entities values, if any.
+        // values created at the current block (by a previous chunk) are updated in place.
         let existing_docs_query = doc! {
             "$and": [
-                doc! { "$or": entities_filter.clone() },
+                doc! { "$or": entities_filter },
                 doc! { "_cursor.to": Bson::Null },
+                doc! { "_cursor.from": { "$lt": end_block } },
             ]
         };
 
-        let mut existing_docs = self
-            .collection
+        let mut existing_docs = collection
             .find_with_session(Some(existing_docs_query.clone()), None, session)
             .await
             .change_context(SinkMongoError)
@@ -300,11 +520,11 @@ impl MongoSink {
             // update validity of previous values
             let clamp_cursor = doc! {
                 "$set": {
-                    "_cursor.to": end_cursor.order_key as i64,
+                    "_cursor.to": end_block,
                 }
             };
 
-            self.collection
+            collection
                 .update_many_with_session(existing_docs_query, clamp_cursor, None, session)
                 .await
                 .change_context(SinkMongoError)
@@ -313,31 +533,235 @@ impl MongoSink {
             // duplicate existing rows so that the update operation has something to work on
             existing_docs
                 .iter_mut()
-                .for_each(|doc| doc.replace_cursor(&new_cursor));
+                .for_each(|doc| doc.replace_cursor(new_cursor));
 
-            self.collection
+            collection
                 .insert_many_with_session(existing_docs, None, session)
                 .await
                 .change_context(SinkMongoError)
                 .attach_printable("failed to insert entities (insert copies)")?;
         }
 
-        // update values as specified by user
-        let update_options = UpdateOptions::builder().upsert(true).build();
+        // update values as specified by user.
+        // the updates are ordered, so that updates to the same entity are applied in order.
+        let updates = entities_with_updates
+            .iter()
+            .map(|(entity, update)| {
+                let mut filter = entity.clone();
+                filter.insert("_cursor.to", Bson::Null);
+                doc! {
+                    "q": filter,
+                    "u": update.clone(),
+                    "upsert": true,
+                    "multi": true,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let command = doc! {
+            "update": collection.name(),
+            "updates": updates,
+            "ordered": true,
+        };
 
-        for (mut doc_filter, update) in entities_with_updates {
-            doc_filter.insert("_cursor.to", Bson::Null);
-            self.collection
-                .update_many_with_session(doc_filter, update, Some(update_options.clone()), session)
-                .await
-                .change_context(SinkMongoError)
-                .attach_printable("failed to insert entities (update entities)")?;
+        let response = self
+            .client
+            .database(&collection.namespace().db)
+            .run_command_with_session(command, None, session)
+            .await
+            .change_context(SinkMongoError)
+            .attach_printable("failed to insert entities (update entities)")?;
+
+        if let Ok(write_errors) = response.get_array("writeErrors") {
+            if !write_errors.is_empty() {
+                return Err(SinkMongoError).attach_printable_lazy(|| {
+                    format!("failed to insert entities (update entities): {write_errors:?}")
+                });
+            }
+        }
+
+        if let Ok(write_concern_error) = response.get_document("writeConcernError") {
+            return Err(SinkMongoError).attach_printable_lazy(|| {
+                format!("failed to insert entities (update entities): {write_concern_error:?}")
+            });
         }
 
         Ok(())
     }
 }
 
+/// Creates the indexes used to invalidate data, together with the indexes in the
+/// configuration.
+async fn create_indexes(
+    collections: &[Collection<Document>],
+    indexes: Vec<CollectionIndex>,
+) -> Result<(), SinkMongoError> {
+    for index in &indexes {
+        if let Some(collection_name) = &index.collection {
+            if !collections
+                .iter()
+                .any(|collection| collection.name() == collection_name)
+            {
+                return Err(SinkMongoError).attach_printable_lazy(|| {
+                    format!("index collection {collection_name} is not in the sink collections")
+                });
+            }
+        }
+    }
+
+    for collection in collections {
+        let mut models = vec![
+            IndexModel::builder()
+                .keys(doc! { "_cursor.from": 1 })
+                .build(),
+            IndexModel::builder().keys(doc! { "_cursor.to": 1 }).build(),
+        ];
+
+        for index in &indexes {
+            if let Some(collection_name) = &index.collection {
+                if collection_name != collection.name() {
+                    continue;
+                }
+            }
+
+            // The current version of entities has `_cursor.to` set to null.
+            let partial_filter_expression = if index.current_only.unwrap_or(false) {
+                Some(doc! { "_cursor.to": { "$type": "null" } })
+            } else {
+                None
+            };
+
+            let options = IndexOptions::builder()
+                .name(index.name.clone())
+                .unique(index.unique)
+                .partial_filter_expression(partial_filter_expression)
+                .build();
+
+            models.push(
+                IndexModel::builder()
+                    .keys(index.keys.clone())
+                    .options(options)
+                    .build(),
+            );
+        }
+
+        info!(collection = %collection.name(), count = models.len(), "creating indexes");
+        collection
+            .create_indexes(models, None)
+            .await
+            .change_context(SinkMongoError)
+            .attach_printable_lazy(|| {
+                format!("failed to create indexes on {}", collection.name())
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Returns true if the deployment supports multi-document transactions.
+///
+/// Transactions are supported by replica sets and sharded clusters, but not by
+/// standalone servers.
+async fn supports_transactions(client: &Client) -> Result<bool, SinkMongoError> {
+    let hello = client
+        .database("admin")
+        .run_command(doc! { "hello": 1 }, None)
+        .await
+        .change_context(SinkMongoError)
+        .attach_printable("failed to read mongo deployment type")?;
+
+    let is_replica_set = hello.contains_key("setName");
+    let is_sharded_cluster = hello
+        .get_str("msg")
+        .map(|msg| msg == "isdbgrid")
+        .unwrap_or(false);
+
+    Ok(is_replica_set || is_sharded_cluster)
+}
+
+impl CursorCollection {
+    async fn get(&self) -> Result<Option<Cursor>, SinkMongoError> {
+        let Some(doc) = self
+            .collection
+            .find_one(doc! { "_id": &self.cursor_id }, None)
+            .await
+            .change_context(SinkMongoError)
+            .attach_printable("failed to read cursor")?
+        else {
+            return Ok(None);
+        };
+
+        let order_key = doc
+            .get_i64("orderKey")
+            .change_context(SinkMongoError)
+            .attach_printable("cursor document has invalid order key")?;
+        let order_key = u64::try_from(order_key)
+            .change_context(SinkMongoError)
+            .attach_printable("cursor document has negative order key")?;
+        let unique_key = doc
+            .get_binary_generic("uniqueKey")
+            .change_context(SinkMongoError)
+            .attach_printable("cursor document has invalid unique key")?
+            .clone();
+
+        Ok(Some(Cursor {
+            order_key,
+            unique_key,
+        }))
+    }
+
+    async fn put(
+        &self,
+        cursor: &Cursor,
+        session: &mut ClientSession,
+    ) -> Result<(), SinkMongoError> {
+        let order_key = order_key_to_i64(cursor)?;
+        let unique_key = Binary {
+            subtype: BinarySubtype::Generic,
+            bytes: cursor.unique_key.clone(),
+        };
+        let update = doc! {
+            "$set": {
+                "orderKey": order_key,
+                "uniqueKey": unique_key,
+            }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+
+        self.collection
+            .update_one_with_session(
+                doc! { "_id": &self.cursor_id },
+                update,
+                Some(options),
+                session,
+            )
+            .await
+            .change_context(SinkMongoError)
+            .attach_printable("failed to store cursor")?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session: &mut ClientSession) -> Result<(), SinkMongoError> {
+        self.collection
+            .delete_one_with_session(doc! { "_id": &self.cursor_id }, None, session)
+            .await
+            .change_context(SinkMongoError)
+            .attach_printable("failed to delete cursor")?;
+
+        Ok(())
+    }
+}
+
+/// Converts the cursor order key to the integer type stored in the `_cursor` field.
+fn order_key_to_i64(cursor: &Cursor) -> Result<i64, SinkMongoError> {
+    i64::try_from(cursor.order_key)
+        .change_context(SinkMongoError)
+        .attach_printable_lazy(|| {
+            format!("cursor order key {} does not fit in i64", cursor.order_key)
+        })
+}
+
 trait DocumentExt {
     fn add_cursor(&mut self, cursor: &Document);
     fn replace_cursor(&mut self, cursor: &Document);
@@ -1,21 +1,58 @@
 use apibara_core::node::v1alpha2::{Cursor, DataFinality};
 use apibara_sink_common::{Context, CursorAction, Sink};
-use apibara_sink_mongo::{MongoSink, SinkMongoError, SinkMongoOptions};
+use apibara_sink_mongo::{
+    migrate_cursor_format, CollectionIndex, MongoSink, SinkMongoError, SinkMongoOptions,
+};
 use error_stack::{Result, ResultExt};
 use futures_util::TryStreamExt;
 use mongodb::{
     bson::{doc, to_document, Bson, Document},
     options::FindOptions,
-    Collection,
+    Client, Collection,
 };
 use serde_json::{json, Value};
-use testcontainers::{clients, core::WaitFor, GenericImage};
+use std::time::Duration;
+use testcontainers::{clients, core::WaitFor, GenericImage, RunnableImage};
 
 fn new_mongo_image() -> GenericImage {
     GenericImage::new("mongo", "7.0.1")
         .with_wait_for(WaitFor::message_on_stdout("Waiting for connections"))
 }
 
+fn new_mongo_replica_set_image() -> RunnableImage<GenericImage> {
+    let args = vec!["--replSet".to_string(), "rs0".to_string()];
+    RunnableImage::from((new_mongo_image(), args))
+}
+
+/// Initiates a single-node replica set and waits for the node to become primary.
+async fn initiate_replica_set(connection_string: &str) {
+    let client = Client::with_uri_str(connection_string).await.unwrap();
+    let admin = client.database("admin");
+
+    admin
+        .run_command(
+            doc! {
+                "replSetInitiate": {
+                    "_id": "rs0",
+                    "members": [{ "_id": 0, "host": "localhost:27017" }],
+                }
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+    for _ in 0..60 {
+        let hello = admin.run_command(doc! { "hello": 1 }, None).await.unwrap();
+        if hello.get_bool("isWritablePrimary").unwrap_or(false) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    panic!("replica set has no primary");
+}
+
 fn new_cursor(order_key: u64) -> Cursor {
     Cursor {
         order_key,
@@ -146,7 +183,10 @@ async fn test_handle_data() -> Result<(), SinkMongoError> {
         assert_eq!(action, CursorAction::Persist);
     }
 
-    assert_eq!(all_docs, get_all_docs(&sink.collection).await);
+    assert_eq!(
+        all_docs,
+        get_all_docs(sink.collection("test").unwrap()).await
+    );
 
     Ok(())
 }
@@ -192,10 +232,16 @@ async fn test_handle_invalidate_all(
         all_docs.extend(new_docs(&cursor, &end_cursor));
     }
 
-    assert_eq!(all_docs, get_all_docs(&sink.collection).await);
+    assert_eq!(
+        all_docs,
+        get_all_docs(sink.collection("test").unwrap()).await
+    );
 
     sink.handle_invalidate(invalidate_from).await?;
-    assert_eq!(Vec::<Document>::new(), get_all_docs(&sink.collection).await);
+    assert_eq!(
+        Vec::<Document>::new(),
+        get_all_docs(sink.collection("test").unwrap()).await
+    );
 
     Ok(())
 }
@@ -251,7 +297,10 @@ async fn test_handle_invalidate() -> Result<(), SinkMongoError> {
         assert_eq!(action, CursorAction::Persist);
     }
 
-    assert_eq!(all_docs, get_all_docs(&sink.collection).await);
+    assert_eq!(
+        all_docs,
+        get_all_docs(sink.collection("test").unwrap()).await
+    );
 
     let invalidate_from = 2;
 
@@ -269,7 +318,10 @@ async fn test_handle_invalidate() -> Result<(), SinkMongoError> {
         })
         .collect();
 
-    assert_eq!(expected_docs, get_all_docs(&sink.collection).await);
+    assert_eq!(
+        expected_docs,
+        get_all_docs(sink.collection("test").unwrap()).await
+    );
 
     Ok(())
 }
@@ -335,7 +387,7 @@ async fn test_handle_invalidate_with_extra_condition() -> Result<(), SinkMongoEr
     let expected_docs_count = (batch_size * num_batches) + (batch_size * (invalidate_from - 1));
     assert_eq!(
         expected_docs_count,
-        get_all_docs(&sink.collection).await.len() as u64
+        get_all_docs(sink.collection("test").unwrap()).await.len() as u64
     );
 
     Ok(())
@@ -353,7 +405,7 @@ async fn test_handle_data_in_entity_mode() -> Result<(), SinkMongoError> {
         database: Some("test".into()),
         collection_name: Some("test".into()),
         entity_mode: Some(true),
-        invalidate: None,
+        ..SinkMongoOptions::default()
     };
 
     let mut sink = MongoSink::from_options(options).await?;
@@ -402,7 +454,8 @@ async fn test_handle_data_in_entity_mode() -> Result<(), SinkMongoError> {
         // For example, we check that key v0 is still present.
 
         let new_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! {"_cursor.to": Bson::Null, "address": "0x1", "token_id": "1" }),
                 None,
@@ -420,7 +473,8 @@ async fn test_handle_data_in_entity_mode() -> Result<(), SinkMongoError> {
         assert_eq!(new_doc.get_i64("v2").unwrap(), 7);
 
         let new_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! {"_cursor.to": Bson::Null, "address": "0x1", "token_id": "2" }),
                 None,
@@ -452,7 +506,8 @@ async fn test_handle_data_in_entity_mode() -> Result<(), SinkMongoError> {
         sink.handle_data(&ctx, &batch).await?;
 
         let updated_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! {"_cursor.to": Bson::Null, "address": "0x1", "token_id": "1" }),
                 None,
@@ -469,7 +524,8 @@ async fn test_handle_data_in_entity_mode() -> Result<(), SinkMongoError> {
         assert_eq!(updated_doc.get_str("v1").unwrap(), "c");
 
         let new_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! {"_cursor.to": Bson::Null, "address": "0x1", "token_id": "4" }),
                 None,
@@ -501,7 +557,7 @@ async fn test_handle_invalidate_in_entity_mode() -> Result<(), SinkMongoError> {
         database: Some("test".into()),
         collection_name: Some("test".into()),
         entity_mode: Some(true),
-        invalidate: None,
+        ..SinkMongoOptions::default()
     };
 
     let mut sink = MongoSink::from_options(options).await?;
@@ -540,7 +596,8 @@ async fn test_handle_invalidate_in_entity_mode() -> Result<(), SinkMongoError> {
         sink.handle_data(&ctx, &batch).await?;
 
         let new_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! { "token_id": "2", "_cursor.to": Bson::Null }),
                 None,
@@ -564,7 +621,8 @@ async fn test_handle_invalidate_in_entity_mode() -> Result<(), SinkMongoError> {
         sink.handle_invalidate(&new_head).await?;
 
         let new_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! { "token_id": "2", "_cursor.to": Bson::Null }),
                 None,
@@ -587,7 +645,8 @@ async fn test_handle_invalidate_in_entity_mode() -> Result<(), SinkMongoError> {
         sink.handle_invalidate(&new_head).await?;
 
         let new_docs = sink
-            .collection
+            .collection("test")
+            .unwrap()
             .find(
                 Some(doc! { "token_id": "2", "_cursor.to": Bson::Null }),
                 None,
@@ -606,3 +665,487 @@ async fn test_handle_invalidate_in_entity_mode() -> Result<(), SinkMongoError> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_multiple_collections() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_names: Some(vec!["transfers".into(), "balances".into()]),
+        ..SinkMongoOptions::default()
+    };
+
+    let mut sink = MongoSink::from_options(options).await?;
+    let finality = DataFinality::DataStatusFinalized;
+
+    let batches = [
+        json!([
+            { "collection": "transfers", "data": { "address": "0xA", "amount": 10 } },
+            { "collection": "balances", "entity": { "address": "0xA" }, "update": { "$inc": { "balance": 10 } } },
+        ]),
+        json!([
+            { "collection": "transfers", "data": { "address": "0xA", "amount": 5 } },
+            { "collection": "balances", "entity": { "address": "0xA" }, "update": { "$inc": { "balance": 5 } } },
+        ]),
+    ];
+
+    for (i, batch) in batches.iter().enumerate() {
+        let ctx = Context {
+            cursor: Some(new_cursor(i as u64)),
+            end_cursor: new_cursor(i as u64 + 1),
+            finality,
+        };
+        sink.handle_data(&ctx, batch).await?;
+    }
+
+    let transfers = get_all_docs(sink.collection("transfers").unwrap()).await;
+    assert_eq!(transfers.len(), 2);
+    assert!(transfers.iter().all(|doc| !doc.contains_key("collection")));
+
+    let balances = get_all_docs(sink.collection("balances").unwrap()).await;
+    assert_eq!(balances.len(), 2);
+
+    sink.handle_invalidate(&Some(new_cursor(1))).await?;
+
+    let transfers = get_all_docs(sink.collection("transfers").unwrap()).await;
+    assert_eq!(transfers.len(), 1);
+
+    let balances = get_all_docs(sink.collection("balances").unwrap()).await;
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].get_i64("balance").unwrap(), 10);
+    assert_eq!(
+        balances[0].get_document("_cursor").unwrap().get("to"),
+        Some(&Bson::Null)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_in_entity_mode_with_chunks() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        entity_mode: Some(true),
+        entity_chunk_size: Some(1),
+        ..SinkMongoOptions::default()
+    };
+
+    let mut sink = MongoSink::from_options(options).await?;
+    let finality = DataFinality::DataStatusFinalized;
+
+    let batches = [
+        json!([
+            { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 1 } } },
+            { "entity": { "address": "0x2" }, "update": { "$inc": { "balance": 1 } } },
+        ]),
+        // Updates to the same entity end up in different chunks.
+        json!([
+            { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 2 } } },
+            { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 3 } } },
+        ]),
+    ];
+
+    for (i, batch) in batches.iter().enumerate() {
+        let ctx = Context {
+            cursor: Some(new_cursor(i as u64)),
+            end_cursor: new_cursor(i as u64 + 1),
+            finality,
+        };
+        sink.handle_data(&ctx, batch).await?;
+    }
+
+    let docs = sink
+        .collection("test")
+        .unwrap()
+        .find(Some(doc! { "address": "0x1" }), None)
+        .await
+        .change_context(SinkMongoError)?
+        .try_collect::<Vec<_>>()
+        .await
+        .change_context(SinkMongoError)?;
+
+    // One version for each block.
+    assert_eq!(docs.len(), 2);
+    let latest = docs
+        .iter()
+        .find(|doc| {
+            matches!(
+                doc.get_document("_cursor").unwrap().get("to"),
+                None | Some(Bson::Null)
+            )
+        })
+        .unwrap();
+    assert_eq!(latest.get_i64("balance").unwrap(), 6);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_create_indexes() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        entity_mode: Some(true),
+        indexes: Some(vec![CollectionIndex {
+            keys: doc! { "address": 1 },
+            name: Some("address_current".into()),
+            unique: Some(true),
+            current_only: Some(true),
+            ..CollectionIndex::default()
+        }]),
+        ..SinkMongoOptions::default()
+    };
+
+    let mut sink = MongoSink::from_options(options).await?;
+    let finality = DataFinality::DataStatusFinalized;
+
+    let mut index_names = sink
+        .collection("test")
+        .unwrap()
+        .list_index_names()
+        .await
+        .change_context(SinkMongoError)?;
+    index_names.sort();
+    assert_eq!(
+        index_names,
+        vec!["_cursor.from_1", "_cursor.to_1", "_id_", "address_current"]
+    );
+
+    // Old versions of the entity are not covered by the unique index.
+    for i in 0..3 {
+        let ctx = Context {
+            cursor: Some(new_cursor(i)),
+            end_cursor: new_cursor(i + 1),
+            finality,
+        };
+        let batch = json!([
+            { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 1 } } },
+        ]);
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    let collection = sink.collection("test").unwrap();
+    let docs = get_all_docs(collection).await;
+    assert_eq!(docs.len(), 3);
+
+    // A second current version of the entity is rejected.
+    let result = collection
+        .insert_one(
+            doc! { "address": "0x1", "_cursor": { "from": 10_i64, "to": Bson::Null } },
+            None,
+        )
+        .await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_create_indexes_on_unknown_collection() {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        indexes: Some(vec![CollectionIndex {
+            collection: Some("other".into()),
+            keys: doc! { "address": 1 },
+            ..CollectionIndex::default()
+        }]),
+        ..SinkMongoOptions::default()
+    };
+
+    assert!(MongoSink::from_options(options).await.is_err());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_invalidate_with_large_order_key() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        ..SinkMongoOptions::default()
+    };
+
+    let mut sink = MongoSink::from_options(options).await?;
+    let finality = DataFinality::DataStatusFinalized;
+
+    // Order keys larger than u32::MAX.
+    let first_order_key = u32::MAX as u64 + 1;
+    for order_key in first_order_key..first_order_key + 3 {
+        let ctx = Context {
+            cursor: Some(new_cursor(order_key)),
+            end_cursor: new_cursor(order_key + 1),
+            finality,
+        };
+        let batch = json!([{ "block": order_key }]);
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    sink.handle_invalidate(&Some(new_cursor(first_order_key + 1)))
+        .await?;
+
+    let docs = get_all_docs(sink.collection("test").unwrap()).await;
+    assert_eq!(
+        docs,
+        vec![
+            doc! { "block": first_order_key as i64, "_cursor": { "from": first_order_key as i64 + 1 } }
+        ]
+    );
+
+    // Order keys that don't fit in i64 are rejected instead of panicking.
+    assert!(sink
+        .handle_invalidate(&Some(new_cursor(u64::MAX)))
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_migrate_cursor_format() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        ..SinkMongoOptions::default()
+    };
+
+    let sink = MongoSink::from_options(options).await?;
+    let collection = sink.collection("test").unwrap();
+
+    // Documents written with 32-bit cursors.
+    collection
+        .insert_many(
+            vec![
+                doc! { "block": 1, "_cursor": { "from": 1_i32, "to": 2_i32 } },
+                doc! { "block": 2, "_cursor": { "from": 2_i32 } },
+                doc! { "block": 3, "_cursor": { "from": 3_i64, "to": Bson::Null } },
+            ],
+            None,
+        )
+        .await
+        .change_context(SinkMongoError)?;
+
+    assert_eq!(migrate_cursor_format(collection).await?, 3);
+    // Migrating again is a no-op.
+    assert_eq!(migrate_cursor_format(collection).await?, 0);
+
+    assert_eq!(
+        get_all_docs(collection).await,
+        vec![
+            doc! { "block": 1, "_cursor": { "from": 1_i64, "to": 2_i64 } },
+            doc! { "block": 2, "_cursor": { "from": 2_i64 } },
+            doc! { "block": 3, "_cursor": { "from": 3_i64, "to": Bson::Null } },
+        ]
+    );
+
+    Ok(())
+}
+
+async fn get_current_entities(collection: &Collection<Document>) -> Vec<Document> {
+    let find_options = Some(
+        FindOptions::builder()
+            .projection(Some(doc! {"_id": 0}))
+            .sort(Some(doc! {"address": 1}))
+            .build(),
+    );
+
+    collection
+        .find(Some(doc! { "_cursor.to": Bson::Null }), find_options)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_entity_mode_with_replica_set_is_atomic() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_replica_set_image());
+    let port = mongo.get_host_port_ipv4(27017);
+    let connection_string = format!("mongodb://localhost:{}/?directConnection=true", port);
+
+    initiate_replica_set(&connection_string).await;
+
+    let options = SinkMongoOptions {
+        connection_string: Some(connection_string),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        entity_mode: Some(true),
+        transactional: Some(true),
+        ..SinkMongoOptions::default()
+    };
+
+    let mut sink = MongoSink::from_options(options).await?;
+    assert!(sink.is_transactional());
+    let finality = DataFinality::DataStatusFinalized;
+
+    let batch = json!([
+        { "entity": { "address": "0x1" }, "update": { "$set": { "name": "a", "balance": 1 } } },
+        { "entity": { "address": "0x2" }, "update": { "$set": { "name": "b", "balance": 1 } } },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(0)),
+        end_cursor: new_cursor(1),
+        finality,
+    };
+    sink.handle_data(&ctx, &batch).await?;
+
+    let batch = json!([
+        { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 1 } } },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(1)),
+        end_cursor: new_cursor(2),
+        finality,
+    };
+    sink.handle_data(&ctx, &batch).await?;
+
+    let collection = sink.collection("test").unwrap().clone();
+    let all_docs = get_all_docs(&collection).await;
+    let current_entities = get_current_entities(&collection).await;
+
+    // The second update fails after the previous versions of both entities
+    // have been closed and the first update has been applied.
+    let batch = json!([
+        { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 1 } } },
+        { "entity": { "address": "0x2" }, "update": { "$inc": { "name": 1 } } },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(2)),
+        end_cursor: new_cursor(3),
+        finality,
+    };
+    assert!(sink.handle_data(&ctx, &batch).await.is_err());
+
+    // The whole batch is rolled back, together with the cursor.
+    assert_eq!(get_all_docs(&collection).await, all_docs);
+    assert_eq!(get_current_entities(&collection).await, current_entities);
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(2)));
+
+    // Invalidate the second batch: the entity updates and the cursor are
+    // reverted together.
+    sink.handle_invalidate(&Some(new_cursor(1))).await?;
+
+    let entities = get_current_entities(&collection).await;
+    assert_eq!(entities.len(), 2);
+    assert_eq!(entities[0].get_str("address").unwrap(), "0x1");
+    assert_eq!(entities[0].get_i64("balance").unwrap(), 1);
+    assert_eq!(entities[1].get_str("address").unwrap(), "0x2");
+    assert_eq!(entities[1].get_i64("balance").unwrap(), 1);
+    assert_eq!(get_all_docs(&collection).await.len(), 2);
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(1)));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_entity_mode_with_standalone_server() -> Result<(), SinkMongoError> {
+    let docker = clients::Cli::default();
+    let mongo = docker.run(new_mongo_image());
+    let port = mongo.get_host_port_ipv4(27017);
+
+    // Transactional mode requires transactions.
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        entity_mode: Some(true),
+        transactional: Some(true),
+        ..SinkMongoOptions::default()
+    };
+    assert!(MongoSink::from_options(options).await.is_err());
+
+    // Otherwise the sink writes without transactions.
+    let options = SinkMongoOptions {
+        connection_string: Some(format!("mongodb://localhost:{}", port)),
+        database: Some("test".into()),
+        collection_name: Some("test".into()),
+        entity_mode: Some(true),
+        ..SinkMongoOptions::default()
+    };
+
+    let mut sink = MongoSink::from_options(options).await?;
+    assert!(!sink.is_transactional());
+    let finality = DataFinality::DataStatusFinalized;
+
+    let batch = json!([
+        { "entity": { "address": "0x1" }, "update": { "$set": { "name": "a", "balance": 1 } } },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(0)),
+        end_cursor: new_cursor(1),
+        finality,
+    };
+    sink.handle_data(&ctx, &batch).await?;
+
+    let batch = json!([
+        { "entity": { "address": "0x1" }, "update": { "$inc": { "balance": 1 } } },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(1)),
+        end_cursor: new_cursor(2),
+        finality,
+    };
+    sink.handle_data(&ctx, &batch).await?;
+
+    let collection = sink.collection("test").unwrap().clone();
+    let entities = get_current_entities(&collection).await;
+    assert_eq!(entities.len(), 1);
+    assert_eq!(entities[0].get_i64("balance").unwrap(), 2);
+
+    // Write errors are still reported.
+    let batch = json!([
+        { "entity": { "address": "0x1" }, "update": { "$inc": { "name": 1 } } },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(2)),
+        end_cursor: new_cursor(3),
+        finality,
+    };
+    assert!(sink.handle_data(&ctx, &batch).await.is_err());
+
+    sink.handle_invalidate(&Some(new_cursor(1))).await?;
+
+    let entities = get_current_entities(&collection).await;
+    assert_eq!(entities.len(), 1);
+    assert_eq!(entities[0].get_i64("balance").unwrap(), 1);
+
+    Ok(())
+}
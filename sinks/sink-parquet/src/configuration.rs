@@ -1,33 +1,168 @@
 use std::path::PathBuf;
 
 use apibara_sink_common::SinkOptions;
+use arrow::datatypes::SchemaRef;
 use clap::Args;
 use error_stack::{Result, ResultExt};
+use parquet::basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel};
+use serde::Deserialize;
 
+use crate::partition::{parse_partitioning, Partitioning};
+use crate::schema::schema_from_options;
 use crate::sink::SinkParquetError;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SinkParquetConfiguration {
-    pub output_dir: PathBuf,
+    pub storage: StorageConfiguration,
     pub batch_size: usize,
+    /// The schema of the data. If `None`, the schema is inferred from the data.
+    pub schema: Option<SchemaRef>,
+    /// If set, each item returned by the transform step specifies its target dataset.
+    pub datasets: Option<Vec<DatasetConfiguration>>,
+    /// How files are grouped in partitions. If `None`, files are not partitioned.
+    pub partitioning: Option<Partitioning>,
+    pub writer: WriterConfiguration,
+}
+
+/// Where the parquet files are written.
+#[derive(Debug, Clone)]
+pub enum StorageConfiguration {
+    /// A local directory.
+    Local(PathBuf),
+    /// A S3-compatible object storage.
+    S3(S3Configuration),
+}
+
+/// S3 settings. Unset values are read from the standard `AWS_*` environment variables.
+#[derive(Debug, Default, Clone)]
+pub struct S3Configuration {
+    pub bucket: String,
+    /// Prefix of the objects written by the sink.
+    pub prefix: String,
+    pub region: Option<String>,
+    /// Endpoint of S3-compatible services, for example `http://localhost:9000`.
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Allow connecting to the endpoint over HTTP.
+    pub allow_http: Option<bool>,
+}
+
+/// Settings of the parquet writer. Unset values use the parquet defaults.
+#[derive(Debug, Default, Clone)]
+pub struct WriterConfiguration {
+    pub compression: Option<Compression>,
+    /// Maximum number of rows in a row group.
+    pub row_group_size: Option<usize>,
+    /// Whether to use dictionary encoding.
+    pub dictionary: Option<bool>,
+    /// Maximum size of a dictionary page, in bytes.
+    pub dictionary_page_size: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct DatasetConfiguration {
+    /// Dataset name, also used as the name of the dataset directory.
+    pub name: String,
+    /// The schema of the dataset. If `None`, the schema is inferred from the data.
+    pub schema: Option<SchemaRef>,
 }
 
 #[derive(Debug, Args, Default, SinkOptions)]
 #[sink_options(tag = "parquet")]
 pub struct SinkParquetOptions {
     /// The output directory to write the parquet files to.
+    ///
+    /// Use a `s3://bucket/prefix` URL to write the files to a S3-compatible object storage.
     #[arg(long, env = "PARQUET_OUTPUT_DIR")]
     pub output_dir: Option<String>,
+    /// The S3 region.
+    #[arg(long, env = "PARQUET_S3_REGION")]
+    pub s3_region: Option<String>,
+    /// The endpoint of a S3-compatible object storage.
+    #[arg(long, env = "PARQUET_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    /// The S3 access key id.
+    #[arg(long, env = "PARQUET_S3_ACCESS_KEY_ID")]
+    pub s3_access_key_id: Option<String>,
+    /// The S3 secret access key.
+    #[arg(long, env = "PARQUET_S3_SECRET_ACCESS_KEY")]
+    pub s3_secret_access_key: Option<String>,
+    /// Allow connecting to the S3 endpoint over HTTP.
+    #[arg(long, env = "PARQUET_S3_ALLOW_HTTP")]
+    pub s3_allow_http: Option<bool>,
     /// The batch size to use when writing parquet files.
     #[arg(long, env = "PARQUET_BATCH_SIZE")]
     pub batch_size: Option<usize>,
+    /// The schema of the parquet files. If not specified, the schema is inferred from the data.
+    #[clap(skip)]
+    pub schema: Option<Vec<FieldOptions>>,
+    /// Target datasets, used to write more than one dataset.
+    ///
+    /// Mutually exclusive with `schema`.
+    #[clap(skip)]
+    pub datasets: Option<Vec<DatasetOptions>>,
+    /// Group files in Hive-style partitions, either `block_range(N)` or `day(field)`.
+    ///
+    /// `block_range(N)` groups files by ranges of `N` blocks, while `day(field)` groups
+    /// them by the day of the timestamp stored in `field`.
+    #[arg(long, env = "PARQUET_PARTITION_BY")]
+    pub partition_by: Option<String>,
+    /// The compression codec, for example `snappy` or `zstd(3)`.
+    #[arg(long, env = "PARQUET_COMPRESSION")]
+    pub compression: Option<String>,
+    /// The maximum number of rows in a row group.
+    #[arg(long, env = "PARQUET_ROW_GROUP_SIZE")]
+    pub row_group_size: Option<usize>,
+    /// Use dictionary encoding. Defaults to `true`.
+    #[arg(long, env = "PARQUET_DICTIONARY")]
+    pub dictionary: Option<bool>,
+    /// The maximum size of a dictionary page, in bytes.
+    #[arg(long, env = "PARQUET_DICTIONARY_PAGE_SIZE")]
+    pub dictionary_page_size: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DatasetOptions {
+    /// Dataset name.
+    pub name: String,
+    /// The schema of the dataset. If not specified, the schema is inferred from the data.
+    pub schema: Option<Vec<FieldOptions>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FieldOptions {
+    /// Field name.
+    pub name: String,
+    /// Field type, for example `int64`, `string` or `felt`.
+    pub r#type: String,
+    /// Whether the field can be null. Defaults to `true`.
+    pub nullable: Option<bool>,
+}
+
+impl Default for StorageConfiguration {
+    fn default() -> Self {
+        StorageConfiguration::Local(PathBuf::default())
+    }
 }
 
 impl SinkOptions for SinkParquetOptions {
     fn merge(self, other: Self) -> Self {
         Self {
             output_dir: self.output_dir.or(other.output_dir),
+            s3_region: self.s3_region.or(other.s3_region),
+            s3_endpoint: self.s3_endpoint.or(other.s3_endpoint),
+            s3_access_key_id: self.s3_access_key_id.or(other.s3_access_key_id),
+            s3_secret_access_key: self.s3_secret_access_key.or(other.s3_secret_access_key),
+            s3_allow_http: self.s3_allow_http.or(other.s3_allow_http),
             batch_size: self.batch_size.or(other.batch_size),
+            schema: self.schema.or(other.schema),
+            datasets: self.datasets.or(other.datasets),
+            partition_by: self.partition_by.or(other.partition_by),
+            compression: self.compression.or(other.compression),
+            row_group_size: self.row_group_size.or(other.row_group_size),
+            dictionary: self.dictionary.or(other.dictionary),
+            dictionary_page_size: self.dictionary_page_size.or(other.dictionary_page_size),
         }
     }
 }
@@ -37,15 +172,173 @@ impl SinkParquetOptions {
         let output_dir = self
             .output_dir
             .ok_or(SinkParquetError)
-            .attach_printable("missing output directory")?
-            .into();
+            .attach_printable("missing output directory")?;
+
+        let storage = match output_dir.split_once("://") {
+            None => StorageConfiguration::Local(output_dir.into()),
+            Some(("s3", location)) => {
+                let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+                if bucket.is_empty() {
+                    return Err(SinkParquetError)
+                        .attach_printable_lazy(|| format!("missing bucket in {output_dir}"));
+                }
+
+                StorageConfiguration::S3(S3Configuration {
+                    bucket: bucket.to_string(),
+                    prefix: prefix.trim_matches('/').to_string(),
+                    region: self.s3_region,
+                    endpoint: self.s3_endpoint,
+                    access_key_id: self.s3_access_key_id,
+                    secret_access_key: self.s3_secret_access_key,
+                    allow_http: self.s3_allow_http,
+                })
+            }
+            Some((scheme, _)) => {
+                return Err(SinkParquetError)
+                    .attach_printable_lazy(|| format!("unsupported output scheme {scheme}"));
+            }
+        };
 
         let batch_size = self.batch_size.unwrap_or(1000);
         let batch_size = batch_size.clamp(100, 5_000);
 
+        if self.schema.is_some() && self.datasets.is_some() {
+            return Err(SinkParquetError)
+                .attach_printable("schema and datasets are mutually exclusive");
+        }
+
+        let schema = self
+            .schema
+            .map(|fields| schema_from_options(&fields))
+            .transpose()?;
+
+        let datasets = self
+            .datasets
+            .map(|datasets| datasets_from_options(datasets))
+            .transpose()?;
+
+        let partitioning = self
+            .partition_by
+            .map(|partitioning| parse_partitioning(&partitioning))
+            .transpose()?;
+
+        let compression = self
+            .compression
+            .map(|compression| parse_compression(&compression))
+            .transpose()?;
+
+        if self.row_group_size == Some(0) {
+            return Err(SinkParquetError).attach_printable("row group size must be positive");
+        }
+
+        let writer = WriterConfiguration {
+            compression,
+            row_group_size: self.row_group_size,
+            dictionary: self.dictionary,
+            dictionary_page_size: self.dictionary_page_size,
+        };
+
         Ok(SinkParquetConfiguration {
-            output_dir,
+            storage,
             batch_size,
+            schema,
+            datasets,
+            partitioning,
+            writer,
         })
     }
 }
+
+fn datasets_from_options(
+    datasets: Vec<DatasetOptions>,
+) -> Result<Vec<DatasetConfiguration>, SinkParquetError> {
+    if datasets.is_empty() {
+        return Err(SinkParquetError).attach_printable("datasets must not be empty");
+    }
+
+    let mut configurations: Vec<DatasetConfiguration> = Vec::with_capacity(datasets.len());
+    for dataset in datasets {
+        // The name is used as a directory name, so it must not point to another directory.
+        let is_valid_name = !dataset.name.is_empty()
+            && !dataset.name.starts_with('.')
+            && !dataset.name.contains(['/', '\\']);
+        if !is_valid_name {
+            return Err(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid dataset name {:?}", dataset.name));
+        }
+
+        if configurations
+            .iter()
+            .any(|other| other.name == dataset.name)
+        {
+            return Err(SinkParquetError)
+                .attach_printable_lazy(|| format!("duplicate dataset {}", dataset.name));
+        }
+
+        let schema = dataset
+            .schema
+            .map(|fields| schema_from_options(&fields))
+            .transpose()?;
+
+        configurations.push(DatasetConfiguration {
+            name: dataset.name,
+            schema,
+        });
+    }
+
+    Ok(configurations)
+}
+
+/// Parses a compression codec, with an optional level for `gzip`, `brotli` and `zstd`.
+fn parse_compression(compression: &str) -> Result<Compression, SinkParquetError> {
+    let compression = compression.trim().to_lowercase();
+    let (codec, level) = match compression
+        .strip_suffix(')')
+        .and_then(|compression| compression.split_once('('))
+    {
+        Some((codec, level)) => {
+            let level = level
+                .trim()
+                .parse::<i32>()
+                .change_context(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid compression level {level}"))?;
+            (codec.trim(), Some(level))
+        }
+        None => (compression.as_str(), None),
+    };
+
+    let compression = match (codec, level) {
+        ("uncompressed", None) => Compression::UNCOMPRESSED,
+        ("snappy", None) => Compression::SNAPPY,
+        ("lz4", None) => Compression::LZ4,
+        ("lz4_raw", None) => Compression::LZ4_RAW,
+        ("gzip", None) => Compression::GZIP(GzipLevel::default()),
+        ("gzip", Some(level)) => Compression::GZIP(
+            u32::try_from(level)
+                .ok()
+                .and_then(|level| GzipLevel::try_new(level).ok())
+                .ok_or(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid gzip level {level}"))?,
+        ),
+        ("brotli", None) => Compression::BROTLI(BrotliLevel::default()),
+        ("brotli", Some(level)) => Compression::BROTLI(
+            u32::try_from(level)
+                .ok()
+                .and_then(|level| BrotliLevel::try_new(level).ok())
+                .ok_or(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid brotli level {level}"))?,
+        ),
+        ("zstd", None) => Compression::ZSTD(ZstdLevel::default()),
+        ("zstd", Some(level)) => Compression::ZSTD(
+            ZstdLevel::try_new(level)
+                .change_context(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid zstd level {level}"))?,
+        ),
+        _ => {
+            return Err(SinkParquetError)
+                .attach_printable_lazy(|| format!("unknown compression {compression}"));
+        }
+    };
+
+    Ok(compression)
+}
@@ -0,0 +1,515 @@
+use std::sync::Arc;
+
+use apibara_core::node::v1alpha2::Cursor;
+use apibara_sink_common::Context;
+use arrow::datatypes::SchemaRef;
+use arrow::json::reader::infer_json_schema_from_iterator;
+use arrow::record_batch::RecordBatch;
+use error_stack::{Result, ResultExt};
+use object_store::path::Path;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+use serde_json::Value;
+use tracing::{debug, info};
+
+use crate::configuration::WriterConfiguration;
+use crate::partition::Partitioning;
+use crate::schema::merge_schemas;
+use crate::sink::SinkParquetError;
+use crate::state::{BatchFile, BlockRows, State, PARTIAL_FILE_EXTENSION};
+use crate::storage::Storage;
+
+/// Key of the file metadata with the number of rows of each block range in the file.
+const BLOCK_ROWS_METADATA_KEY: &str = "apibara.block_rows";
+
+/// A set of parquet files with the same kind of data, stored in the same directory.
+///
+/// Each dataset has its own schema and file rotation.
+pub struct Dataset {
+    /// The dataset name, `None` if the sink writes a single dataset.
+    pub name: Option<String>,
+    storage: Arc<Storage>,
+    /// The directory containing the dataset files.
+    dir: Path,
+    /// How many blocks to include in a single parquet file.
+    batch_size: usize,
+    /// The configured schema. If `None`, the schema is inferred from the data.
+    schema: Option<SchemaRef>,
+    partitioning: Option<Partitioning>,
+    writer: WriterConfiguration,
+    /// The current batch.
+    state: Option<State>,
+    /// Whether the dataset already looked for a partial file to resume from.
+    resumed: bool,
+    /// The partial files that may be outdated.
+    ///
+    /// `None` until the dataset directory is listed, which happens once at startup.
+    partial_files: Option<Vec<Path>>,
+}
+
+/// A parquet file in the dataset directory.
+struct DataFile {
+    /// The first block (inclusive) in the file.
+    pub starting_block_number: u64,
+    /// The last block (exclusive) in the file.
+    pub end_block_number: u64,
+    /// Whether the file contains an incomplete batch.
+    pub partial: bool,
+    pub path: Path,
+}
+
+impl Dataset {
+    pub fn new(
+        name: Option<String>,
+        storage: Arc<Storage>,
+        dir: Path,
+        batch_size: usize,
+        schema: Option<SchemaRef>,
+        partitioning: Option<Partitioning>,
+        writer: WriterConfiguration,
+    ) -> Self {
+        Self {
+            name,
+            storage,
+            dir,
+            batch_size,
+            schema,
+            partitioning,
+            writer,
+            state: None,
+            resumed: false,
+            partial_files: None,
+        }
+    }
+
+    /// Adds the rows of a message to the current batch.
+    ///
+    /// Returns the files that are complete and must be written.
+    pub async fn handle_rows(
+        &mut self,
+        ctx: &Context,
+        rows: &[Value],
+    ) -> Result<Vec<BatchFile>, SinkParquetError> {
+        let state = match self.state.take() {
+            Some(state) => Some(state),
+            // The partial file must be loaded even if the message has no rows for this
+            // dataset, otherwise it's removed when the next file is written.
+            None if !self.resumed => {
+                self.resumed = true;
+                self.resume_from_partial(&ctx.cursor).await?
+            }
+            None => None,
+        };
+
+        let schema = match &state {
+            Some(state) if rows.is_empty() => state.schema.clone(),
+            None if rows.is_empty() => return Ok(Vec::new()),
+            state => self.batch_schema(state.as_ref(), rows)?,
+        };
+
+        // `None` if the dataset is not partitioned or the partition is not known.
+        let partition = match &self.partitioning {
+            None => None,
+            Some(partitioning) => partitioning.partition(ctx, rows)?,
+        };
+
+        let mut files = Vec::new();
+        let mut state = match state {
+            Some(mut state) if state.schema.fields() != schema.fields() => {
+                // Files have a single schema, so the current batch ends here.
+                info!(dataset = ?self.name, schema = ?schema, "schema changed, starting a new file");
+                files.push(state.take_file().await?);
+                let partition = partition.or(state.partition);
+                State::new_from_batch(
+                    self.batch_size,
+                    schema,
+                    partition,
+                    &ctx.cursor,
+                    &ctx.end_cursor,
+                )?
+            }
+            Some(mut state) if partition.is_some() && partition != state.partition => {
+                // Files belong to a single partition.
+                info!(dataset = ?self.name, partition = ?partition, "partition changed, starting a new file");
+                files.push(state.take_file().await?);
+                State::new_from_batch(
+                    self.batch_size,
+                    schema,
+                    partition,
+                    &ctx.cursor,
+                    &ctx.end_cursor,
+                )?
+            }
+            Some(state) => state,
+            None => State::new_from_batch(
+                self.batch_size,
+                schema,
+                partition,
+                &ctx.cursor,
+                &ctx.end_cursor,
+            )?,
+        };
+
+        let result = state.handle_batch(&ctx.cursor, &ctx.end_cursor, rows).await;
+        self.state = Some(state);
+        files.extend(result?);
+
+        // Blocks without rows for this dataset don't produce any file.
+        files.retain(|file| !file.block_rows.is_empty());
+        Ok(files)
+    }
+
+    /// Flushes the data in the current batch to be written to a partial file.
+    pub async fn flush_partial(&mut self) -> Result<Option<BatchFile>, SinkParquetError> {
+        match self.state.as_mut() {
+            None => Ok(None),
+            Some(state) => state.flush_partial().await,
+        }
+    }
+
+    /// Returns true if the current batch contains data that was not written to any file.
+    pub fn has_unwritten_data(&self) -> bool {
+        self.state
+            .as_ref()
+            .map(|state| state.has_unwritten_data)
+            .unwrap_or(false)
+    }
+
+    /// Write a record batch to a parquet file.
+    pub async fn write_file(&mut self, file: &BatchFile) -> Result<(), SinkParquetError> {
+        debug!(
+            dataset = ?self.name,
+            size = file.batch.num_rows(),
+            filename = file.filename,
+            "writing batch to file"
+        );
+
+        let block_rows = serde_json::to_string(&file.block_rows)
+            .change_context(SinkParquetError)
+            .attach_printable("failed to serialize block rows")?;
+        let mut properties =
+            WriterProperties::builder().set_key_value_metadata(Some(vec![KeyValue::new(
+                BLOCK_ROWS_METADATA_KEY.to_string(),
+                block_rows,
+            )]));
+        if let Some(compression) = self.writer.compression {
+            properties = properties.set_compression(compression);
+        }
+        if let Some(row_group_size) = self.writer.row_group_size {
+            properties = properties.set_max_row_group_size(row_group_size);
+        }
+        if let Some(dictionary) = self.writer.dictionary {
+            properties = properties.set_dictionary_enabled(dictionary);
+        }
+        if let Some(dictionary_page_size) = self.writer.dictionary_page_size {
+            properties = properties.set_dictionary_pagesize_limit(dictionary_page_size);
+        }
+
+        // Files are written to storage in a single operation, so that partially written
+        // files are never visible.
+        let mut output = Vec::new();
+        let mut writer =
+            ArrowWriter::try_new(&mut output, file.batch.schema(), Some(properties.build()))
+                .change_context(SinkParquetError)
+                .attach_printable("failed to create Arrow writer")?;
+
+        writer
+            .write(&file.batch)
+            .change_context(SinkParquetError)
+            .attach_printable("failed to write batch")?;
+
+        writer
+            .close()
+            .change_context(SinkParquetError)
+            .attach_printable("failed to close parquet file")?;
+
+        let path = self.file_path(&file.filename);
+        self.storage.put(&path, output).await?;
+
+        if let Some(partial_files) = self.partial_files.as_mut() {
+            if file.filename.ends_with(PARTIAL_FILE_EXTENSION) && !partial_files.contains(&path) {
+                partial_files.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the partial files that don't belong to the current batch.
+    ///
+    /// Must be called after the cursor is stored, so that the sink can always resume from
+    /// the partial file matching the stored cursor.
+    pub async fn remove_outdated_partial_files(&mut self) -> Result<(), SinkParquetError> {
+        let current = self
+            .state
+            .as_ref()
+            .map(|state| self.file_path(&state.get_partial_filename()));
+
+        let partial_files = match self.partial_files.take() {
+            Some(partial_files) => partial_files,
+            // Look for the partial files left by previous runs.
+            None => self
+                .data_files()
+                .await?
+                .into_iter()
+                .filter(|data_file| data_file.partial)
+                .map(|data_file| data_file.path)
+                .collect(),
+        };
+
+        // If removing a file fails, the directory is listed again on the next call.
+        let mut remaining = Vec::new();
+        for path in partial_files {
+            if Some(&path) == current.as_ref() {
+                remaining.push(path);
+                continue;
+            }
+            self.storage.delete(&path).await?;
+        }
+
+        self.partial_files = Some(remaining);
+        Ok(())
+    }
+
+    /// Removes the data after the given cursor from the current batch.
+    ///
+    /// Returns the files that contain invalidated data. These files must be removed after
+    /// the new cursor is stored. Returns `None` if no data was invalidated.
+    pub async fn invalidate(
+        &mut self,
+        cursor: &Cursor,
+    ) -> Result<Option<Vec<Path>>, SinkParquetError> {
+        let block_number = cursor.order_key;
+
+        let mut truncated = false;
+        if let Some(mut state) = self.state.take() {
+            if state.end_cursor.order_key <= block_number {
+                // Nothing to invalidate.
+                self.state = Some(state);
+                return Ok(None);
+            }
+
+            truncated = true;
+            if state.starting_block_number < block_number {
+                let result = state.truncate(cursor).await;
+                self.state = Some(state);
+                result?;
+            }
+        }
+
+        // Complete files that end after the cursor are removed. The file that contains the
+        // cursor becomes the current batch.
+        let mut invalidated_files = Vec::new();
+        for data_file in self.data_files().await? {
+            if data_file.end_block_number <= block_number {
+                continue;
+            }
+
+            if !data_file.partial && data_file.starting_block_number < block_number {
+                info!(path = %data_file.path, "resuming from invalidated file");
+                let (schema, partial, block_rows) = self.read_file(&data_file.path).await?;
+                let mut state = State::new(
+                    self.batch_size,
+                    schema,
+                    self.partition_of(&data_file.path),
+                    data_file.starting_block_number,
+                    cursor.clone(),
+                    partial,
+                    block_rows,
+                )?;
+                state.truncate(cursor).await?;
+                self.state = Some(state);
+            }
+
+            invalidated_files.push(data_file.path);
+        }
+
+        if !truncated && invalidated_files.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(invalidated_files))
+    }
+
+    /// Removes all data in the dataset.
+    pub async fn remove_all(&mut self) -> Result<(), SinkParquetError> {
+        self.state = None;
+        self.partial_files = None;
+        for data_file in self.data_files().await? {
+            self.storage.delete(&data_file.path).await?;
+        }
+        self.partial_files = Some(Vec::new());
+        Ok(())
+    }
+
+    /// Returns the parquet files in the dataset directory and its partitions, sorted by
+    /// block range.
+    async fn data_files(&self) -> Result<Vec<DataFile>, SinkParquetError> {
+        let mut data_files = Vec::new();
+        for path in self.storage.list(&self.dir).await? {
+            // Partition directories are named like `key=value`.
+            let in_dataset_dir = match self.partition_of(&path) {
+                None => true,
+                Some(partition) => partition.contains('='),
+            };
+            if !in_dataset_dir {
+                continue;
+            }
+            if let Some(data_file) = DataFile::from_path(path) {
+                data_files.push(data_file);
+            }
+        }
+
+        data_files.sort_by_key(|file| (file.starting_block_number, file.end_block_number));
+        Ok(data_files)
+    }
+
+    /// Returns the path of a file, relative to the dataset directory.
+    fn file_path(&self, filename: &str) -> Path {
+        filename
+            .split('/')
+            .fold(self.dir.clone(), |path, part| path.child(part))
+    }
+
+    /// Returns the directories between the dataset directory and the file, joined by `/`.
+    ///
+    /// Returns `None` if the file is directly in the dataset directory.
+    fn partition_of(&self, path: &Path) -> Option<String> {
+        let parts = path.prefix_match(&self.dir)?.collect::<Vec<_>>();
+        let (_, dirs) = parts.split_last()?;
+        if dirs.is_empty() {
+            return None;
+        }
+
+        let dirs = dirs.iter().map(|part| part.as_ref()).collect::<Vec<_>>();
+        Some(dirs.join("/"))
+    }
+
+    /// Reads a parquet file written by the sink.
+    ///
+    /// Returns the file data, together with the number of rows of each block range.
+    async fn read_file(
+        &self,
+        path: &Path,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>, Vec<BlockRows>), SinkParquetError> {
+        let data = self
+            .storage
+            .get(path)
+            .await?
+            .ok_or(SinkParquetError)
+            .attach_printable_lazy(|| format!("file {path} not found"))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(data)
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("failed to read file {path}"))?;
+
+        let block_rows = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|metadata| {
+                metadata
+                    .iter()
+                    .find(|kv| kv.key == BLOCK_ROWS_METADATA_KEY)
+                    .and_then(|kv| kv.value.as_ref())
+            })
+            .ok_or(SinkParquetError)
+            .attach_printable_lazy(|| format!("file {path} is missing the block rows metadata"))?;
+        let block_rows = serde_json::from_str(block_rows)
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("file {path} has invalid block rows metadata"))?;
+
+        let schema = builder.schema().clone();
+        let batches = builder
+            .build()
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("failed to read file {path}"))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("failed to read file {path}"))?;
+
+        Ok((schema, batches, block_rows))
+    }
+
+    /// Loads the partial file that ends at the given cursor, if any.
+    async fn resume_from_partial(
+        &self,
+        cursor: &Option<Cursor>,
+    ) -> Result<Option<State>, SinkParquetError> {
+        let Some(cursor) = cursor else {
+            return Ok(None);
+        };
+
+        let Some(data_file) = self
+            .data_files()
+            .await?
+            .into_iter()
+            .find(|file| file.partial && file.end_block_number == cursor.order_key)
+        else {
+            return Ok(None);
+        };
+
+        info!(path = %data_file.path, "resuming from partial file");
+        let (schema, partial, block_rows) = self.read_file(&data_file.path).await?;
+
+        let state = State::new(
+            self.batch_size,
+            schema,
+            self.partition_of(&data_file.path),
+            data_file.starting_block_number,
+            cursor.clone(),
+            partial,
+            block_rows,
+        )?;
+
+        Ok(Some(state))
+    }
+
+    /// Returns the schema of the file that will contain the given rows.
+    ///
+    /// If the schema is not configured, it's inferred from the data and merged with the
+    /// schema of the current file.
+    fn batch_schema(
+        &self,
+        state: Option<&State>,
+        rows: &[Value],
+    ) -> Result<SchemaRef, SinkParquetError> {
+        if let Some(schema) = &self.schema {
+            return Ok(schema.clone());
+        }
+
+        let inferred = infer_json_schema_from_iterator(rows.iter().map(std::result::Result::Ok))
+            .change_context(SinkParquetError)
+            .attach_printable("failed to infer json schema")?;
+
+        match state {
+            None => {
+                debug!(dataset = ?self.name, schema = ?inferred, "inferred schema from batch");
+                Ok(Arc::new(inferred))
+            }
+            Some(state) => merge_schemas(&state.schema, &inferred),
+        }
+    }
+}
+
+impl DataFile {
+    /// Parses the block range from the file name.
+    ///
+    /// Returns `None` if the file was not written by the sink.
+    fn from_path(path: Path) -> Option<Self> {
+        let filename = path.filename()?;
+        let (range, partial) = match filename.strip_suffix(PARTIAL_FILE_EXTENSION) {
+            Some(filename) => (filename.strip_suffix(".parquet.")?, true),
+            None => (filename.strip_suffix(".parquet")?, false),
+        };
+        let (start, end) = range.split_once('_')?;
+
+        Some(DataFile {
+            starting_block_number: start.parse().ok()?,
+            end_block_number: end.parse().ok()?,
+            partial,
+            path,
+        })
+    }
+}
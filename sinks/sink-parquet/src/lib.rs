@@ -1,5 +1,14 @@
 mod configuration;
+mod dataset;
+mod partition;
+mod schema;
 mod sink;
+mod state;
+mod storage;
 
-pub use self::configuration::{SinkParquetConfiguration, SinkParquetOptions};
+pub use self::configuration::{
+    DatasetConfiguration, DatasetOptions, FieldOptions, S3Configuration, SinkParquetConfiguration,
+    SinkParquetOptions, StorageConfiguration, WriterConfiguration,
+};
+pub use self::partition::Partitioning;
 pub use self::sink::{ParquetSink, SinkParquetError};
@@ -0,0 +1,167 @@
+use apibara_sink_common::Context;
+use chrono::{DateTime, NaiveDateTime};
+use error_stack::{Result, ResultExt};
+use serde_json::Value;
+
+use crate::sink::SinkParquetError;
+
+/// How files are grouped in Hive-style partitions.
+#[derive(Debug, Clone)]
+pub enum Partitioning {
+    /// Partition by block number, with the given number of blocks in each partition.
+    ///
+    /// Files are stored in directories like `block_range=1000000`.
+    BlockRange(u64),
+    /// Partition by the day of the timestamp stored in the given field.
+    ///
+    /// Files are stored in directories like `day=2023-08-15`.
+    Day(String),
+}
+
+impl Partitioning {
+    /// Returns the partition of the data in a message, for example `day=2023-08-15`.
+    ///
+    /// Returns `None` if the partition can't be known because the message has no rows.
+    pub fn partition(
+        &self,
+        ctx: &Context,
+        rows: &[Value],
+    ) -> Result<Option<String>, SinkParquetError> {
+        match self {
+            Partitioning::BlockRange(size) => {
+                let block_number = ctx.cursor.as_ref().map(|c| c.order_key).unwrap_or(0);
+                let start = block_number - block_number % size;
+                Ok(Some(format!("block_range={start}")))
+            }
+            Partitioning::Day(field) => {
+                let Some(row) = rows.first() else {
+                    return Ok(None);
+                };
+
+                let timestamp = row
+                    .get(field)
+                    .ok_or(SinkParquetError)
+                    .attach_printable_lazy(|| format!("row missing timestamp field {field}"))?;
+                let day = timestamp_to_day(timestamp)
+                    .attach_printable_lazy(|| format!("invalid timestamp in field {field}"))?;
+                Ok(Some(format!("day={day}")))
+            }
+        }
+    }
+}
+
+/// Parses the partitioning option, either `block_range(N)` or `day(field)`.
+pub fn parse_partitioning(partitioning: &str) -> Result<Partitioning, SinkParquetError> {
+    let partitioning = partitioning.trim();
+    let (kind, arg) = partitioning
+        .strip_suffix(')')
+        .and_then(|partitioning| partitioning.split_once('('))
+        .ok_or(SinkParquetError)
+        .attach_printable_lazy(|| format!("invalid partitioning {partitioning}"))?;
+
+    match (kind.trim(), arg.trim()) {
+        ("block_range", size) => {
+            let size = size
+                .parse::<u64>()
+                .change_context(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid block range size {size}"))?;
+            if size == 0 {
+                return Err(SinkParquetError).attach_printable("block range size must be positive");
+            }
+            Ok(Partitioning::BlockRange(size))
+        }
+        ("day", "") => Err(SinkParquetError).attach_printable("missing day timestamp field"),
+        ("day", field) => Ok(Partitioning::Day(field.to_string())),
+        _ => Err(SinkParquetError)
+            .attach_printable_lazy(|| format!("unknown partitioning {partitioning}")),
+    }
+}
+
+/// Returns the UTC day of a timestamp, either seconds since the unix epoch or an RFC 3339
+/// string.
+fn timestamp_to_day(timestamp: &Value) -> Result<String, SinkParquetError> {
+    let datetime = match timestamp {
+        Value::Number(seconds) => {
+            let seconds = seconds
+                .as_i64()
+                .ok_or(SinkParquetError)
+                .attach_printable("timestamp is not an integer")?;
+            NaiveDateTime::from_timestamp_opt(seconds, 0)
+                .ok_or(SinkParquetError)
+                .attach_printable("timestamp out of range")?
+        }
+        Value::String(datetime) => DateTime::parse_from_rfc3339(datetime)
+            .change_context(SinkParquetError)
+            .attach_printable("timestamp is not a RFC 3339 date")?
+            .naive_utc(),
+        _ => {
+            return Err(SinkParquetError).attach_printable("timestamp is not a number or a string");
+        }
+    };
+
+    Ok(datetime.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::node::v1alpha2::{Cursor, DataFinality};
+    use apibara_sink_common::Context;
+    use serde_json::json;
+
+    use super::{parse_partitioning, Partitioning};
+
+    fn new_context(order_key: u64) -> Context {
+        Context {
+            cursor: Some(Cursor {
+                order_key,
+                unique_key: Vec::new(),
+            }),
+            end_cursor: Cursor {
+                order_key: order_key + 1,
+                unique_key: Vec::new(),
+            },
+            finality: DataFinality::DataStatusFinalized,
+        }
+    }
+
+    #[test]
+    pub fn test_parse_partitioning() {
+        assert!(matches!(
+            parse_partitioning("block_range(10000)").unwrap(),
+            Partitioning::BlockRange(10000)
+        ));
+        assert!(matches!(
+            parse_partitioning("day(timestamp)").unwrap(),
+            Partitioning::Day(field) if field == "timestamp"
+        ));
+        assert!(parse_partitioning("block_range(0)").is_err());
+        assert!(parse_partitioning("day()").is_err());
+        assert!(parse_partitioning("month(timestamp)").is_err());
+        assert!(parse_partitioning("day").is_err());
+    }
+
+    #[test]
+    pub fn test_partition() {
+        let partitioning = Partitioning::BlockRange(1000);
+        let partition = partitioning.partition(&new_context(12_345), &[]).unwrap();
+        assert_eq!(partition.as_deref(), Some("block_range=12000"));
+
+        let partitioning = Partitioning::Day("timestamp".to_string());
+        let ctx = new_context(0);
+        assert_eq!(partitioning.partition(&ctx, &[]).unwrap(), None);
+
+        let partition = partitioning
+            .partition(&ctx, &[json!({ "timestamp": 1692057600 })])
+            .unwrap();
+        assert_eq!(partition.as_deref(), Some("day=2023-08-15"));
+
+        let partition = partitioning
+            .partition(&ctx, &[json!({ "timestamp": "2023-08-15T23:30:00-02:00" })])
+            .unwrap();
+        assert_eq!(partition.as_deref(), Some("day=2023-08-16"));
+
+        assert!(partitioning
+            .partition(&ctx, &[json!({ "block_number": 0 })])
+            .is_err());
+    }
+}
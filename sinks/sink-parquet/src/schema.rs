@@ -0,0 +1,385 @@
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, Decimal128Array, Decimal256Array, FixedSizeBinaryArray, StringArray,
+};
+use arrow::datatypes::{i256, DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use error_stack::{Result, ResultExt};
+
+use crate::configuration::FieldOptions;
+use crate::sink::SinkParquetError;
+
+/// Creates the arrow schema from the fields in the configuration.
+pub fn schema_from_options(fields: &[FieldOptions]) -> Result<SchemaRef, SinkParquetError> {
+    if fields.is_empty() {
+        return Err(SinkParquetError).attach_printable("schema must have at least one field");
+    }
+
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let data_type = parse_data_type(&field.r#type)
+                .attach_printable_lazy(|| format!("invalid type for field {}", field.name))?;
+            Ok(Field::new(
+                &field.name,
+                data_type,
+                field.nullable.unwrap_or(true),
+            ))
+        })
+        .collect::<Result<Vec<_>, SinkParquetError>>()?;
+
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Parses a data type, for example `int64`, `felt` or `decimal256(76, 0)`.
+pub fn parse_data_type(data_type: &str) -> Result<DataType, SinkParquetError> {
+    let data_type = data_type.trim().to_lowercase();
+    let parsed = match data_type.as_str() {
+        "bool" | "boolean" => DataType::Boolean,
+        "int8" => DataType::Int8,
+        "int16" => DataType::Int16,
+        "int32" => DataType::Int32,
+        "int64" => DataType::Int64,
+        "uint8" => DataType::UInt8,
+        "uint16" => DataType::UInt16,
+        "uint32" => DataType::UInt32,
+        "uint64" => DataType::UInt64,
+        "float32" => DataType::Float32,
+        "float64" => DataType::Float64,
+        "string" | "utf8" => DataType::Utf8,
+        // Field elements are smaller than 2^252, so they fit in 32 bytes.
+        "felt" => DataType::FixedSizeBinary(32),
+        _ => {
+            let (name, args) = data_type
+                .strip_suffix(')')
+                .and_then(|data_type| data_type.split_once('('))
+                .ok_or(SinkParquetError)
+                .attach_printable_lazy(|| format!("unknown data type {data_type}"))?;
+            let args = args
+                .split(',')
+                .map(|arg| arg.trim().parse::<i32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .change_context(SinkParquetError)
+                .attach_printable_lazy(|| format!("invalid arguments of data type {data_type}"))?;
+
+            match (name.trim(), args.as_slice()) {
+                ("fixed_size_binary", [size]) => DataType::FixedSizeBinary(*size),
+                ("decimal128", [precision, scale]) => {
+                    DataType::Decimal128(decimal_precision(*precision)?, decimal_scale(*scale)?)
+                }
+                ("decimal256", [precision, scale]) => {
+                    DataType::Decimal256(decimal_precision(*precision)?, decimal_scale(*scale)?)
+                }
+                _ => {
+                    return Err(SinkParquetError)
+                        .attach_printable_lazy(|| format!("unknown data type {data_type}"));
+                }
+            }
+        }
+    };
+
+    Ok(parsed)
+}
+
+/// Returns the schema used to decode JSON data.
+///
+/// The JSON decoder doesn't support fixed size binary and decimal types, so these fields
+/// are decoded as strings and then converted by [convert_batch].
+pub fn decoding_schema(schema: &Schema) -> SchemaRef {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| match field.data_type() {
+            DataType::FixedSizeBinary(_)
+            | DataType::Decimal128(_, _)
+            | DataType::Decimal256(_, _) => {
+                Field::new(field.name(), DataType::Utf8, field.is_nullable())
+            }
+            _ => field.as_ref().clone(),
+        })
+        .collect::<Vec<_>>();
+
+    Arc::new(Schema::new(fields))
+}
+
+/// Converts a batch decoded with the [decoding_schema] to the given schema.
+pub fn convert_batch(
+    batch: RecordBatch,
+    schema: &SchemaRef,
+) -> Result<RecordBatch, SinkParquetError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(schema.fields())
+        .map(|(column, field)| {
+            let Some(strings) = column.as_any().downcast_ref::<StringArray>() else {
+                return Ok(column.clone());
+            };
+
+            let converted = match field.data_type() {
+                DataType::FixedSizeBinary(size) => to_fixed_size_binary(strings, *size),
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    to_decimal(strings, field.data_type())
+                }
+                _ => Ok(column.clone()),
+            };
+            converted.attach_printable_lazy(|| format!("failed to convert field {}", field.name()))
+        })
+        .collect::<Result<Vec<_>, SinkParquetError>>()?;
+
+    RecordBatch::try_new(schema.clone(), columns)
+        .change_context(SinkParquetError)
+        .attach_printable("failed to create record batch")
+}
+
+/// Merges the schema inferred from a new batch into the current schema.
+///
+/// New fields are appended to the schema. Fields with conflicting types are stored
+/// as floats, if both types are numbers, or as strings.
+pub fn merge_schemas(current: &Schema, new: &Schema) -> Result<SchemaRef, SinkParquetError> {
+    let mut fields = current
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect::<Vec<_>>();
+
+    for new_field in new.fields() {
+        let Some(field) = fields
+            .iter_mut()
+            .find(|field| field.name() == new_field.name())
+        else {
+            fields.push(new_field.as_ref().clone());
+            continue;
+        };
+
+        if field.data_type() == new_field.data_type() {
+            continue;
+        }
+
+        if matches!(field.data_type(), DataType::Struct(_) | DataType::List(_)) {
+            field
+                .try_merge(new_field)
+                .change_context(SinkParquetError)
+                .attach_printable_lazy(|| {
+                    format!("incompatible types for field {}", field.name())
+                })?;
+            continue;
+        }
+
+        let data_type = match (field.data_type(), new_field.data_type()) {
+            (DataType::Int64, DataType::Float64) | (DataType::Float64, DataType::Int64) => {
+                DataType::Float64
+            }
+            _ => DataType::Utf8,
+        };
+
+        *field = Field::new(field.name(), data_type, true);
+    }
+
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+fn decimal_precision(precision: i32) -> Result<u8, SinkParquetError> {
+    u8::try_from(precision)
+        .change_context(SinkParquetError)
+        .attach_printable_lazy(|| format!("invalid decimal precision {precision}"))
+}
+
+fn decimal_scale(scale: i32) -> Result<i8, SinkParquetError> {
+    i8::try_from(scale)
+        .ok()
+        .filter(|scale| *scale >= 0)
+        .ok_or(SinkParquetError)
+        .attach_printable_lazy(|| format!("invalid decimal scale {scale}"))
+}
+
+fn to_fixed_size_binary(strings: &StringArray, size: i32) -> Result<ArrayRef, SinkParquetError> {
+    let values = strings
+        .iter()
+        .map(|value| {
+            value
+                .map(|value| parse_hex(value, size as usize))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, SinkParquetError>>()?;
+
+    // Arrays without any value are not supported by `try_from_sparse_iter_with_size`.
+    if values.iter().all(Option::is_none) {
+        return Ok(arrow::array::new_null_array(
+            &DataType::FixedSizeBinary(size),
+            values.len(),
+        ));
+    }
+
+    let array = FixedSizeBinaryArray::try_from_sparse_iter_with_size(values.into_iter(), size)
+        .change_context(SinkParquetError)
+        .attach_printable("failed to create fixed size binary array")?;
+
+    Ok(Arc::new(array))
+}
+
+fn to_decimal(strings: &StringArray, data_type: &DataType) -> Result<ArrayRef, SinkParquetError> {
+    match data_type {
+        DataType::Decimal128(precision, scale) => {
+            let values = strings
+                .iter()
+                .map(|value| {
+                    value
+                        .map(|value| {
+                            parse_decimal(value, *scale)?
+                                .to_i128()
+                                .ok_or(SinkParquetError)
+                                .attach_printable_lazy(|| {
+                                    format!("value {value} does not fit in decimal128")
+                                })
+                        })
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>, SinkParquetError>>()?;
+            let array = Decimal128Array::from(values)
+                .with_precision_and_scale(*precision, *scale)
+                .change_context(SinkParquetError)?;
+            array
+                .validate_decimal_precision(*precision)
+                .change_context(SinkParquetError)?;
+            Ok(Arc::new(array))
+        }
+        DataType::Decimal256(precision, scale) => {
+            let values = strings
+                .iter()
+                .map(|value| value.map(|value| parse_decimal(value, *scale)).transpose())
+                .collect::<Result<Vec<_>, SinkParquetError>>()?;
+            let array = Decimal256Array::from(values)
+                .with_precision_and_scale(*precision, *scale)
+                .change_context(SinkParquetError)?;
+            array
+                .validate_decimal_precision(*precision)
+                .change_context(SinkParquetError)?;
+            Ok(Arc::new(array))
+        }
+        _ => Err(SinkParquetError)
+            .attach_printable_lazy(|| format!("{data_type} is not a decimal type")),
+    }
+}
+
+/// Parses a hex string, left padding it with zeros to the given size.
+fn parse_hex(value: &str, size: usize) -> Result<Vec<u8>, SinkParquetError> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    let digits = if digits.len() % 2 == 1 {
+        format!("0{digits}")
+    } else {
+        digits.to_string()
+    };
+
+    if digits.len() / 2 > size {
+        return Err(SinkParquetError)
+            .attach_printable_lazy(|| format!("value {value} is larger than {size} bytes"));
+    }
+
+    let mut bytes = vec![0; size - digits.len() / 2];
+    for i in (0..digits.len()).step_by(2) {
+        let byte = u8::from_str_radix(&digits[i..i + 2], 16)
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("value {value} is not a hex string"))?;
+        bytes.push(byte);
+    }
+
+    Ok(bytes)
+}
+
+/// Parses a decimal or hex string to a decimal with the given scale.
+fn parse_decimal(value: &str, scale: i8) -> Result<i256, SinkParquetError> {
+    let scale = scale as usize;
+
+    if value.starts_with("0x") {
+        let bytes = parse_hex(value, 32)?;
+        let integer = i256::from_be_bytes(bytes.try_into().expect("value has 32 bytes"));
+        return i256::from_i128(10)
+            .checked_pow(scale as u32)
+            .and_then(|multiplier| integer.checked_mul(multiplier))
+            .ok_or(SinkParquetError)
+            .attach_printable_lazy(|| format!("value {value} is too large"));
+    }
+
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > scale {
+        return Err(SinkParquetError)
+            .attach_printable_lazy(|| format!("value {value} has more than {scale} decimals"));
+    }
+
+    let digits = format!("{integer}{fraction:0<scale$}");
+    i256::from_string(&digits)
+        .ok_or(SinkParquetError)
+        .attach_printable_lazy(|| format!("value {value} is not a decimal number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::datatypes::{i256, DataType, Field, Schema};
+
+    use super::{merge_schemas, parse_data_type, parse_decimal, parse_hex};
+
+    #[test]
+    pub fn test_parse_data_type() {
+        assert_eq!(parse_data_type("int64").unwrap(), DataType::Int64);
+        assert_eq!(parse_data_type("String").unwrap(), DataType::Utf8);
+        assert_eq!(
+            parse_data_type("felt").unwrap(),
+            DataType::FixedSizeBinary(32)
+        );
+        assert_eq!(
+            parse_data_type("fixed_size_binary(20)").unwrap(),
+            DataType::FixedSizeBinary(20)
+        );
+        assert_eq!(
+            parse_data_type("decimal256(76, 0)").unwrap(),
+            DataType::Decimal256(76, 0)
+        );
+        assert!(parse_data_type("decimal128(38)").is_err());
+        assert!(parse_data_type("decimal128(38, -1)").is_err());
+        assert!(parse_data_type("felt252").is_err());
+    }
+
+    #[test]
+    pub fn test_parse_values() {
+        assert_eq!(parse_hex("0x1", 2).unwrap(), vec![0, 1]);
+        assert_eq!(parse_hex("abcd", 2).unwrap(), vec![0xab, 0xcd]);
+        assert!(parse_hex("0x10000", 2).is_err());
+        assert!(parse_hex("0xzz", 2).is_err());
+
+        assert_eq!(parse_decimal("0x10", 0).unwrap(), i256::from_i128(16));
+        assert_eq!(parse_decimal("0x10", 2).unwrap(), i256::from_i128(1600));
+        assert_eq!(parse_decimal("-1.5", 2).unwrap(), i256::from_i128(-150));
+        assert!(parse_decimal("1.555", 2).is_err());
+    }
+
+    #[test]
+    pub fn test_merge_schemas() {
+        let current = Schema::new(vec![
+            Field::new("a", DataType::Int64, true),
+            Field::new("b", DataType::Int64, true),
+            Field::new("c", DataType::Utf8, true),
+        ]);
+        let new = Schema::new(vec![
+            Field::new("b", DataType::Float64, true),
+            Field::new("c", DataType::Boolean, true),
+            Field::new("d", DataType::Boolean, true),
+        ]);
+
+        let merged = merge_schemas(&current, &new).unwrap();
+        assert_eq!(
+            merged.as_ref(),
+            &Schema::new(vec![
+                Field::new("a", DataType::Int64, true),
+                Field::new("b", DataType::Float64, true),
+                Field::new("c", DataType::Utf8, true),
+                Field::new("d", DataType::Boolean, true),
+            ])
+        );
+
+        // Merging a subset of the schema doesn't change it.
+        let merged = merge_schemas(&merged, &current).unwrap();
+        assert_eq!(merged.fields().len(), 4);
+    }
+}
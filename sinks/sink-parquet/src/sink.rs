@@ -1,18 +1,21 @@
 use std::fmt;
-use std::{fs::File, sync::Arc};
+use std::sync::Arc;
 
-use apibara_core::node::v1alpha2::Cursor;
-use apibara_sink_common::{Context, CursorAction, Sink, ValueExt};
-use arrow::json::reader::{infer_json_schema_from_iterator, Decoder, ReaderBuilder};
-use arrow::record_batch::RecordBatch;
+use apibara_core::node::v1alpha2::{Cursor, DataFinality};
+use apibara_sink_common::{Context, CursorAction, DisplayCursor, Sink, ValueExt};
 use async_trait::async_trait;
 use error_stack::{Result, ResultExt};
-use parquet::arrow::ArrowWriter;
+use object_store::path::Path;
 use serde_json::Value;
-use tokio::sync::Mutex;
-use tracing::{debug, instrument, warn};
+use tracing::{debug, info, instrument, warn};
 
 use crate::configuration::{SinkParquetConfiguration, SinkParquetOptions};
+use crate::dataset::Dataset;
+use crate::state::BatchFile;
+use crate::storage::Storage;
+
+/// Name of the file storing the cursor of the last block written to disk.
+const CURSOR_FILE_NAME: &str = "_apibara_cursor.json";
 
 #[derive(Debug)]
 pub struct SinkParquetError;
@@ -26,63 +29,194 @@ impl fmt::Display for SinkParquetError {
 
 pub struct ParquetSink {
     config: SinkParquetConfiguration,
-    /// Sync state.
-    state: Option<State>,
-}
-
-/// Sink state.
-///
-/// This is used to keep track of the file schema, starting and end blocks.
-struct State {
-    /// JSON to arrow data decoder.
-    /// Notice that [Decoder] is not `Sync` so we need to wrap it in a mutex.
-    pub decoder: Mutex<Decoder>,
-    /// How many blocks to include in a single parquet file.
-    pub batch_size: usize,
-    /// The first block (inclusive) in the current batch.
-    pub starting_block_number: u64,
-    /// The last block (exclusive) in the current batch.
-    pub end_block_number: u64,
+    storage: Arc<Storage>,
+    datasets: Vec<Dataset>,
+    /// The cursor of the last block handled by the sink.
+    end_cursor: Option<Cursor>,
+    /// Whether the cursor file exists.
+    ///
+    /// Indexers that were started before the sink stored its own cursor don't have a cursor
+    /// file. In this case, the connector resumes from the cursor stored by the persistence
+    /// backend.
+    has_cursor_file: bool,
 }
 
 impl ParquetSink {
-    pub fn new(config: SinkParquetConfiguration) -> Self {
-        Self {
+    pub fn new(config: SinkParquetConfiguration) -> Result<Self, SinkParquetError> {
+        let storage = Arc::new(Storage::new(&config.storage)?);
+
+        let datasets = match &config.datasets {
+            None => vec![Dataset::new(
+                None,
+                storage.clone(),
+                Path::default(),
+                config.batch_size,
+                config.schema.clone(),
+                config.partitioning.clone(),
+                config.writer.clone(),
+            )],
+            Some(datasets) => datasets
+                .iter()
+                .map(|dataset| {
+                    Dataset::new(
+                        Some(dataset.name.clone()),
+                        storage.clone(),
+                        Path::from(dataset.name.as_str()),
+                        config.batch_size,
+                        dataset.schema.clone(),
+                        config.partitioning.clone(),
+                        config.writer.clone(),
+                    )
+                })
+                .collect(),
+        };
+
+        Ok(Self {
             config,
-            state: None,
+            storage,
+            datasets,
+            end_cursor: None,
+            has_cursor_file: false,
+        })
+    }
+
+    /// Groups the rows by dataset.
+    fn group_rows(&self, batch: &[Value]) -> Result<Vec<Vec<Value>>, SinkParquetError> {
+        if self.config.datasets.is_none() {
+            return Ok(vec![batch.to_vec()]);
         }
+
+        let mut rows = vec![Vec::new(); self.datasets.len()];
+        for item in batch {
+            let (dataset_index, data) = self.route_item(item)?;
+            rows[dataset_index].push(data.clone());
+        }
+
+        Ok(rows)
+    }
+
+    /// Returns the index of the dataset targeted by the item, together with the item's data.
+    fn route_item<'a>(&self, item: &'a Value) -> Result<(usize, &'a Value), SinkParquetError> {
+        let dataset_name = item
+            .get("dataset")
+            .ok_or(SinkParquetError)
+            .attach_printable("item missing dataset key")?
+            .as_str()
+            .ok_or(SinkParquetError)
+            .attach_printable("dataset is not a string")?;
+
+        let dataset_index = self
+            .datasets
+            .iter()
+            .position(|dataset| dataset.name.as_deref() == Some(dataset_name))
+            .ok_or(SinkParquetError)
+            .attach_printable_lazy(|| {
+                format!("dataset {dataset_name} is not in the sink datasets")
+            })?;
+
+        let data = item
+            .get("data")
+            .ok_or(SinkParquetError)
+            .attach_printable("item missing data key")?;
+        if !data.is_object() {
+            return Err(SinkParquetError).attach_printable("data is not an object");
+        }
+
+        Ok((dataset_index, data))
+    }
+
+    /// Writes the data of the current batches to partial files.
+    ///
+    /// The partial files are replaced by the complete files once the batches are complete.
+    async fn write_partial(&mut self) -> Result<(), SinkParquetError> {
+        let Some(end_cursor) = self.end_cursor.clone() else {
+            return Ok(());
+        };
+
+        if !self.datasets.iter().any(Dataset::has_unwritten_data) {
+            return Ok(());
+        }
+
+        self.commit(Vec::new(), &end_cursor).await
     }
 
-    /// Write a record batch to a parquet file.
-    fn write_batch(
+    /// Writes the given files together with the partial files of all datasets, then stores
+    /// the cursor and removes the partial files that are now outdated.
+    ///
+    /// The data of every dataset up to the cursor is on disk before the cursor is stored,
+    /// so that the sink can always resume from the stored cursor.
+    async fn commit(
         &mut self,
-        batch: RecordBatch,
-        filename: String,
+        mut files: Vec<(usize, BatchFile)>,
+        end_cursor: &Cursor,
     ) -> Result<(), SinkParquetError> {
-        debug!(
-            size = batch.num_rows(),
-            filename = filename,
-            "writing batch to file"
-        );
-
-        let output_file = self.config.output_dir.join(filename);
-        let mut file = File::create(&output_file)
-            .change_context(SinkParquetError)
-            .attach_printable_lazy(|| format!("failed to create output file at {output_file:?}"))?;
+        for (dataset_index, dataset) in self.datasets.iter_mut().enumerate() {
+            if let Some(file) = dataset.flush_partial().await? {
+                files.push((dataset_index, file));
+            }
+        }
 
-        let mut writer = ArrowWriter::try_new(&mut file, batch.schema(), None)
-            .change_context(SinkParquetError)
-            .attach_printable("failed to create Arrow writer")?;
+        for (dataset_index, file) in &files {
+            self.datasets[*dataset_index].write_file(file).await?;
+        }
 
-        writer
-            .write(&batch)
-            .change_context(SinkParquetError)
-            .attach_printable("failed to write batch")?;
+        self.put_cursor(end_cursor).await?;
+
+        for dataset in &mut self.datasets {
+            dataset.remove_outdated_partial_files().await?;
+        }
 
-        writer
-            .close()
+        Ok(())
+    }
+
+    async fn put_cursor(&mut self, cursor: &Cursor) -> Result<(), SinkParquetError> {
+        let serialized = serde_json::to_vec(cursor)
             .change_context(SinkParquetError)
-            .attach_printable("failed to close parquet file")?;
+            .attach_printable("failed to serialize cursor")?;
+
+        self.storage
+            .put(&Path::from(CURSOR_FILE_NAME), serialized)
+            .await?;
+        self.has_cursor_file = true;
+        Ok(())
+    }
+
+    /// Removes the data after the given cursor from the current batches and from the
+    /// files already written.
+    async fn invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), SinkParquetError> {
+        let Some(cursor) = cursor else {
+            // Invalidate all data.
+            for dataset in &mut self.datasets {
+                dataset.remove_all().await?;
+            }
+            self.end_cursor = None;
+            self.storage.delete(&Path::from(CURSOR_FILE_NAME)).await?;
+            self.has_cursor_file = false;
+            return Ok(());
+        };
+
+        let mut invalidated = false;
+        let mut invalidated_files = Vec::new();
+        for dataset in &mut self.datasets {
+            if let Some(files) = dataset.invalidate(cursor).await? {
+                invalidated = true;
+                invalidated_files.extend(files);
+            }
+        }
+
+        if !invalidated {
+            return Ok(());
+        }
+
+        // Store the new cursor before removing files, so that the sink never resumes from a
+        // cursor without data.
+        self.end_cursor = Some(cursor.clone());
+        self.commit(Vec::new(), cursor).await?;
+
+        for path in invalidated_files {
+            info!(path = %path, "removing invalidated file");
+            self.storage.delete(&path).await?;
+        }
 
         Ok(())
     }
@@ -95,7 +229,9 @@ impl Sink for ParquetSink {
 
     async fn from_options(options: Self::Options) -> Result<Self, Self::Error> {
         let config = options.to_parquet_configuration()?;
-        Ok(Self::new(config))
+        let mut sink = Self::new(config)?;
+        sink.has_cursor_file = sink.get_cursor().await?.is_some();
+        Ok(sink)
     }
 
     #[instrument(skip_all, err(Debug))]
@@ -104,121 +240,73 @@ impl Sink for ParquetSink {
         ctx: &Context,
         batch: &Value,
     ) -> Result<CursorAction, Self::Error> {
-        let Some(batch) = batch.as_array_of_objects() else {
-            warn!("data is not an array of objects, skipping");
-            // Skip persistence in case the buffer is still not flushed
-            return Ok(CursorAction::Skip);
-        };
-
-        if batch.is_empty() {
-            // Skip persistence in case the buffer is still not flushed
+        // Pending data is invalidated by the next message, the same blocks are received
+        // again once they're accepted.
+        if ctx.finality == DataFinality::DataStatusPending {
+            debug!(ctx = %ctx, "skipping pending data");
             return Ok(CursorAction::Skip);
         }
 
-        debug!(ctx = %ctx, "handling data");
-
-        let mut state = match self.state.take() {
-            Some(state) => state,
+        // Messages without rows still extend the current batches, so that the batches of
+        // all datasets end at the same cursor.
+        let rows = match batch.as_array_of_objects() {
+            Some(batch) => self.group_rows(batch)?,
             None => {
-                State::new_from_batch(self.config.batch_size, &ctx.cursor, &ctx.end_cursor, batch)?
+                warn!("data is not an array of objects, skipping");
+                vec![Vec::new(); self.datasets.len()]
             }
         };
 
-        let mut cursor_action = CursorAction::Skip;
+        debug!(ctx = %ctx, "handling data");
 
-        if let Some((batch, filename)) = state
-            .handle_batch(&ctx.cursor, &ctx.end_cursor, batch)
-            .await?
-        {
-            self.write_batch(batch, filename)?;
-            cursor_action = CursorAction::Persist;
+        let mut files = Vec::new();
+        for (dataset_index, (dataset, rows)) in self.datasets.iter_mut().zip(rows).enumerate() {
+            for file in dataset.handle_rows(ctx, &rows).await? {
+                files.push((dataset_index, file));
+            }
         }
+        self.end_cursor = Some(ctx.end_cursor.clone());
 
-        self.state = Some(state);
-        Ok(cursor_action)
-    }
+        if files.is_empty() {
+            // Skip persistence in case the buffer is still not flushed
+            return Ok(CursorAction::Skip);
+        }
 
-    #[instrument(skip(self, _cursor), err(Debug))]
-    async fn handle_invalidate(&mut self, _cursor: &Option<Cursor>) -> Result<(), Self::Error> {
-        Ok(())
+        self.commit(files, &ctx.end_cursor).await?;
+        Ok(CursorAction::Persist)
     }
 
-    #[instrument(skip(self), err(Debug))]
-    async fn handle_heartbeat(&mut self) -> Result<(), Self::Error> {
-        // TODO: write to incomplete file.
-        Ok(())
-    }
-
-    async fn cleanup(&mut self) -> Result<(), Self::Error> {
-        // TODO: flush to incomplete file.
-        Ok(())
+    #[instrument(skip(self, cursor), err(Debug))]
+    async fn handle_invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), Self::Error> {
+        debug!(cursor = %DisplayCursor(cursor), "handling invalidate");
+        self.invalidate(cursor).await
     }
-}
 
-impl State {
-    /// Initialize state from the first batch of data.
-    pub fn new_from_batch(
-        batch_size: usize,
-        cursor: &Option<Cursor>,
-        end_cursor: &Cursor,
-        batch: &[Value],
-    ) -> Result<Self, SinkParquetError> {
-        let schema = infer_json_schema_from_iterator(batch.iter().map(std::result::Result::Ok))
-            .change_context(SinkParquetError)
-            .attach_printable("failed to infer json schema")?;
-        debug!(schema = ?schema, "inferred schema from batch");
-        let decoder = ReaderBuilder::new(Arc::new(schema))
-            .build_decoder()
-            .change_context(SinkParquetError)
-            .attach_printable("failed to create reader")?;
-        let starting_block_number = cursor.as_ref().map(|c| c.order_key).unwrap_or(0);
-        let end_block_number = end_cursor.order_key;
-
-        Ok(State {
-            decoder: Mutex::new(decoder),
-            starting_block_number,
-            batch_size,
-            end_block_number,
-        })
+    fn is_transactional(&self) -> bool {
+        // The cursor is stored together with the files, so that the sink resumes from the
+        // last block written to disk.
+        self.has_cursor_file
     }
 
-    pub async fn handle_batch(
-        &mut self,
-        _cursor: &Option<Cursor>,
-        end_cursor: &Cursor,
-        batch: &[Value],
-    ) -> Result<Option<(RecordBatch, String)>, SinkParquetError> {
-        debug!(size = batch.len(), "handling batch");
-        let mut decoder = self.decoder.lock().await;
-        (*decoder)
-            .serialize(batch)
-            .change_context(SinkParquetError)
-            .attach_printable("failed to serialize batch data")?;
-
-        self.end_block_number = end_cursor.order_key;
-        if !self.should_flush() {
+    async fn get_cursor(&mut self) -> Result<Option<Cursor>, Self::Error> {
+        let Some(content) = self.storage.get(&Path::from(CURSOR_FILE_NAME)).await? else {
             return Ok(None);
-        }
+        };
 
-        debug!("flushing batch");
-        let batch_with_filename = (*decoder)
-            .flush()
-            .change_context(SinkParquetError)?
-            .map(|batch| (batch, self.get_filename()));
+        let cursor = serde_json::from_slice(&content)
+            .change_context(SinkParquetError)
+            .attach_printable("failed to deserialize cursor")?;
 
-        self.starting_block_number = self.end_block_number;
-        Ok(batch_with_filename)
+        Ok(Some(cursor))
     }
 
-    fn should_flush(&self) -> bool {
-        let num_blocks = self.end_block_number - self.starting_block_number;
-        num_blocks >= self.batch_size as u64
+    #[instrument(skip(self), err(Debug))]
+    async fn handle_heartbeat(&mut self) -> Result<(), Self::Error> {
+        // The stream is idle, write the data received so far.
+        self.write_partial().await
     }
 
-    fn get_filename(&self) -> String {
-        format!(
-            "{:0>10}_{:0>10}.parquet",
-            self.starting_block_number, self.end_block_number
-        )
+    async fn cleanup(&mut self) -> Result<(), Self::Error> {
+        self.write_partial().await
     }
 }
@@ -0,0 +1,274 @@
+use apibara_core::node::v1alpha2::Cursor;
+use apibara_sink_common::SinkConnectorError;
+use arrow::compute::concat_batches;
+use arrow::datatypes::SchemaRef;
+use arrow::json::reader::{Decoder, ReaderBuilder};
+use arrow::record_batch::RecordBatch;
+use error_stack::{Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::schema::{convert_batch, decoding_schema};
+use crate::sink::SinkParquetError;
+
+/// Extension of files containing an incomplete batch.
+pub const PARTIAL_FILE_EXTENSION: &str = "partial";
+
+/// A batch of data ready to be written to a file.
+pub struct BatchFile {
+    pub filename: String,
+    pub batch: RecordBatch,
+    pub block_rows: Vec<BlockRows>,
+}
+
+/// Number of rows written for a block range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockRows {
+    /// The first block (inclusive) in the range.
+    pub start: u64,
+    /// The last block (exclusive) in the range.
+    pub end: u64,
+    pub rows: usize,
+}
+
+/// Dataset state.
+///
+/// This is used to keep track of the file schema, starting and end blocks.
+pub struct State {
+    /// JSON to arrow data decoder.
+    /// Notice that [Decoder] is not `Sync` so we need to wrap it in a mutex.
+    pub decoder: Mutex<Decoder>,
+    /// The schema of the data in the current batch.
+    pub schema: SchemaRef,
+    /// Data in the current batch that was already flushed from the decoder.
+    pub partial: Vec<RecordBatch>,
+    /// Number of rows of each block range in the current batch.
+    ///
+    /// Used to remove the rows of invalidated blocks.
+    pub block_rows: Vec<BlockRows>,
+    /// Whether the current batch contains data that was not written to any file.
+    pub has_unwritten_data: bool,
+    /// How many blocks to include in a single parquet file.
+    pub batch_size: usize,
+    /// The first block (inclusive) in the current batch.
+    pub starting_block_number: u64,
+    /// The cursor of the last block (exclusive) in the current batch.
+    pub end_cursor: Cursor,
+    /// The partition directory of the current batch, if the dataset is partitioned.
+    pub partition: Option<String>,
+}
+
+impl State {
+    /// Initialize state for the first batch of data.
+    pub fn new_from_batch(
+        batch_size: usize,
+        schema: SchemaRef,
+        partition: Option<String>,
+        cursor: &Option<Cursor>,
+        end_cursor: &Cursor,
+    ) -> Result<Self, SinkParquetError> {
+        let starting_block_number = cursor.as_ref().map(|c| c.order_key).unwrap_or(0);
+
+        State::new(
+            batch_size,
+            schema,
+            partition,
+            starting_block_number,
+            end_cursor.clone(),
+            Vec::new(),
+            Vec::new(),
+        )
+    }
+
+    /// Initialize state with the given schema and data already in the batch.
+    pub fn new(
+        batch_size: usize,
+        schema: SchemaRef,
+        partition: Option<String>,
+        starting_block_number: u64,
+        end_cursor: Cursor,
+        partial: Vec<RecordBatch>,
+        block_rows: Vec<BlockRows>,
+    ) -> Result<Self, SinkParquetError> {
+        let decoder = ReaderBuilder::new(decoding_schema(&schema))
+            .with_coerce_primitive(true)
+            .build_decoder()
+            .change_context(SinkParquetError)
+            .attach_printable("failed to create reader")?;
+
+        Ok(State {
+            decoder: Mutex::new(decoder),
+            schema,
+            partial,
+            block_rows,
+            has_unwritten_data: false,
+            starting_block_number,
+            batch_size,
+            end_cursor,
+            partition,
+        })
+    }
+
+    /// Adds the rows of a message to the current batch.
+    ///
+    /// The batch is extended to the end cursor even if there are no rows, so that the
+    /// batches of all datasets cover the same blocks.
+    pub async fn handle_batch(
+        &mut self,
+        cursor: &Option<Cursor>,
+        end_cursor: &Cursor,
+        batch: &[Value],
+    ) -> Result<Option<BatchFile>, SinkParquetError> {
+        debug!(size = batch.len(), "handling batch");
+        if !batch.is_empty() {
+            self.decoder
+                .lock()
+                .await
+                .serialize(batch)
+                .change_context(SinkParquetError)
+                .attach_printable("failed to serialize batch data")?;
+
+            self.block_rows.push(BlockRows {
+                start: cursor.as_ref().map(|c| c.order_key).unwrap_or(0),
+                end: end_cursor.order_key,
+                rows: batch.len(),
+            });
+        }
+
+        self.end_cursor = end_cursor.clone();
+        self.has_unwritten_data = true;
+        if !self.should_flush() {
+            return Ok(None);
+        }
+
+        debug!("flushing batch");
+        let file = self.take_file().await?;
+        Ok(Some(file))
+    }
+
+    /// Returns all data in the current batch, then starts a new batch at the end cursor.
+    pub async fn take_file(&mut self) -> Result<BatchFile, SinkParquetError> {
+        self.flush_decoder().await?;
+
+        let file = BatchFile {
+            filename: self.get_filename(),
+            batch: concat_batches(&self.schema, &self.partial)
+                .change_context(SinkParquetError)
+                .attach_printable("failed to concatenate batches")?,
+            block_rows: std::mem::take(&mut self.block_rows),
+        };
+
+        self.partial.clear();
+        self.has_unwritten_data = false;
+        self.starting_block_number = self.end_cursor.order_key;
+        Ok(file)
+    }
+
+    /// Flushes the data in the current batch, returning it together with the name of the
+    /// partial file.
+    ///
+    /// Returns `None` if all data was already written or the batch is empty.
+    pub async fn flush_partial(&mut self) -> Result<Option<BatchFile>, SinkParquetError> {
+        if !self.has_unwritten_data {
+            return Ok(None);
+        }
+
+        if self.block_rows.is_empty() {
+            self.has_unwritten_data = false;
+            return Ok(None);
+        }
+
+        debug!("flushing partial batch");
+        self.flush_decoder().await?;
+        self.has_unwritten_data = false;
+
+        Ok(Some(BatchFile {
+            filename: self.get_partial_filename(),
+            batch: concat_batches(&self.schema, &self.partial)
+                .change_context(SinkParquetError)
+                .attach_printable("failed to concatenate batches")?,
+            block_rows: self.block_rows.clone(),
+        }))
+    }
+
+    /// Removes the data after the given cursor from the batch.
+    pub async fn truncate(&mut self, cursor: &Cursor) -> Result<(), SinkParquetError> {
+        self.flush_decoder().await?;
+
+        // Rows are not associated with a block, so ranges that contain the cursor can't be split.
+        if let Some(range) = self
+            .block_rows
+            .iter()
+            .find(|range| range.start < cursor.order_key && cursor.order_key < range.end)
+        {
+            // Retrying won't split the range, so the indexer must stop.
+            return Err(SinkParquetError)
+                .attach_printable_lazy(|| {
+                    format!(
+                        "cannot invalidate data in the middle of block range {}-{}",
+                        range.start, range.end
+                    )
+                })
+                .attach(SinkConnectorError::Fatal);
+        }
+
+        self.block_rows
+            .retain(|range| range.end <= cursor.order_key);
+        let num_rows = self.block_rows.iter().map(|range| range.rows).sum();
+
+        let batch = concat_batches(&self.schema, &self.partial)
+            .change_context(SinkParquetError)
+            .attach_printable("failed to concatenate batches")?;
+        debug!(
+            rows = batch.num_rows(),
+            remaining = num_rows,
+            "truncating batch"
+        );
+        self.partial = vec![batch.slice(0, num_rows)];
+        self.end_cursor = cursor.clone();
+        // Write the truncated batch, replacing any partial file with invalidated data.
+        self.has_unwritten_data = true;
+
+        Ok(())
+    }
+
+    /// Moves the data in the decoder to the partial batches.
+    async fn flush_decoder(&mut self) -> Result<(), SinkParquetError> {
+        let batch = self
+            .decoder
+            .lock()
+            .await
+            .flush()
+            .change_context(SinkParquetError)
+            .attach_printable("failed to flush decoder")?;
+
+        if let Some(batch) = batch {
+            self.partial.push(convert_batch(batch, &self.schema)?);
+        }
+
+        Ok(())
+    }
+
+    fn should_flush(&self) -> bool {
+        let num_blocks = self.end_cursor.order_key - self.starting_block_number;
+        num_blocks >= self.batch_size as u64
+    }
+
+    /// Returns the path of the batch file, relative to the dataset directory.
+    fn get_filename(&self) -> String {
+        let filename = format!(
+            "{:0>10}_{:0>10}.parquet",
+            self.starting_block_number, self.end_cursor.order_key
+        );
+        match &self.partition {
+            None => filename,
+            Some(partition) => format!("{partition}/{filename}"),
+        }
+    }
+
+    pub fn get_partial_filename(&self) -> String {
+        format!("{}.{}", self.get_filename(), PARTIAL_FILE_EXTENSION)
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use error_stack::{Result, ResultExt};
+use futures::TryStreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+
+use crate::configuration::StorageConfiguration;
+use crate::sink::SinkParquetError;
+
+/// Stores the sink files, either in a local directory or in an object storage.
+///
+/// Objects are written atomically: readers never see a partially written file.
+pub struct Storage {
+    store: Arc<dyn ObjectStore>,
+    /// Prefix of all objects written by the sink.
+    prefix: Path,
+}
+
+impl Storage {
+    pub fn new(config: &StorageConfiguration) -> Result<Self, SinkParquetError> {
+        match config {
+            StorageConfiguration::Local(dir) => {
+                fs::create_dir_all(dir)
+                    .change_context(SinkParquetError)
+                    .attach_printable_lazy(|| format!("failed to create directory {dir:?}"))?;
+                let store = LocalFileSystem::new_with_prefix(dir)
+                    .change_context(SinkParquetError)
+                    .attach_printable_lazy(|| format!("failed to open directory {dir:?}"))?;
+
+                Ok(Storage {
+                    store: Arc::new(store),
+                    prefix: Path::default(),
+                })
+            }
+            StorageConfiguration::S3(s3) => {
+                // Start from the standard AWS environment variables, then apply the options.
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(&s3.bucket);
+                if let Some(region) = &s3.region {
+                    builder = builder.with_region(region);
+                }
+                if let Some(endpoint) = &s3.endpoint {
+                    builder = builder.with_endpoint(endpoint);
+                }
+                if let Some(access_key_id) = &s3.access_key_id {
+                    builder = builder.with_access_key_id(access_key_id);
+                }
+                if let Some(secret_access_key) = &s3.secret_access_key {
+                    builder = builder.with_secret_access_key(secret_access_key);
+                }
+                if let Some(allow_http) = s3.allow_http {
+                    builder = builder.with_allow_http(allow_http);
+                }
+
+                let store = builder
+                    .build()
+                    .change_context(SinkParquetError)
+                    .attach_printable("failed to create s3 client")?;
+
+                Ok(Storage {
+                    store: Arc::new(store),
+                    prefix: Path::from(s3.prefix.as_str()),
+                })
+            }
+        }
+    }
+
+    /// Writes an object, replacing it if it already exists.
+    pub async fn put(&self, path: &Path, data: Vec<u8>) -> Result<(), SinkParquetError> {
+        self.store
+            .put(&self.full_path(path), Bytes::from(data))
+            .await
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("failed to write {path}"))
+    }
+
+    /// Reads an object, returning `None` if it doesn't exist.
+    pub async fn get(&self, path: &Path) -> Result<Option<Bytes>, SinkParquetError> {
+        let result = match self.store.get(&self.full_path(path)).await {
+            Ok(result) => result,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .change_context(SinkParquetError)
+                    .attach_printable_lazy(|| format!("failed to read {path}"));
+            }
+        };
+
+        let data = result
+            .bytes()
+            .await
+            .change_context(SinkParquetError)
+            .attach_printable_lazy(|| format!("failed to read {path}"))?;
+        Ok(Some(data))
+    }
+
+    /// Returns the path of all objects under the given prefix, at any depth.
+    pub async fn list(&self, prefix: &Path) -> Result<Vec<Path>, SinkParquetError> {
+        let full_prefix = self.full_path(prefix);
+        let objects = match self.store.list(Some(&full_prefix)).await {
+            Ok(objects) => objects.try_collect::<Vec<_>>().await,
+            Err(err) => Err(err),
+        };
+
+        let objects = match objects {
+            Ok(objects) => objects,
+            Err(object_store::Error::NotFound { .. }) => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err)
+                    .change_context(SinkParquetError)
+                    .attach_printable_lazy(|| format!("failed to list objects in {prefix}"));
+            }
+        };
+
+        let paths = objects
+            .into_iter()
+            .filter_map(|object| {
+                object
+                    .location
+                    .prefix_match(&self.prefix)
+                    .map(Path::from_iter)
+            })
+            .collect();
+        Ok(paths)
+    }
+
+    /// Removes an object. Removing an object that doesn't exist is not an error.
+    pub async fn delete(&self, path: &Path) -> Result<(), SinkParquetError> {
+        match self.store.delete(&self.full_path(path)).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err)
+                .change_context(SinkParquetError)
+                .attach_printable_lazy(|| format!("failed to remove {path}")),
+        }
+    }
+
+    fn full_path(&self, path: &Path) -> Path {
+        Path::from_iter(self.prefix.parts().chain(path.parts()))
+    }
+}
@@ -1,17 +1,29 @@
-use std::{ffi::OsString, sync::Arc};
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    sync::Arc,
+};
 
 use apibara_core::node::v1alpha2::{Cursor, DataFinality};
-use apibara_sink_common::{Context, CursorAction, Sink};
-use apibara_sink_parquet::{ParquetSink, SinkParquetConfiguration, SinkParquetError};
+use apibara_sink_common::{is_fatal_error, Context, CursorAction, Sink};
+use apibara_sink_parquet::{
+    DatasetOptions, FieldOptions, ParquetSink, SinkParquetConfiguration, SinkParquetError,
+    SinkParquetOptions, StorageConfiguration,
+};
 use arrow::{
-    array::{ArrayRef, Int64Array, StringArray},
+    array::{Array, ArrayRef, Decimal256Array, FixedSizeBinaryArray, Int64Array, StringArray},
+    datatypes::{i256, DataType},
     record_batch::RecordBatch,
 };
 use error_stack::Result;
+use futures::TryStreamExt;
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore};
 use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::basic::Compression;
 use serde_json::{json, Value};
 use std::fs::File;
 use tempdir::TempDir;
+use testcontainers::{clients, core::WaitFor, GenericImage, RunnableImage};
 
 fn read_parquet(output_dir: &TempDir, file_name: &str) -> RecordBatch {
     let file = File::open(output_dir.path().join(file_name)).unwrap();
@@ -25,11 +37,12 @@ fn new_sink(batch_size: usize) -> (TempDir, ParquetSink) {
     let output_dir = TempDir::new("sink_parquet_test").unwrap();
 
     let config = SinkParquetConfiguration {
-        output_dir: output_dir.path().to_path_buf(),
+        storage: StorageConfiguration::Local(output_dir.path().to_path_buf()),
         batch_size,
+        ..SinkParquetConfiguration::default()
     };
 
-    (output_dir, ParquetSink::new(config))
+    (output_dir, ParquetSink::new(config).unwrap())
 }
 
 fn new_batch(start_cursor: &Option<Cursor>, end_cursor: &Cursor) -> Value {
@@ -86,11 +99,19 @@ fn new_cursor(order_key: u64) -> Cursor {
     }
 }
 
-fn get_file_names(output_dir: &TempDir) -> Vec<OsString> {
-    std::fs::read_dir(output_dir)
+fn get_file_names(output_dir: impl AsRef<Path>) -> Vec<OsString> {
+    get_file_names_with_extension(output_dir, "parquet")
+}
+
+fn get_file_names_with_extension(output_dir: impl AsRef<Path>, extension: &str) -> Vec<OsString> {
+    let mut file_names: Vec<OsString> = std::fs::read_dir(output_dir)
         .unwrap()
-        .map(|entry| entry.unwrap().file_name())
-        .collect()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new(extension)))
+        .map(|path| path.file_name().unwrap().to_owned())
+        .collect();
+    file_names.sort();
+    file_names
 }
 
 #[tokio::test]
@@ -164,3 +185,702 @@ async fn test_handle_data() -> Result<(), SinkParquetError> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[ignore]
+async fn test_write_partial_file_on_cleanup() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    let finality = DataFinality::DataStatusFinalized;
+
+    let cursor = None;
+    let end_cursor = new_cursor(5);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality,
+    };
+
+    let action = sink.handle_data(&ctx, &batch).await?;
+    assert_eq!(action, CursorAction::Skip);
+
+    sink.cleanup().await?;
+
+    assert_eq!(get_file_names(&output_dir).len(), 0);
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial"),
+        vec!["0000000000_0000000005.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(5)));
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000005.parquet.partial");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(5));
+    assert_eq!(record_batch, expected_record_batch);
+
+    // Restart the sink from the stored cursor.
+    let config = SinkParquetConfiguration {
+        storage: StorageConfiguration::Local(output_dir.path().to_path_buf()),
+        batch_size: parquet_batch_size,
+        ..SinkParquetConfiguration::default()
+    };
+    let mut sink = ParquetSink::new(config)?;
+    let cursor = sink.get_cursor().await?;
+    assert_eq!(cursor, Some(new_cursor(5)));
+
+    let end_cursor = new_cursor(10);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality,
+    };
+
+    let action = sink.handle_data(&ctx, &batch).await?;
+    assert_eq!(action, CursorAction::Persist);
+
+    // The partial file is replaced by the complete file.
+    assert_eq!(
+        get_file_names(&output_dir),
+        vec!["0000000000_0000000010.parquet"]
+    );
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial").len(),
+        0
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(10)));
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000010.parquet");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(10));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_write_partial_file_on_heartbeat() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    let finality = DataFinality::DataStatusFinalized;
+
+    // No data received yet.
+    sink.handle_heartbeat().await?;
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial").len(),
+        0
+    );
+
+    for (start, end) in [(0, 3), (3, 6)] {
+        let cursor = Some(new_cursor(start));
+        let end_cursor = new_cursor(end);
+        let batch = new_batch(&cursor, &end_cursor);
+        let ctx = Context {
+            cursor,
+            end_cursor,
+            finality,
+        };
+
+        let action = sink.handle_data(&ctx, &batch).await?;
+        assert_eq!(action, CursorAction::Skip);
+
+        sink.handle_heartbeat().await?;
+    }
+
+    // Only the latest partial file is kept.
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial"),
+        vec!["0000000000_0000000006.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(6)));
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000006.parquet.partial");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(6));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_use_persistence_cursor_without_cursor_file() -> Result<(), SinkParquetError> {
+    let output_dir = TempDir::new("sink_parquet_test").unwrap();
+    let new_options = || SinkParquetOptions {
+        output_dir: Some(output_dir.path().to_string_lossy().to_string()),
+        batch_size: Some(10),
+        ..SinkParquetOptions::default()
+    };
+
+    // Without the cursor file, the connector resumes from the persistence cursor.
+    let mut sink = ParquetSink::from_options(new_options()).await?;
+    assert!(!sink.is_transactional());
+    assert_eq!(sink.get_cursor().await?, None);
+
+    handle_blocks(&mut sink, 0, 10, DataFinality::DataStatusFinalized).await?;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(10)));
+
+    // Once the cursor file is written, the sink resumes from it.
+    let mut sink = ParquetSink::from_options(new_options()).await?;
+    assert!(sink.is_transactional());
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(10)));
+
+    Ok(())
+}
+
+/// Sends one message for each block in the range.
+async fn handle_blocks(
+    sink: &mut ParquetSink,
+    start: u64,
+    end: u64,
+    finality: DataFinality,
+) -> Result<(), SinkParquetError> {
+    for order_key in start..end {
+        let cursor = Some(new_cursor(order_key));
+        let end_cursor = new_cursor(order_key + 1);
+        let batch = new_batch(&cursor, &end_cursor);
+        let ctx = Context {
+            cursor,
+            end_cursor,
+            finality,
+        };
+        sink.handle_data(&ctx, &batch).await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_skip_pending_data() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    handle_blocks(&mut sink, 0, 5, DataFinality::DataStatusAccepted).await?;
+
+    let cursor = Some(new_cursor(5));
+    let end_cursor = new_cursor(6);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality: DataFinality::DataStatusPending,
+    };
+
+    let action = sink.handle_data(&ctx, &batch).await?;
+    assert_eq!(action, CursorAction::Skip);
+
+    sink.cleanup().await?;
+
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial"),
+        vec!["0000000000_0000000005.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(5)));
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000005.parquet.partial");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(5));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_invalidate_current_batch() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    let finality = DataFinality::DataStatusAccepted;
+
+    handle_blocks(&mut sink, 0, 8, finality).await?;
+    sink.handle_heartbeat().await?;
+
+    sink.handle_invalidate(&Some(new_cursor(6))).await?;
+
+    // The partial file is replaced with one without the invalidated data.
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial"),
+        vec!["0000000000_0000000006.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(6)));
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000006.parquet.partial");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(6));
+    assert_eq!(record_batch, expected_record_batch);
+
+    handle_blocks(&mut sink, 6, 10, finality).await?;
+
+    assert_eq!(
+        get_file_names(&output_dir),
+        vec!["0000000000_0000000010.parquet"]
+    );
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial").len(),
+        0
+    );
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000010.parquet");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(10));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_invalidate_written_file() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    let finality = DataFinality::DataStatusAccepted;
+
+    handle_blocks(&mut sink, 0, 22, finality).await?;
+    assert_eq!(
+        get_file_names(&output_dir),
+        vec![
+            "0000000000_0000000010.parquet",
+            "0000000010_0000000020.parquet"
+        ]
+    );
+
+    sink.handle_invalidate(&Some(new_cursor(8))).await?;
+
+    // Files with invalidated data are removed, the file that contains the cursor
+    // becomes a partial file.
+    assert_eq!(get_file_names(&output_dir).len(), 0);
+    assert_eq!(
+        get_file_names_with_extension(&output_dir, "partial"),
+        vec!["0000000000_0000000008.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(8)));
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000008.parquet.partial");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(8));
+    assert_eq!(record_batch, expected_record_batch);
+
+    handle_blocks(&mut sink, 8, 10, finality).await?;
+
+    assert_eq!(
+        get_file_names(&output_dir),
+        vec!["0000000000_0000000010.parquet"]
+    );
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000010.parquet");
+    let expected_record_batch = new_record_batch(&None, &new_cursor(10));
+    assert_eq!(record_batch, expected_record_batch);
+
+    // Invalidate all data.
+    sink.handle_invalidate(&None).await?;
+
+    assert_eq!(get_file_names(&output_dir).len(), 0);
+    assert_eq!(sink.get_cursor().await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_invalidate_in_block_range() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (_output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    let cursor = None;
+    let end_cursor = new_cursor(5);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality: DataFinality::DataStatusAccepted,
+    };
+    sink.handle_data(&ctx, &batch).await?;
+
+    // Rows of the same message can't be split, retrying doesn't help.
+    let err = sink
+        .handle_invalidate(&Some(new_cursor(3)))
+        .await
+        .unwrap_err();
+    assert!(is_fatal_error(&err));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_schema() -> Result<(), SinkParquetError> {
+    let output_dir = TempDir::new("sink_parquet_test").unwrap();
+
+    let options = SinkParquetOptions {
+        output_dir: Some(output_dir.path().to_str().unwrap().to_string()),
+        schema: Some(vec![
+            FieldOptions {
+                name: "block_num".into(),
+                r#type: "int64".into(),
+                nullable: Some(false),
+            },
+            FieldOptions {
+                name: "address".into(),
+                r#type: "felt".into(),
+                ..FieldOptions::default()
+            },
+            FieldOptions {
+                name: "amount".into(),
+                r#type: "decimal256(76, 0)".into(),
+                ..FieldOptions::default()
+            },
+        ]),
+        ..SinkParquetOptions::default()
+    };
+    let mut config = options.to_parquet_configuration()?;
+    config.batch_size = 10;
+    let mut sink = ParquetSink::new(config)?;
+
+    let finality = DataFinality::DataStatusFinalized;
+    for order_key in 0..10 {
+        // Fields not in the schema are ignored.
+        let batch = json!([{
+            "block_num": order_key,
+            "address": format!("0x{order_key:x}"),
+            "amount": if order_key % 2 == 0 { json!("0x10") } else { json!(16) },
+            "ignored": "value",
+        }]);
+        let ctx = Context {
+            cursor: Some(new_cursor(order_key)),
+            end_cursor: new_cursor(order_key + 1),
+            finality,
+        };
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000010.parquet");
+    let schema = record_batch.schema();
+    assert_eq!(schema.fields().len(), 3);
+    assert_eq!(
+        schema.field_with_name("address").unwrap().data_type(),
+        &DataType::FixedSizeBinary(32)
+    );
+    assert_eq!(
+        schema.field_with_name("amount").unwrap().data_type(),
+        &DataType::Decimal256(76, 0)
+    );
+
+    let addresses = record_batch
+        .column_by_name("address")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<FixedSizeBinaryArray>()
+        .unwrap();
+    let mut expected_address = [0u8; 32];
+    expected_address[31] = 9;
+    assert_eq!(addresses.value(9), expected_address);
+
+    let amounts = record_batch
+        .column_by_name("amount")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Decimal256Array>()
+        .unwrap();
+    assert_eq!(amounts.len(), 10);
+    assert!(amounts
+        .iter()
+        .all(|amount| amount == Some(i256::from_i128(16))));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_schema_change() -> Result<(), SinkParquetError> {
+    let parquet_batch_size = 10;
+    let (output_dir, mut sink) = new_sink(parquet_batch_size);
+
+    let finality = DataFinality::DataStatusFinalized;
+    for order_key in 0..15 {
+        let batch = if order_key < 5 {
+            json!([{ "block_num": order_key }])
+        } else {
+            json!([{ "block_num": order_key, "block_str": format!("block_{}", order_key) }])
+        };
+        let ctx = Context {
+            cursor: Some(new_cursor(order_key)),
+            end_cursor: new_cursor(order_key + 1),
+            finality,
+        };
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    // A new file is started when the schema changes.
+    assert_eq!(
+        get_file_names(&output_dir),
+        vec![
+            "0000000000_0000000005.parquet",
+            "0000000005_0000000015.parquet"
+        ]
+    );
+
+    let record_batch = read_parquet(&output_dir, "0000000000_0000000005.parquet");
+    assert_eq!(record_batch.schema().fields().len(), 1);
+    assert_eq!(record_batch.num_rows(), 5);
+
+    let record_batch = read_parquet(&output_dir, "0000000005_0000000015.parquet");
+    let expected_record_batch = new_record_batch(&Some(new_cursor(5)), &new_cursor(15));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_datasets() -> Result<(), SinkParquetError> {
+    let output_dir = TempDir::new("sink_parquet_test").unwrap();
+
+    let options = SinkParquetOptions {
+        output_dir: Some(output_dir.path().to_str().unwrap().to_string()),
+        datasets: Some(vec![
+            DatasetOptions {
+                name: "transfers".into(),
+                schema: Some(vec![FieldOptions {
+                    name: "block_num".into(),
+                    r#type: "int64".into(),
+                    ..FieldOptions::default()
+                }]),
+            },
+            DatasetOptions {
+                name: "swaps".into(),
+                ..DatasetOptions::default()
+            },
+        ]),
+        ..SinkParquetOptions::default()
+    };
+    let mut config = options.to_parquet_configuration()?;
+    config.batch_size = 10;
+    let mut sink = ParquetSink::new(config)?;
+
+    let finality = DataFinality::DataStatusFinalized;
+    for order_key in 0..12 {
+        let mut batch = vec![json!({
+            "dataset": "transfers",
+            "data": { "block_num": order_key },
+        })];
+        if [1, 2, 11].contains(&order_key) {
+            batch.push(json!({
+                "dataset": "swaps",
+                "data": { "block_num": order_key, "pool": format!("pool_{order_key}") },
+            }));
+        }
+        let ctx = Context {
+            cursor: Some(new_cursor(order_key)),
+            end_cursor: new_cursor(order_key + 1),
+            finality,
+        };
+        sink.handle_data(&ctx, &json!(batch)).await?;
+    }
+
+    // Each dataset rotates its files independently, starting from its first row.
+    let transfers_dir = output_dir.path().join("transfers");
+    let swaps_dir = output_dir.path().join("swaps");
+    assert_eq!(
+        get_file_names(&transfers_dir),
+        vec!["0000000000_0000000010.parquet"]
+    );
+    assert_eq!(
+        get_file_names(&swaps_dir),
+        vec!["0000000001_0000000011.parquet"]
+    );
+
+    let record_batch = read_parquet(&output_dir, "transfers/0000000000_0000000010.parquet");
+    assert_eq!(record_batch.schema().fields().len(), 1);
+    assert_eq!(record_batch.num_rows(), 10);
+
+    let record_batch = read_parquet(&output_dir, "swaps/0000000001_0000000011.parquet");
+    assert_eq!(record_batch.schema().fields().len(), 2);
+    assert_eq!(record_batch.num_rows(), 2);
+
+    // The cursor is shared by all datasets, so the data of the other datasets up to the
+    // cursor is written to partial files.
+    assert_eq!(
+        get_file_names_with_extension(&transfers_dir, "partial"),
+        vec!["0000000010_0000000011.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(11)));
+
+    sink.cleanup().await?;
+
+    assert_eq!(
+        get_file_names_with_extension(&transfers_dir, "partial"),
+        vec!["0000000010_0000000012.parquet.partial"]
+    );
+    assert_eq!(
+        get_file_names_with_extension(&swaps_dir, "partial"),
+        vec!["0000000011_0000000012.parquet.partial"]
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(12)));
+
+    // Items must target one of the datasets.
+    let ctx = Context {
+        cursor: Some(new_cursor(12)),
+        end_cursor: new_cursor(13),
+        finality,
+    };
+    let batch = json!([{ "dataset": "mints", "data": { "block_num": 12 } }]);
+    assert!(sink.handle_data(&ctx, &batch).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_partitions() -> Result<(), SinkParquetError> {
+    let output_dir = TempDir::new("sink_parquet_test").unwrap();
+
+    let options = SinkParquetOptions {
+        output_dir: Some(output_dir.path().to_str().unwrap().to_string()),
+        partition_by: Some("block_range(15)".into()),
+        compression: Some("zstd(3)".into()),
+        ..SinkParquetOptions::default()
+    };
+    let mut config = options.to_parquet_configuration()?;
+    config.batch_size = 10;
+    let mut sink = ParquetSink::new(config)?;
+
+    let finality = DataFinality::DataStatusAccepted;
+    handle_blocks(&mut sink, 0, 20, finality).await?;
+
+    // Files don't cross partition boundaries.
+    let first_partition = output_dir.path().join("block_range=0");
+    let second_partition = output_dir.path().join("block_range=15");
+    assert_eq!(
+        get_file_names(&first_partition),
+        vec![
+            "0000000000_0000000010.parquet",
+            "0000000010_0000000015.parquet"
+        ]
+    );
+    assert_eq!(get_file_names(&second_partition).len(), 0);
+
+    let file = File::open(first_partition.join("0000000000_0000000010.parquet")).unwrap();
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+    let compression = builder.metadata().row_group(0).column(0).compression();
+    assert!(matches!(compression, Compression::ZSTD(_)));
+
+    sink.cleanup().await?;
+    assert_eq!(
+        get_file_names_with_extension(&second_partition, "partial"),
+        vec!["0000000015_0000000020.parquet.partial"]
+    );
+
+    // The file that contains the cursor stays in its partition.
+    sink.handle_invalidate(&Some(new_cursor(12))).await?;
+    assert_eq!(
+        get_file_names(&first_partition),
+        vec!["0000000000_0000000010.parquet"]
+    );
+    assert_eq!(
+        get_file_names_with_extension(&first_partition, "partial"),
+        vec!["0000000010_0000000012.parquet.partial"]
+    );
+    assert_eq!(
+        get_file_names_with_extension(&second_partition, "partial").len(),
+        0
+    );
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(12)));
+
+    let record_batch = read_parquet(
+        &output_dir,
+        "block_range=0/0000000010_0000000012.parquet.partial",
+    );
+    let expected_record_batch = new_record_batch(&Some(new_cursor(10)), &new_cursor(12));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
+
+/// A MinIO server with an `apibara` bucket.
+fn new_minio_image() -> RunnableImage<GenericImage> {
+    // Older releases create a bucket for each directory in the data directory.
+    let image = GenericImage::new("minio/minio", "RELEASE.2022-10-08T20-11-00Z")
+        .with_entrypoint("sh")
+        .with_wait_for(WaitFor::message_on_stdout("API:"));
+    let args = vec![
+        "-c".to_string(),
+        "mkdir -p /data/apibara && minio server /data".to_string(),
+    ];
+    RunnableImage::from((image, args))
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_write_to_s3() -> Result<(), SinkParquetError> {
+    let docker = clients::Cli::default();
+    let minio = docker.run(new_minio_image());
+    let port = minio.get_host_port_ipv4(9000);
+    let endpoint = format!("http://localhost:{port}");
+
+    let new_s3_sink = || {
+        let options = SinkParquetOptions {
+            output_dir: Some("s3://apibara/indexer".into()),
+            s3_endpoint: Some(endpoint.clone()),
+            s3_region: Some("us-east-1".into()),
+            s3_access_key_id: Some("minioadmin".into()),
+            s3_secret_access_key: Some("minioadmin".into()),
+            s3_allow_http: Some(true),
+            ..SinkParquetOptions::default()
+        };
+        let mut config = options.to_parquet_configuration()?;
+        config.batch_size = 10;
+        ParquetSink::new(config)
+    };
+
+    let mut sink = new_s3_sink()?;
+    handle_blocks(&mut sink, 0, 15, DataFinality::DataStatusAccepted).await?;
+    sink.cleanup().await?;
+
+    // Restart the sink and complete the partial file.
+    let mut sink = new_s3_sink()?;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(15)));
+    handle_blocks(&mut sink, 15, 20, DataFinality::DataStatusAccepted).await?;
+
+    let store = AmazonS3Builder::new()
+        .with_bucket_name("apibara")
+        .with_endpoint(&endpoint)
+        .with_region("us-east-1")
+        .with_access_key_id("minioadmin")
+        .with_secret_access_key("minioadmin")
+        .with_allow_http(true)
+        .build()
+        .unwrap();
+    let mut objects = store
+        .list(Some(&ObjectPath::from("indexer")))
+        .await
+        .unwrap()
+        .map_ok(|object| object.location.to_string())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    objects.sort();
+    assert_eq!(
+        objects,
+        vec![
+            "indexer/0000000000_0000000010.parquet",
+            "indexer/0000000010_0000000020.parquet",
+            "indexer/_apibara_cursor.json",
+        ]
+    );
+
+    let data = store
+        .get(&ObjectPath::from("indexer/0000000010_0000000020.parquet"))
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let mut reader = ParquetRecordBatchReaderBuilder::try_new(data)
+        .unwrap()
+        .build()
+        .unwrap();
+    let record_batch = reader.next().unwrap().unwrap();
+    let expected_record_batch = new_record_batch(&Some(new_cursor(10)), &new_cursor(20));
+    assert_eq!(record_batch, expected_record_batch);
+
+    Ok(())
+}
@@ -4,10 +4,13 @@ use apibara_sink_common::SinkOptions;
 use clap::Args;
 use error_stack::{Result, ResultExt};
 use serde::Deserialize;
+use serde_json::Value;
 use tokio_postgres::Config;
 
 use crate::sink::SinkPostgresError;
 
+const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
 #[derive(Debug)]
 pub enum TlsConfiguration {
     NoTls,
@@ -20,12 +23,55 @@ pub enum TlsConfiguration {
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableMode {
+    /// Rows are immutable and appended to the table.
+    Standard,
+    /// Rows are mutable entities, versioned by their `_cursor` range.
+    Entity,
+}
+
+#[derive(Debug)]
+pub struct TableConfiguration {
+    pub name: String,
+    pub mode: TableMode,
+    /// Columns declared in the configuration, used when managing the table schema.
+    pub columns: Vec<ColumnOptions>,
+}
+
 #[derive(Debug)]
 pub struct SinkPostgresConfiguration {
     pub pg: Config,
-    pub table_name: String,
+    pub tables: Vec<TableConfiguration>,
+    /// If true, each item returned by the transform step specifies its target table.
+    pub multi_table: bool,
+    /// If true, the sink creates the tables and adds missing columns.
+    pub manage_schema: bool,
     pub tls: TlsConfiguration,
     pub invalidate: Vec<InvalidateColumn>,
+    /// Identifier of the cursor row, only set in transactional mode.
+    pub cursor_id: Option<String>,
+    pub ingestion: IngestionConfiguration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IngestionConfiguration {
+    /// If true, rows are copied to a staging table before moving them to the target table.
+    pub copy: bool,
+    /// Format used to copy rows to the staging table.
+    pub copy_format: CopyFormat,
+    /// Maximum number of rows written with a single query.
+    pub chunk_size: usize,
+}
+
+/// Format of the data sent with `COPY ... FROM STDIN`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CopyFormat {
+    /// The PostgreSQL binary format.
+    #[default]
+    Binary,
+    /// CSV, with one quoted JSON value for each row.
+    Csv,
 }
 
 #[derive(Debug, Args, Default, SinkOptions)]
@@ -40,6 +86,22 @@ pub struct SinkPostgresOptions {
     /// transformation step.
     #[arg(long, env = "POSTGRES_TABLE_NAME")]
     pub table_name: Option<String>,
+    /// Store rows in the target table as mutable entities.
+    #[arg(long, env = "POSTGRES_ENTITY_MODE")]
+    pub entity_mode: Option<bool>,
+    /// Target tables, used to write to more than one table.
+    ///
+    /// Mutually exclusive with `table_name`.
+    #[clap(skip)]
+    pub tables: Option<Vec<TableOptions>>,
+    /// Create the target tables if they don't exist and add columns for new fields.
+    ///
+    /// Column types are taken from `columns`, or inferred from the data.
+    #[arg(long, env = "POSTGRES_MANAGE_SCHEMA")]
+    pub manage_schema: Option<bool>,
+    /// Columns of the target table, used when managing the table schema.
+    #[clap(skip)]
+    pub columns: Option<Vec<ColumnOptions>>,
     /// Disable TLS when connecting to the PostgreSQL server.
     #[arg(long, env = "POSTGRES_NO_TLS")]
     pub no_tls: Option<bool>,
@@ -61,14 +123,78 @@ pub struct SinkPostgresOptions {
     /// Additional conditions for the invalidate query.
     #[clap(skip)]
     pub invalidate: Option<Vec<InvalidateColumn>>,
+    /// Store the cursor in the `_apibara_cursors` table, in the same transaction as the data.
+    ///
+    /// When enabled, the starting cursor is read from the database instead of the
+    /// persistence backend.
+    #[arg(long, env = "POSTGRES_TRANSACTIONAL")]
+    pub transactional: Option<bool>,
+    /// Identifier of the cursor row in the `_apibara_cursors` table. Defaults to `default`.
+    ///
+    /// Indexers writing to the same database must use different identifiers.
+    #[arg(long, env = "POSTGRES_CURSOR_ID")]
+    pub cursor_id: Option<String>,
+    /// Insert rows with `COPY` through a staging table.
+    ///
+    /// Faster than the default insert query for large batches, for example when backfilling
+    /// historical data.
+    #[arg(long, env = "POSTGRES_COPY")]
+    pub copy: Option<bool>,
+    /// Format used to copy rows: `binary` or `csv`. Defaults to `binary`.
+    #[arg(long, env = "POSTGRES_COPY_FORMAT")]
+    pub copy_format: Option<String>,
+    /// Maximum number of rows written with a single query. Defaults to 10000.
+    #[arg(long, env = "POSTGRES_CHUNK_SIZE")]
+    pub chunk_size: Option<usize>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 pub struct InvalidateColumn {
     /// Column name.
     pub column: String,
-    /// Column value.
-    pub value: String,
+    /// Column value. Must be an array if the operator is `in` or `not in`.
+    pub value: Value,
+    /// Comparison operator. Defaults to `in` for arrays and `=` for other values.
+    pub operator: Option<InvalidateOperator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum InvalidateOperator {
+    #[serde(rename = "=")]
+    Eq,
+    #[serde(rename = "!=")]
+    NotEq,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    LtEq,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    GtEq,
+    #[serde(rename = "in")]
+    In,
+    #[serde(rename = "not in")]
+    NotIn,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableOptions {
+    /// Table name.
+    pub name: String,
+    /// Store rows as mutable entities.
+    pub entity_mode: Option<bool>,
+    /// Table columns, used when managing the table schema.
+    pub columns: Option<Vec<ColumnOptions>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ColumnOptions {
+    /// Column name.
+    pub name: String,
+    /// Column type, for example `bigint` or `text`.
+    pub r#type: String,
 }
 
 impl SinkOptions for SinkPostgresOptions {
@@ -76,6 +202,10 @@ impl SinkOptions for SinkPostgresOptions {
         Self {
             connection_string: self.connection_string.or(other.connection_string),
             table_name: self.table_name.or(other.table_name),
+            entity_mode: self.entity_mode.or(other.entity_mode),
+            tables: self.tables.or(other.tables),
+            manage_schema: self.manage_schema.or(other.manage_schema),
+            columns: self.columns.or(other.columns),
             no_tls: self.no_tls.or(other.no_tls),
             tls_certificate: self.tls_certificate.or(other.tls_certificate),
             tls_disable_system_roots: self
@@ -89,6 +219,11 @@ impl SinkOptions for SinkPostgresOptions {
                 .or(other.tls_accept_invalid_hostnames),
             tls_use_sni: self.tls_use_sni.or(other.tls_use_sni),
             invalidate: self.invalidate.or(other.invalidate),
+            transactional: self.transactional.or(other.transactional),
+            cursor_id: self.cursor_id.or(other.cursor_id),
+            copy: self.copy.or(other.copy),
+            copy_format: self.copy_format.or(other.copy_format),
+            chunk_size: self.chunk_size.or(other.chunk_size),
         }
     }
 }
@@ -102,10 +237,37 @@ impl SinkPostgresOptions {
         let pg = Config::from_str(&connection_string)
             .change_context(SinkPostgresError)
             .attach_printable("failed to build postgres config from connection string")?;
-        let table_name = self
-            .table_name
-            .ok_or(SinkPostgresError)
-            .attach_printable("missing table name")?;
+        let (tables, multi_table) = match (self.table_name, self.tables) {
+            (Some(_), Some(_)) => {
+                return Err(SinkPostgresError)
+                    .attach_printable("table name and tables are mutually exclusive");
+            }
+            (None, None) => {
+                return Err(SinkPostgresError).attach_printable("missing table name");
+            }
+            (Some(name), None) => {
+                let table = TableConfiguration {
+                    name,
+                    mode: TableMode::from_entity_mode(self.entity_mode),
+                    columns: self.columns.unwrap_or_default(),
+                };
+                (vec![table], false)
+            }
+            (None, Some(tables)) => {
+                if tables.is_empty() {
+                    return Err(SinkPostgresError).attach_printable("tables must not be empty");
+                }
+                let tables = tables
+                    .into_iter()
+                    .map(|table| TableConfiguration {
+                        name: table.name,
+                        mode: TableMode::from_entity_mode(table.entity_mode),
+                        columns: table.columns.unwrap_or_default(),
+                    })
+                    .collect();
+                (tables, true)
+            }
+        };
 
         let tls = if self.no_tls.unwrap_or(false) {
             TlsConfiguration::NoTls
@@ -120,12 +282,122 @@ impl SinkPostgresOptions {
         };
 
         let invalidate = self.invalidate.unwrap_or_default();
+        for column in &invalidate {
+            column.validate()?;
+        }
+
+        let cursor_id = if self.transactional.unwrap_or(false) {
+            Some(self.cursor_id.unwrap_or_else(|| "default".to_string()))
+        } else {
+            None
+        };
+
+        let chunk_size = self.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+        if chunk_size == 0 {
+            return Err(SinkPostgresError).attach_printable("chunk size must be greater than 0");
+        }
+        let copy_format = match self.copy_format.as_deref().map(str::trim) {
+            None | Some("binary") => CopyFormat::Binary,
+            Some("csv") => CopyFormat::Csv,
+            Some(format) => {
+                return Err(SinkPostgresError).attach_printable_lazy(|| {
+                    format!("unknown copy format {format}, expected binary or csv")
+                });
+            }
+        };
+        let ingestion = IngestionConfiguration {
+            copy: self.copy.unwrap_or(false),
+            copy_format,
+            chunk_size,
+        };
 
         Ok(SinkPostgresConfiguration {
             pg,
-            table_name,
+            tables,
+            multi_table,
+            manage_schema: self.manage_schema.unwrap_or(false),
             tls,
             invalidate,
+            cursor_id,
+            ingestion,
         })
     }
 }
+
+impl TableMode {
+    fn from_entity_mode(entity_mode: Option<bool>) -> Self {
+        if entity_mode.unwrap_or(false) {
+            TableMode::Entity
+        } else {
+            TableMode::Standard
+        }
+    }
+}
+
+impl InvalidateColumn {
+    /// Returns the operator used to compare the column with the value.
+    pub fn operator(&self) -> InvalidateOperator {
+        match (self.operator, &self.value) {
+            (Some(operator), _) => operator,
+            (None, Value::Array(_)) => InvalidateOperator::In,
+            (None, _) => InvalidateOperator::Eq,
+        }
+    }
+
+    fn validate(&self) -> Result<(), SinkPostgresError> {
+        let operator = self.operator();
+        match (operator, &self.value) {
+            (InvalidateOperator::In | InvalidateOperator::NotIn, Value::Array(values)) => {
+                if values.is_empty() {
+                    return Err(SinkPostgresError).attach_printable_lazy(|| {
+                        format!("invalidate value for column {} is empty", self.column)
+                    });
+                }
+            }
+            (InvalidateOperator::In | InvalidateOperator::NotIn, _) => {
+                return Err(SinkPostgresError).attach_printable_lazy(|| {
+                    format!(
+                        "invalidate value for column {} must be an array",
+                        self.column
+                    )
+                });
+            }
+            (_, Value::Array(_)) => {
+                return Err(SinkPostgresError).attach_printable_lazy(|| {
+                    format!(
+                        "invalidate operator for column {} must be in or not in",
+                        self.column
+                    )
+                });
+            }
+            (InvalidateOperator::Eq | InvalidateOperator::NotEq, Value::Null) => {}
+            (_, Value::Null) => {
+                return Err(SinkPostgresError).attach_printable_lazy(|| {
+                    format!(
+                        "invalidate value for column {} must not be null",
+                        self.column
+                    )
+                });
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl InvalidateOperator {
+    /// Returns the SQL operator.
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            InvalidateOperator::Eq => "=",
+            InvalidateOperator::NotEq => "<>",
+            InvalidateOperator::Lt => "<",
+            InvalidateOperator::LtEq => "<=",
+            InvalidateOperator::Gt => ">",
+            InvalidateOperator::GtEq => ">=",
+            InvalidateOperator::In => "IN",
+            InvalidateOperator::NotIn => "NOT IN",
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use deadpool_postgres::{Hook, Manager, ManagerConfig, Pool, RecyclingMethod};
+use error_stack::{Result, ResultExt};
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use tokio_postgres::{Config, NoTls};
+use tracing::info;
+
+use crate::configuration::TlsConfiguration;
+use crate::sink::SinkPostgresError;
+
+/// Maximum number of connections in the pool.
+///
+/// The sink writes one batch at a time, so it only needs one connection. The pool replaces it
+/// when it's closed.
+const POOL_MAX_SIZE: usize = 1;
+
+/// Creates new connections to the PostgreSQL server.
+///
+/// The TLS connector is built once, so that the pool can reconnect without reading the
+/// certificate again.
+pub enum Connector {
+    NoTls,
+    Tls(MakeTlsConnector),
+}
+
+impl Connector {
+    pub async fn new(tls: &TlsConfiguration) -> Result<Self, SinkPostgresError> {
+        match tls {
+            TlsConfiguration::NoTls => {
+                info!("Using insecure connection");
+                Ok(Connector::NoTls)
+            }
+            TlsConfiguration::Tls {
+                certificate,
+                accept_invalid_hostnames,
+                accept_invalid_certificates,
+                disable_system_roots,
+                use_sni,
+            } => {
+                info!("Configure TLS connection");
+                let mut builder = TlsConnector::builder();
+
+                if let Some(ref certificate) = certificate {
+                    let certificate = tokio::fs::read(certificate)
+                        .await
+                        .change_context(SinkPostgresError)
+                        .attach_printable_lazy(|| {
+                            format!("failed to read tls certificate at {certificate:?}")
+                        })?;
+                    let certificate = Certificate::from_pem(&certificate)
+                        .change_context(SinkPostgresError)
+                        .attach_printable("failed to build certificate from PEM file")?;
+                    builder.add_root_certificate(certificate);
+                }
+
+                if let Some(accept_invalid_certificates) = accept_invalid_certificates {
+                    builder.danger_accept_invalid_certs(*accept_invalid_certificates);
+                }
+
+                if let Some(disable_system_roots) = disable_system_roots {
+                    builder.disable_built_in_roots(*disable_system_roots);
+                }
+
+                if let Some(accept_invalid_hostnames) = accept_invalid_hostnames {
+                    builder.danger_accept_invalid_hostnames(*accept_invalid_hostnames);
+                }
+
+                if let Some(use_sni) = use_sni {
+                    builder.use_sni(*use_sni);
+                }
+
+                let connector = builder
+                    .build()
+                    .change_context(SinkPostgresError)
+                    .attach_printable("failed to build tls connector")?;
+                Ok(Connector::Tls(MakeTlsConnector::new(connector)))
+            }
+        }
+    }
+
+    /// Creates a connection pool.
+    ///
+    /// Closed connections are discarded when they are taken from the pool, and new connections
+    /// are created as needed. `post_create` runs on each new connection.
+    pub fn pool(&self, pg: Config, post_create: Option<Hook>) -> Result<Pool, SinkPostgresError> {
+        let manager_config = ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        };
+        let manager = match self {
+            Connector::NoTls => Manager::from_config(pg, NoTls, manager_config),
+            Connector::Tls(connector) => {
+                Manager::from_config(pg, connector.clone(), manager_config)
+            }
+        };
+
+        let mut builder = Pool::builder(manager).max_size(POOL_MAX_SIZE);
+        if let Some(hook) = post_create {
+            builder = builder.post_create(hook);
+        }
+
+        builder
+            .build()
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to build postgres connection pool")
+    }
+}
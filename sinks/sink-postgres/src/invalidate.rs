@@ -0,0 +1,147 @@
+use serde_json::{Map, Value};
+use tokio_postgres::types::Json;
+
+use crate::configuration::{InvalidateColumn, InvalidateOperator};
+use crate::table::quote_identifier;
+
+/// Additional conditions of the invalidate queries on a table.
+///
+/// Values are bound as query parameters and converted to the column type by
+/// `json_populate_record`, so they are never interpolated in the query.
+pub struct InvalidateConditions {
+    table_name: String,
+    columns: Vec<(String, InvalidateOperator, Option<Json<Value>>)>,
+}
+
+impl InvalidateConditions {
+    pub fn new(table_name: &str, columns: &[InvalidateColumn]) -> Self {
+        let columns = columns
+            .iter()
+            .map(|column| {
+                let operator = column.operator();
+                let param = match (&column.value, operator) {
+                    (Value::Null, _) => None,
+                    (Value::Array(values), InvalidateOperator::In | InvalidateOperator::NotIn) => {
+                        let records = values
+                            .iter()
+                            .map(|value| single_column_record(&column.column, value))
+                            .collect();
+                        Some(Json(Value::Array(records)))
+                    }
+                    (value, _) => Some(Json(single_column_record(&column.column, value))),
+                };
+                (column.column.clone(), operator, param)
+            })
+            .collect();
+
+        InvalidateConditions {
+            table_name: table_name.to_string(),
+            columns,
+        }
+    }
+
+    /// Returns the conditions, to be appended to a `WHERE` clause.
+    ///
+    /// Parameters are numbered starting from `first_param`.
+    pub fn to_sql(&self, first_param: usize) -> String {
+        let mut param_index = first_param;
+        let mut sql = String::default();
+        for (column, operator, param) in &self.columns {
+            let column = quote_identifier(column);
+            let condition = match (operator, param) {
+                (InvalidateOperator::NotEq, None) => format!("{column} IS NOT NULL"),
+                (_, None) => format!("{column} IS NULL"),
+                (InvalidateOperator::In | InvalidateOperator::NotIn, Some(_)) => {
+                    let condition = format!(
+                        "{column} {} (SELECT {column} FROM json_populate_recordset(NULL::{}, ${param_index}::json))",
+                        operator.as_sql(),
+                        self.table_name
+                    );
+                    param_index += 1;
+                    condition
+                }
+                (_, Some(_)) => {
+                    let condition = format!(
+                        "{column} {} (SELECT {column} FROM json_populate_record(NULL::{}, ${param_index}::json))",
+                        operator.as_sql(),
+                        self.table_name
+                    );
+                    param_index += 1;
+                    condition
+                }
+            };
+            sql.push_str(" AND ");
+            sql.push_str(&condition);
+        }
+        sql
+    }
+
+    /// Returns the parameters of the conditions, in the same order as the placeholders.
+    pub fn params(&self) -> impl Iterator<Item = &Json<Value>> {
+        self.columns
+            .iter()
+            .filter_map(|(_, _, param)| param.as_ref())
+    }
+}
+
+fn single_column_record(column: &str, value: &Value) -> Value {
+    let mut record = Map::new();
+    record.insert(column.to_string(), value.clone());
+    Value::Object(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::InvalidateConditions;
+    use crate::configuration::{InvalidateColumn, InvalidateOperator};
+
+    #[test]
+    pub fn test_invalidate_conditions() {
+        let columns = vec![
+            InvalidateColumn {
+                column: "tenant".into(),
+                value: "a' OR 1=1; --".into(),
+                operator: None,
+            },
+            InvalidateColumn {
+                column: "deleted_at".into(),
+                value: json!(null),
+                operator: None,
+            },
+            InvalidateColumn {
+                column: "chain_id".into(),
+                value: json!([1, 2]),
+                operator: None,
+            },
+            InvalidateColumn {
+                column: "amount".into(),
+                value: json!(10),
+                operator: Some(InvalidateOperator::GtEq),
+            },
+        ];
+        let conditions = InvalidateConditions::new("test", &columns);
+
+        assert_eq!(
+            conditions.to_sql(2),
+            " AND \"tenant\" = (SELECT \"tenant\" FROM json_populate_record(NULL::test, $2::json)) \
+            AND \"deleted_at\" IS NULL \
+            AND \"chain_id\" IN (SELECT \"chain_id\" FROM json_populate_recordset(NULL::test, $3::json)) \
+            AND \"amount\" >= (SELECT \"amount\" FROM json_populate_record(NULL::test, $4::json))"
+        );
+
+        let params = conditions
+            .params()
+            .map(|param| param.0.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            params,
+            vec![
+                json!({ "tenant": "a' OR 1=1; --" }),
+                json!([{ "chain_id": 1 }, { "chain_id": 2 }]),
+                json!({ "amount": 10 }),
+            ]
+        );
+    }
+}
@@ -1,5 +1,13 @@
 mod configuration;
+mod connection;
+mod invalidate;
+mod schema;
 mod sink;
+mod staging;
+mod table;
 
-pub use self::configuration::{InvalidateColumn, SinkPostgresConfiguration, SinkPostgresOptions};
+pub use self::configuration::{
+    ColumnOptions, CopyFormat, IngestionConfiguration, InvalidateColumn, InvalidateOperator,
+    SinkPostgresConfiguration, SinkPostgresOptions, TableConfiguration, TableMode, TableOptions,
+};
 pub use self::sink::{PostgresSink, SinkPostgresError};
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+
+use error_stack::{Result, ResultExt};
+use serde_json::{Map, Value};
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::configuration::{TableConfiguration, TableMode};
+use crate::sink::SinkPostgresError;
+use crate::table::quote_identifier;
+
+/// Tracks the schema of a table managed by the sink.
+pub struct TableSchema {
+    /// Columns currently in the table.
+    columns: HashSet<String>,
+    /// Entity keys that already have an index.
+    entity_indexes: HashSet<Vec<String>>,
+}
+
+impl TableSchema {
+    /// Creates the table if it doesn't exist, then adds any declared column missing from it.
+    pub async fn initialize(
+        client: &Client,
+        config: &TableConfiguration,
+    ) -> Result<Self, SinkPostgresError> {
+        let name = &config.name;
+
+        let mut column_definitions = config
+            .columns
+            .iter()
+            .map(|column| format!("{} {}", quote_identifier(&column.name), column.r#type))
+            .collect::<Vec<_>>();
+
+        // Standard tables can declare an int8range cursor to preserve the rows history.
+        let cursor_type = config
+            .columns
+            .iter()
+            .find(|column| column.name == "_cursor")
+            .map(|column| column.r#type.as_str());
+        if cursor_type.is_none() {
+            let cursor_type = match config.mode {
+                TableMode::Standard => "bigint",
+                TableMode::Entity => "int8range",
+            };
+            column_definitions.push(format!("_cursor {cursor_type}"));
+        }
+
+        let create_query = format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            name,
+            column_definitions.join(", ")
+        );
+        client
+            .execute(&create_query, &[])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to create table {name}"))?;
+
+        let rows = client
+            .query(
+                "SELECT attname FROM pg_attribute WHERE attrelid = $1::text::regclass AND attnum > 0 AND NOT attisdropped",
+                &[name],
+            )
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to read columns of table {name}"))?;
+
+        let mut schema = TableSchema {
+            columns: rows.into_iter().map(|row| row.get(0)).collect(),
+            entity_indexes: HashSet::default(),
+        };
+
+        for column in &config.columns {
+            if !schema.columns.contains(&column.name) {
+                schema
+                    .add_column(client, name, &column.name, &column.r#type)
+                    .await?;
+            }
+        }
+
+        let index_method = match (config.mode, cursor_type) {
+            (TableMode::Entity, _) | (_, Some("int8range")) => "gist",
+            _ => "btree",
+        };
+        let index_query = format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} USING {} (_cursor)",
+            index_name(name, "cursor"),
+            name,
+            index_method
+        );
+        client
+            .execute(&index_query, &[])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to create cursor index on {name}"))?;
+
+        Ok(schema)
+    }
+
+    /// Adds a nullable column for each field in `rows` that is not in the table yet.
+    ///
+    /// Fields with a `null` value are skipped since their type can't be inferred.
+    pub async fn add_missing_columns<'a>(
+        &mut self,
+        client: &Client,
+        table_name: &str,
+        rows: impl Iterator<Item = &'a Map<String, Value>>,
+    ) -> Result<(), SinkPostgresError> {
+        let mut new_columns: Vec<(&String, &'static str)> = Vec::new();
+        for row in rows {
+            for (name, value) in row {
+                if name == "_cursor"
+                    || self.columns.contains(name)
+                    || new_columns.iter().any(|(new_name, _)| *new_name == name)
+                {
+                    continue;
+                }
+
+                if let Some(column_type) = infer_column_type(value) {
+                    new_columns.push((name, column_type));
+                }
+            }
+        }
+
+        for (name, column_type) in new_columns {
+            self.add_column(client, table_name, name, column_type)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates an index on the entity columns, if one was not created already.
+    pub async fn ensure_entity_index(
+        &mut self,
+        client: &Client,
+        table_name: &str,
+        entity: &Map<String, Value>,
+    ) -> Result<(), SinkPostgresError> {
+        let mut keys = entity.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+
+        if self.entity_indexes.contains(&keys) {
+            return Ok(());
+        }
+
+        // Columns with only null values are not created yet.
+        if !keys.iter().all(|key| self.columns.contains(key)) {
+            return Ok(());
+        }
+
+        let columns = keys
+            .iter()
+            .map(|key| quote_identifier(key))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let index_query = format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+            index_name(table_name, &keys.join("_")),
+            table_name,
+            columns
+        );
+
+        info!(table = %table_name, columns = %columns, "creating entity index");
+        client
+            .execute(&index_query, &[])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to create entity index on {table_name}"))?;
+
+        self.entity_indexes.insert(keys);
+        Ok(())
+    }
+
+    async fn add_column(
+        &mut self,
+        client: &Client,
+        table_name: &str,
+        name: &str,
+        column_type: &str,
+    ) -> Result<(), SinkPostgresError> {
+        info!(table = %table_name, column = %name, column_type = %column_type, "adding column");
+
+        // Statements that reference the table are re-planned by PostgreSQL after the schema
+        // changes, so there is no need to prepare them again.
+        let query = format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+            table_name,
+            quote_identifier(name),
+            column_type
+        );
+        client
+            .execute(&query, &[])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to add column {name} to {table_name}"))?;
+
+        self.columns.insert(name.to_string());
+        Ok(())
+    }
+}
+
+/// Returns the column type that can store the given value.
+///
+/// Returns `None` for `null` values.
+pub fn infer_column_type(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Null => None,
+        Value::Bool(_) => Some("boolean"),
+        Value::Number(number) if number.is_i64() => Some("bigint"),
+        Value::Number(number) if number.is_u64() => Some("numeric"),
+        Value::Number(_) => Some("double precision"),
+        Value::String(_) => Some("text"),
+        Value::Array(_) | Value::Object(_) => Some("jsonb"),
+    }
+}
+
+/// Returns the (quoted) name of the index on the given table.
+fn index_name(table_name: &str, suffix: &str) -> String {
+    let table_name = table_name.replace('.', "_");
+    quote_identifier(&format!("{table_name}_{suffix}_idx"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::infer_column_type;
+
+    #[test]
+    pub fn test_infer_column_type() {
+        assert_eq!(infer_column_type(&json!(null)), None);
+        assert_eq!(infer_column_type(&json!(true)), Some("boolean"));
+        assert_eq!(infer_column_type(&json!(-1)), Some("bigint"));
+        assert_eq!(infer_column_type(&json!(u64::MAX)), Some("numeric"));
+        assert_eq!(infer_column_type(&json!(1.5)), Some("double precision"));
+        assert_eq!(infer_column_type(&json!("0x1")), Some("text"));
+        assert_eq!(infer_column_type(&json!([1, 2])), Some("jsonb"));
+        assert_eq!(infer_column_type(&json!({ "a": 1 })), Some("jsonb"));
+    }
+}
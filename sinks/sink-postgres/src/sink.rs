@@ -1,17 +1,17 @@
 use std::fmt;
 
-use apibara_core::node::v1alpha2::Cursor;
-use apibara_sink_common::{Context, CursorAction, DisplayCursor, Sink, ValueExt};
+use apibara_core::node::v1alpha2::{Cursor, DataFinality};
+use apibara_sink_common::{Context, CursorAction, DisplayCursor, Sink, SinkHealth, ValueExt};
 use async_trait::async_trait;
+use deadpool_postgres::{ClientWrapper, Object, Pool, Transaction};
 use error_stack::{Result, ResultExt};
-use native_tls::{Certificate, TlsConnector};
-use postgres_native_tls::MakeTlsConnector;
-use serde_json::Value;
-use tokio_postgres::types::Json;
-use tokio_postgres::{Client, NoTls, Statement};
+use serde_json::{Map, Value};
 use tracing::{debug, info, warn};
 
-use crate::configuration::{InvalidateColumn, TlsConfiguration};
+use crate::configuration::{SinkPostgresConfiguration, TableMode};
+use crate::connection::Connector;
+use crate::staging;
+use crate::table::Table;
 use crate::SinkPostgresOptions;
 
 #[derive(Debug)]
@@ -25,12 +25,24 @@ impl fmt::Display for SinkPostgresError {
 }
 
 pub struct PostgresSink {
-    pub client: Client,
-    insert_statement: Statement,
-    delete_statement: Statement,
-    delete_all_statement: Statement,
+    pool: Pool,
+    config: SinkPostgresConfiguration,
+    tables: Vec<Table>,
+    cursor: Option<CursorTable>,
+    /// Error of the last attempt to get a connection, reported as the sink health.
+    connection_error: Option<String>,
 }
 
+/// Stores the cursor in the same transaction as the data.
+struct CursorTable {
+    cursor_id: String,
+}
+
+const GET_CURSOR_QUERY: &str = "SELECT order_key, unique_key FROM _apibara_cursors WHERE id = $1";
+const PUT_CURSOR_QUERY: &str = "INSERT INTO _apibara_cursors(id, order_key, unique_key) VALUES ($1, $2, $3) \
+    ON CONFLICT (id) DO UPDATE SET order_key = excluded.order_key, unique_key = excluded.unique_key";
+const DELETE_CURSOR_QUERY: &str = "DELETE FROM _apibara_cursors WHERE id = $1";
+
 #[async_trait]
 impl Sink for PostgresSink {
     type Options = SinkPostgresOptions;
@@ -39,129 +51,32 @@ impl Sink for PostgresSink {
     async fn from_options(options: Self::Options) -> Result<Self, Self::Error> {
         info!("connecting to database");
         let config = options.to_postgres_configuration()?;
-        let table_name = config.table_name;
-
-        // Notice that all `connector` and `connection` types are different, so it's easier/cleaner
-        // to just connect and spawn a connection inside each branch.
-        let client = match config.tls {
-            TlsConfiguration::NoTls => {
-                info!("Using insecure connection");
-                let (client, connection) = config
-                    .pg
-                    .connect(NoTls)
-                    .await
-                    .change_context(SinkPostgresError)
-                    .attach_printable("failed to connect to postgres (no tls)")?;
-                tokio::spawn(connection);
-                client
-            }
-            TlsConfiguration::Tls {
-                certificate,
-                accept_invalid_hostnames,
-                accept_invalid_certificates,
-                disable_system_roots,
-                use_sni,
-            } => {
-                info!("Configure TLS connection");
-                let mut builder = TlsConnector::builder();
-
-                if let Some(ref certificate) = certificate {
-                    let certificate = tokio::fs::read(certificate)
-                        .await
-                        .change_context(SinkPostgresError)
-                        .attach_printable_lazy(|| {
-                            format!("failed to read tls certificate at {certificate:?}")
-                        })?;
-                    let certificate = Certificate::from_pem(&certificate)
-                        .change_context(SinkPostgresError)
-                        .attach_printable("failed to build certificate from PEM file")?;
-                    builder.add_root_certificate(certificate);
-                }
-
-                if let Some(accept_invalid_certificates) = accept_invalid_certificates {
-                    builder.danger_accept_invalid_certs(accept_invalid_certificates);
-                }
-
-                if let Some(disable_system_roots) = disable_system_roots {
-                    builder.disable_built_in_roots(disable_system_roots);
-                }
-
-                if let Some(accept_invalid_hostnames) = accept_invalid_hostnames {
-                    builder.danger_accept_invalid_hostnames(accept_invalid_hostnames);
-                }
-
-                if let Some(use_sni) = use_sni {
-                    builder.use_sni(use_sni);
-                }
-
-                let connector = builder
-                    .build()
-                    .change_context(SinkPostgresError)
-                    .attach_printable("failed to build tls connector")?;
-                let connector = MakeTlsConnector::new(connector);
-                let (client, connection) = config
-                    .pg
-                    .connect(connector)
-                    .await
-                    .change_context(SinkPostgresError)
-                    .attach_printable("failed to connect to postgres (tls)")?;
-                tokio::spawn(connection);
-                client
-            }
-        };
 
-        info!("client connected successfully");
+        let connector = Connector::new(&config.tls).await?;
+        // Each connection has its own staging table.
+        let post_create = config
+            .ingestion
+            .copy
+            .then(staging::create_staging_table_hook);
+        let pool = connector.pool(config.pg.clone(), post_create)?;
 
-        let query = format!(
-            "INSERT INTO {} SELECT * FROM json_populate_recordset(NULL::{}, $1::json)",
-            &table_name, &table_name
-        );
-
-        let additional_conditions: String = if config.invalidate.is_empty() {
-            "".into()
-        } else {
-            // TODO: this is quite fragile. It should properly format the column name
-            // and value.
-            config.invalidate.iter().fold(
-                String::default(),
-                |acc, InvalidateColumn { column, value }| {
-                    format!("{acc} AND \"{column}\" = '{value}'")
-                },
-            )
-        };
-
-        let delete_query = format!(
-            "DELETE FROM {} WHERE _cursor > $1 {}",
-            table_name, additional_conditions
-        );
-        let delete_all_query = format!(
-            "DELETE FROM {} WHERE true {}",
-            table_name, additional_conditions
-        );
-
-        let insert_statement = client
-            .prepare(&query)
+        let client = pool
+            .get()
             .await
             .change_context(SinkPostgresError)
-            .attach_printable("failed to prepare insert data query")?;
+            .attach_printable("failed to connect to postgres")?;
 
-        let delete_statement = client
-            .prepare(&delete_query)
-            .await
-            .change_context(SinkPostgresError)
-            .attach_printable("failed to prepare invalidate data query")?;
+        info!("client connected successfully");
 
-        let delete_all_statement = client
-            .prepare(&delete_all_query)
-            .await
-            .change_context(SinkPostgresError)
-            .attach_printable("failed to prepare invalidate all query")?;
+        let (tables, cursor) = prepare_tables(&client, &config).await?;
+        drop(client);
 
         Ok(Self {
-            client,
-            insert_statement,
-            delete_statement,
-            delete_all_statement,
+            pool,
+            config,
+            tables,
+            cursor,
+            connection_error: None,
         })
     }
 
@@ -181,21 +96,55 @@ impl Sink for PostgresSink {
             return Ok(CursorAction::Persist);
         }
 
-        let batch = batch
-            .iter()
-            .map(|value| {
-                // Safety: we know that the batch is an array of objects
-                let mut value = value.as_object().expect("value is an object").clone();
-                value.insert("_cursor".into(), ctx.end_cursor.order_key.into());
-                value
-            })
-            .collect::<Vec<_>>();
-
-        self.client
-            .execute(&self.insert_statement, &[&Json(batch)])
+        // Group rows by table, so that each table is written with a single query.
+        let mut rows = vec![Vec::new(); self.tables.len()];
+        let mut entities = vec![Vec::new(); self.tables.len()];
+        for item in batch {
+            // Safety: we know that the batch is an array of objects
+            let item = item.as_object().expect("value is an object");
+            let (table_index, item) = self.route_item(item)?;
+            match self.tables[table_index].mode {
+                TableMode::Standard => rows[table_index].push(item.clone()),
+                TableMode::Entity => entities[table_index].push(entity_with_update(item)?),
+            }
+        }
+
+        let mut client = self.client().await?;
+
+        // Schema changes are applied outside of the data transaction.
+        for ((table, rows), entities) in self.tables.iter_mut().zip(&rows).zip(&entities) {
+            table.migrate(&client, rows, entities).await?;
+        }
+
+        let txn = client
+            .transaction()
             .await
             .change_context(SinkPostgresError)
-            .attach_printable("failed to run insert data query")?;
+            .attach_printable("failed to start transaction")?;
+
+        for ((table, rows), entities) in self.tables.iter().zip(rows).zip(entities) {
+            if !rows.is_empty() {
+                table.insert(&txn, &ctx.end_cursor, rows).await?;
+            }
+
+            for (entity, update) in entities {
+                table
+                    .update_entity(&txn, &ctx.end_cursor, entity, update)
+                    .await?;
+            }
+        }
+
+        // Pending data is invalidated by the next message, so its cursor is never stored.
+        if let Some(cursor) = &self.cursor {
+            if ctx.finality != DataFinality::DataStatusPending {
+                cursor.put(&txn, &ctx.end_cursor).await?;
+            }
+        }
+
+        txn.commit()
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to commit transaction")?;
 
         Ok(CursorAction::Persist)
     }
@@ -203,22 +152,258 @@ impl Sink for PostgresSink {
     async fn handle_invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), Self::Error> {
         debug!(cursor = %DisplayCursor(cursor), "handling invalidate");
 
-        if let Some(cursor) = cursor {
-            // convert to i64 because that's the tokio_postgres type that maps to bigint
-            let block_number = i64::try_from(cursor.order_key).unwrap();
-            self.client
-                .execute(&self.delete_statement, &[&block_number])
-                .await
-                .change_context(SinkPostgresError)
-                .attach_printable("failed to run invalidate data query")?;
-        } else {
-            self.client
-                .execute(&self.delete_all_statement, &[])
-                .await
-                .change_context(SinkPostgresError)
-                .attach_printable("failed to run invalidate all data query")?;
+        let mut client = self.client().await?;
+        let txn = client
+            .transaction()
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to start transaction")?;
+
+        for table in &self.tables {
+            table.invalidate(&txn, cursor).await?;
+        }
+
+        if let Some(cursor_table) = &self.cursor {
+            match cursor {
+                None => cursor_table.delete(&txn).await?,
+                Some(cursor) => cursor_table.put(&txn, cursor).await?,
+            }
+        }
+
+        txn.commit()
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to commit transaction")?;
+
+        Ok(())
+    }
+
+    fn is_transactional(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    async fn get_cursor(&mut self) -> Result<Option<Cursor>, Self::Error> {
+        let Some(cursor_id) = self.cursor.as_ref().map(|cursor| cursor.cursor_id.clone()) else {
+            return Ok(None);
+        };
+
+        let client = self.client().await?;
+        let statement = client
+            .prepare_cached(GET_CURSOR_QUERY)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to prepare get cursor query")?;
+        let row = client
+            .query_opt(&statement, &[&cursor_id])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to read cursor")?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let order_key: i64 = row.get("order_key");
+        let unique_key: Vec<u8> = row.get("unique_key");
+        let order_key = u64::try_from(order_key)
+            .change_context(SinkPostgresError)
+            .attach_printable("stored cursor has a negative order key")?;
+
+        Ok(Some(Cursor {
+            order_key,
+            unique_key,
+        }))
+    }
+
+    async fn handle_heartbeat(&mut self) -> Result<(), Self::Error> {
+        // Reconnect while the stream is idle. If it fails, the next message tries again.
+        if let Err(err) = self.client().await {
+            warn!(err = ?err, "failed to reconnect to postgres");
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        match &self.connection_error {
+            None => SinkHealth::Healthy,
+            Some(err) => SinkHealth::Unhealthy(format!("failed to connect to postgres: {err}")),
+        }
+    }
+}
+
+impl PostgresSink {
+    /// Returns a connection from the pool.
+    ///
+    /// Closed connections are replaced by new ones, which prepare their statements again
+    /// when they are first used.
+    async fn client(&mut self) -> Result<Object, SinkPostgresError> {
+        match self.pool.get().await {
+            Ok(client) => {
+                if self.connection_error.take().is_some() {
+                    info!("reconnected to postgres");
+                }
+                Ok(client)
+            }
+            Err(err) => {
+                self.connection_error = Some(err.to_string());
+                Err(err)
+                    .change_context(SinkPostgresError)
+                    .attach_printable("failed to get connection from pool")
+            }
         }
+    }
 
+    /// Returns the index of the table targeted by the item, together with the item's payload.
+    fn route_item<'a>(
+        &self,
+        item: &'a Map<String, Value>,
+    ) -> Result<(usize, &'a Map<String, Value>), SinkPostgresError> {
+        if !self.config.multi_table {
+            return Ok((0, item));
+        }
+
+        let table_name = item
+            .get("table")
+            .ok_or(SinkPostgresError)
+            .attach_printable("item missing table key")?
+            .as_str()
+            .ok_or(SinkPostgresError)
+            .attach_printable("table is not a string")?;
+
+        let table_index = self
+            .tables
+            .iter()
+            .position(|table| table.name == table_name)
+            .ok_or(SinkPostgresError)
+            .attach_printable_lazy(|| format!("table {table_name} is not in the sink tables"))?;
+
+        match self.tables[table_index].mode {
+            TableMode::Entity => Ok((table_index, item)),
+            TableMode::Standard => {
+                let data = item
+                    .get("data")
+                    .ok_or(SinkPostgresError)
+                    .attach_printable("item missing data key")?
+                    .as_object()
+                    .ok_or(SinkPostgresError)
+                    .attach_printable("data is not an object")?;
+                Ok((table_index, data))
+            }
+        }
+    }
+}
+
+/// Prepares the tables used by the sink.
+async fn prepare_tables(
+    client: &ClientWrapper,
+    config: &SinkPostgresConfiguration,
+) -> Result<(Vec<Table>, Option<CursorTable>), SinkPostgresError> {
+    let mut tables = Vec::with_capacity(config.tables.len());
+    for table in &config.tables {
+        let table = Table::prepare(
+            client,
+            table,
+            &config.invalidate,
+            config.manage_schema,
+            &config.ingestion,
+        )
+        .await?;
+        tables.push(table);
+    }
+
+    let cursor = match &config.cursor_id {
+        None => None,
+        Some(cursor_id) => Some(CursorTable::prepare(client, cursor_id.clone()).await?),
+    };
+
+    Ok((tables, cursor))
+}
+
+/// Extracts the `entity` and `update` objects from an entity item.
+fn entity_with_update(
+    item: &Map<String, Value>,
+) -> Result<(&Map<String, Value>, &Map<String, Value>), SinkPostgresError> {
+    let entity = item
+        .get("entity")
+        .ok_or(SinkPostgresError)
+        .attach_printable("item missing entity key")?
+        .as_object()
+        .ok_or(SinkPostgresError)
+        .attach_printable("entity is not an object")?;
+
+    let update = item
+        .get("update")
+        .ok_or(SinkPostgresError)
+        .attach_printable("item missing update key")?
+        .as_object()
+        .ok_or(SinkPostgresError)
+        .attach_printable("update is not an object")?;
+
+    Ok((entity, update))
+}
+
+impl CursorTable {
+    /// Creates the cursors table, if needed, and prepares the cursor queries.
+    async fn prepare(client: &ClientWrapper, cursor_id: String) -> Result<Self, SinkPostgresError> {
+        client
+            .execute(
+                "CREATE TABLE IF NOT EXISTS _apibara_cursors(id text PRIMARY KEY, order_key bigint NOT NULL, unique_key bytea NOT NULL)",
+                &[],
+            )
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to create cursors table")?;
+
+        client
+            .prepare_cached(GET_CURSOR_QUERY)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to prepare get cursor query")?;
+
+        client
+            .prepare_cached(PUT_CURSOR_QUERY)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to prepare put cursor query")?;
+
+        client
+            .prepare_cached(DELETE_CURSOR_QUERY)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to prepare delete cursor query")?;
+
+        Ok(Self { cursor_id })
+    }
+
+    async fn put(&self, txn: &Transaction<'_>, cursor: &Cursor) -> Result<(), SinkPostgresError> {
+        let order_key = i64::try_from(cursor.order_key)
+            .change_context(SinkPostgresError)
+            .attach_printable("cursor order key does not fit in bigint")?;
+        let statement = txn
+            .prepare_cached(PUT_CURSOR_QUERY)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to prepare put cursor query")?;
+        txn.execute(
+            &statement,
+            &[&self.cursor_id, &order_key, &cursor.unique_key],
+        )
+        .await
+        .change_context(SinkPostgresError)
+        .attach_printable("failed to store cursor")?;
+        Ok(())
+    }
+
+    async fn delete(&self, txn: &Transaction<'_>) -> Result<(), SinkPostgresError> {
+        let statement = txn
+            .prepare_cached(DELETE_CURSOR_QUERY)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to prepare delete cursor query")?;
+        txn.execute(&statement, &[&self.cursor_id])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to delete cursor")?;
         Ok(())
     }
 }
@@ -0,0 +1,132 @@
+use bytes::Bytes;
+use deadpool_postgres::{Hook, HookError, HookErrorCause};
+use error_stack::{Result, ResultExt};
+use futures::SinkExt;
+use serde_json::{Map, Value};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{Json, Type};
+use tokio_postgres::Transaction;
+
+use crate::configuration::CopyFormat;
+use crate::sink::SinkPostgresError;
+
+/// Name of the table used to stage rows before moving them to the target table.
+pub const STAGING_TABLE: &str = "_apibara_staging";
+
+/// Returns a hook that creates the staging table on each new connection.
+///
+/// The table is temporary, so it's only visible to the session that created it.
+pub fn create_staging_table_hook() -> Hook {
+    Hook::async_fn(|client, _| {
+        Box::pin(async move {
+            let query =
+                format!("CREATE TEMPORARY TABLE IF NOT EXISTS {STAGING_TABLE} (data jsonb)");
+            client
+                .execute(&query, &[])
+                .await
+                .map_err(|err| HookError::Abort(HookErrorCause::Backend(err)))?;
+            Ok(())
+        })
+    })
+}
+
+/// Copies the rows to the staging table, using the given `COPY` format.
+pub async fn copy_rows(
+    txn: &Transaction<'_>,
+    rows: &[Map<String, Value>],
+    format: CopyFormat,
+) -> Result<(), SinkPostgresError> {
+    match format {
+        CopyFormat::Binary => copy_rows_binary(txn, rows).await,
+        CopyFormat::Csv => copy_rows_csv(txn, rows).await,
+    }
+}
+
+async fn copy_rows_binary(
+    txn: &Transaction<'_>,
+    rows: &[Map<String, Value>],
+) -> Result<(), SinkPostgresError> {
+    let query = format!("COPY {STAGING_TABLE} (data) FROM STDIN (FORMAT binary)");
+    let sink = txn
+        .copy_in(&query)
+        .await
+        .change_context(SinkPostgresError)
+        .attach_printable("failed to start copy to staging table")?;
+
+    let writer = BinaryCopyInWriter::new(sink, &[Type::JSONB]);
+    tokio::pin!(writer);
+
+    for row in rows {
+        writer
+            .as_mut()
+            .write(&[&Json(row)])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to copy row to staging table")?;
+    }
+
+    writer
+        .finish()
+        .await
+        .change_context(SinkPostgresError)
+        .attach_printable("failed to finish copy to staging table")?;
+
+    Ok(())
+}
+
+async fn copy_rows_csv(
+    txn: &Transaction<'_>,
+    rows: &[Map<String, Value>],
+) -> Result<(), SinkPostgresError> {
+    let query = format!("COPY {STAGING_TABLE} (data) FROM STDIN (FORMAT csv)");
+    let sink = txn
+        .copy_in(&query)
+        .await
+        .change_context(SinkPostgresError)
+        .attach_printable("failed to start copy to staging table")?;
+    tokio::pin!(sink);
+
+    let mut data = String::new();
+    for row in rows {
+        let row = serde_json::to_string(row)
+            .change_context(SinkPostgresError)
+            .attach_printable("failed to serialize row")?;
+        write_csv_field(&mut data, &row);
+    }
+
+    sink.send(Bytes::from(data))
+        .await
+        .change_context(SinkPostgresError)
+        .attach_printable("failed to copy rows to staging table")?;
+
+    sink.as_mut()
+        .finish()
+        .await
+        .change_context(SinkPostgresError)
+        .attach_printable("failed to finish copy to staging table")?;
+
+    Ok(())
+}
+
+/// Appends a line with a single quoted CSV field to `data`.
+fn write_csv_field(data: &mut String, value: &str) {
+    data.push('"');
+    data.push_str(&value.replace('"', "\"\""));
+    data.push_str("\"\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_csv_field;
+
+    #[test]
+    pub fn test_write_csv_field() {
+        let mut data = String::new();
+        write_csv_field(&mut data, r#"{"a":"x,\"y\"\nz"}"#);
+        write_csv_field(&mut data, "{}");
+        assert_eq!(
+            data,
+            "\"{\"\"a\"\":\"\"x,\\\"\"y\\\"\"\\nz\"\"}\"\n\"{}\"\n"
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use apibara_core::node::v1alpha2::Cursor;
+use deadpool_postgres::{ClientWrapper, Transaction};
+use error_stack::{Result, ResultExt};
+use serde_json::{Map, Value};
+use tokio_postgres::types::{Json, ToSql};
+use tokio_postgres::{Client, Statement};
+
+use crate::configuration::{
+    CopyFormat, IngestionConfiguration, InvalidateColumn, TableConfiguration, TableMode,
+};
+use crate::invalidate::InvalidateConditions;
+use crate::schema::TableSchema;
+use crate::sink::SinkPostgresError;
+use crate::staging::{self, STAGING_TABLE};
+
+/// A target table, together with the queries used to write to it.
+///
+/// Prepared statements belong to a connection, so queries are prepared with the statement
+/// cache of the connection that runs them.
+pub struct Table {
+    pub name: String,
+    pub mode: TableMode,
+    cursor_column: CursorColumn,
+    insert_query: String,
+    /// Moves rows from the staging table to the table. Only used when copying rows.
+    move_query: Option<String>,
+    /// Format used to copy rows to the staging table.
+    copy_format: CopyFormat,
+    /// Maximum number of rows inserted with a single query.
+    chunk_size: usize,
+    delete_query: String,
+    delete_all_query: String,
+    /// Reopens rows closed after the invalidated block. Only used with range cursors.
+    unclamp_query: Option<String>,
+    /// Table schema, only tracked if the sink manages it.
+    schema: Option<TableSchema>,
+    /// Additional conditions of the invalidate queries.
+    invalidate: InvalidateConditions,
+}
+
+/// Type of the `_cursor` column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CursorColumn {
+    /// A `bigint` with the block number that produced the row.
+    Block,
+    /// An `int8range` with the blocks for which the row is valid.
+    Range,
+}
+
+impl Table {
+    /// Prepares the queries used to write to and invalidate the table.
+    ///
+    /// The `invalidate` columns are added as conditions to the invalidate queries.
+    /// If `manage_schema` is true, the table is created before preparing the queries.
+    /// If rows are copied, the staging table must exist already.
+    pub async fn prepare(
+        client: &ClientWrapper,
+        config: &TableConfiguration,
+        invalidate: &[InvalidateColumn],
+        manage_schema: bool,
+        ingestion: &IngestionConfiguration,
+    ) -> Result<Self, SinkPostgresError> {
+        let schema = if manage_schema {
+            Some(TableSchema::initialize(client, config).await?)
+        } else {
+            None
+        };
+
+        let name = config.name.clone();
+
+        let insert_query = format!(
+            "INSERT INTO {} SELECT * FROM json_populate_recordset(NULL::{}, $1::json)",
+            &name, &name
+        );
+
+        let invalidate = InvalidateConditions::new(&name, invalidate);
+        // The first parameter of the invalidate queries is the block number, except for
+        // the query that invalidates all data.
+        let additional_conditions = invalidate.to_sql(2);
+
+        let cursor_column = CursorColumn::read_from_table(client, &name).await?;
+        if config.mode == TableMode::Entity && cursor_column != CursorColumn::Range {
+            return Err(SinkPostgresError).attach_printable_lazy(|| {
+                format!("table {name} is in entity mode but its _cursor column is not an int8range")
+            });
+        }
+
+        // Rows with a range cursor are restored when the reorg invalidates the block
+        // that closed them.
+        let (delete_query, unclamp_query) = match cursor_column {
+            CursorColumn::Block => {
+                let delete_query = format!(
+                    "DELETE FROM {} WHERE _cursor > $1 {}",
+                    &name, additional_conditions
+                );
+                (delete_query, None)
+            }
+            CursorColumn::Range => {
+                let delete_query = format!(
+                    "DELETE FROM {} WHERE lower(_cursor) > $1 {}",
+                    &name, additional_conditions
+                );
+                let unclamp_query = format!(
+                    "UPDATE {} SET _cursor = int8range(lower(_cursor), NULL) WHERE upper(_cursor) > $1 {}",
+                    &name, additional_conditions
+                );
+                (delete_query, Some(unclamp_query))
+            }
+        };
+
+        let delete_all_query = format!("DELETE FROM {} WHERE true {}", &name, invalidate.to_sql(1));
+
+        let move_query = if ingestion.copy {
+            Some(format!(
+                "WITH _staged AS (DELETE FROM {STAGING_TABLE} RETURNING data) INSERT INTO {} SELECT _row.* FROM _staged, jsonb_populate_record(NULL::{}, _staged.data) AS _row",
+                &name, &name
+            ))
+        } else {
+            None
+        };
+
+        // Prepare the queries once, so that invalid queries are reported at startup.
+        client
+            .prepare_cached(&insert_query)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to prepare insert data query ({name})"))?;
+
+        if let Some(move_query) = &move_query {
+            client
+                .prepare_cached(move_query)
+                .await
+                .change_context(SinkPostgresError)
+                .attach_printable_lazy(|| {
+                    format!("failed to prepare move staged data query ({name})")
+                })?;
+        }
+
+        client
+            .prepare_cached(&delete_query)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| {
+                format!("failed to prepare invalidate data query ({name})")
+            })?;
+
+        client
+            .prepare_cached(&delete_all_query)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to prepare invalidate all query ({name})"))?;
+
+        if let Some(unclamp_query) = &unclamp_query {
+            client
+                .prepare_cached(unclamp_query)
+                .await
+                .change_context(SinkPostgresError)
+                .attach_printable_lazy(|| {
+                    format!("failed to prepare reopen rows query ({name})")
+                })?;
+        }
+
+        Ok(Table {
+            name,
+            mode: config.mode,
+            cursor_column,
+            insert_query,
+            move_query,
+            copy_format: ingestion.copy_format,
+            chunk_size: ingestion.chunk_size,
+            delete_query,
+            delete_all_query,
+            unclamp_query,
+            schema,
+            invalidate,
+        })
+    }
+
+    /// Adds the columns and indexes needed to store the given rows and entities.
+    ///
+    /// Does nothing if the sink doesn't manage the table schema.
+    pub async fn migrate(
+        &mut self,
+        client: &Client,
+        rows: &[Map<String, Value>],
+        entities: &[(&Map<String, Value>, &Map<String, Value>)],
+    ) -> Result<(), SinkPostgresError> {
+        let Some(schema) = self.schema.as_mut() else {
+            return Ok(());
+        };
+
+        let values = rows.iter().chain(
+            entities
+                .iter()
+                .flat_map(|(entity, update)| [*entity, *update]),
+        );
+        schema
+            .add_missing_columns(client, &self.name, values)
+            .await?;
+
+        for (entity, _) in entities {
+            schema
+                .ensure_entity_index(client, &self.name, entity)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends the given rows to the table.
+    ///
+    /// Rows are written in chunks of at most `chunk_size` rows.
+    pub async fn insert(
+        &self,
+        txn: &Transaction<'_>,
+        end_cursor: &Cursor,
+        rows: Vec<Map<String, Value>>,
+    ) -> Result<(), SinkPostgresError> {
+        let cursor: Value = match self.cursor_column {
+            CursorColumn::Block => end_cursor.order_key.into(),
+            CursorColumn::Range => format!("[{},)", end_cursor.order_key).into(),
+        };
+
+        let rows = rows
+            .into_iter()
+            .map(|mut row| {
+                row.insert("_cursor".into(), cursor.clone());
+                row
+            })
+            .collect::<Vec<_>>();
+
+        for chunk in rows.chunks(self.chunk_size) {
+            match &self.move_query {
+                None => {
+                    let statement = self.prepare_cached(txn, &self.insert_query).await?;
+                    txn.execute(&statement, &[&Json(chunk)])
+                        .await
+                        .change_context(SinkPostgresError)
+                        .attach_printable_lazy(|| {
+                            format!("failed to run insert data query ({})", self.name)
+                        })?;
+                }
+                Some(move_query) => {
+                    let statement = self.prepare_cached(txn, move_query).await?;
+                    staging::copy_rows(txn, chunk, self.copy_format).await?;
+                    txn.execute(&statement, &[])
+                        .await
+                        .change_context(SinkPostgresError)
+                        .attach_printable_lazy(|| {
+                            format!("failed to run move staged data query ({})", self.name)
+                        })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the entity matching `entity` with the values in `update`.
+    ///
+    /// The current version of the entity is closed at `end_cursor` and a new version, valid from
+    /// `end_cursor`, is inserted. If the entity was already updated at `end_cursor`, that version
+    /// is replaced instead.
+    pub async fn update_entity(
+        &self,
+        txn: &Transaction<'_>,
+        end_cursor: &Cursor,
+        entity: &Map<String, Value>,
+        update: &Map<String, Value>,
+    ) -> Result<(), SinkPostgresError> {
+        if entity.is_empty() {
+            return Err(SinkPostgresError).attach_printable("entity must have at least one key");
+        }
+
+        // convert to i64 because that's the tokio_postgres type that maps to bigint
+        let block_number = i64::try_from(end_cursor.order_key)
+            .change_context(SinkPostgresError)
+            .attach_printable("cursor order key does not fit in bigint")?;
+
+        let condition = self.entity_condition(entity);
+        let entity_json = Json(Value::Object(entity.clone()));
+
+        let replace_query = format!(
+            "DELETE FROM {} AS _row WHERE {} AND lower(_row._cursor) = $2 RETURNING row_to_json(_row)",
+            self.name, condition
+        );
+        let mut previous = txn
+            .query_opt(&replace_query, &[&entity_json, &block_number])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to replace entity ({})", self.name))?;
+
+        if previous.is_none() {
+            let clamp_query = format!(
+                "UPDATE {} AS _row SET _cursor = int8range(lower(_row._cursor), $2) WHERE {} AND upper_inf(_row._cursor) RETURNING row_to_json(_row)",
+                self.name, condition
+            );
+            previous = txn
+                .query_opt(&clamp_query, &[&entity_json, &block_number])
+                .await
+                .change_context(SinkPostgresError)
+                .attach_printable_lazy(|| format!("failed to clamp entity ({})", self.name))?;
+        }
+
+        let mut new_row = match previous {
+            None => Map::new(),
+            Some(row) => {
+                let Json(previous): Json<Value> = row.get(0);
+                match previous {
+                    Value::Object(previous) => previous,
+                    _ => Map::new(),
+                }
+            }
+        };
+
+        new_row.extend(entity.clone());
+        new_row.extend(update.clone());
+        new_row.insert("_cursor".into(), format!("[{},)", block_number).into());
+
+        let insert_query = format!(
+            "INSERT INTO {} SELECT * FROM json_populate_record(NULL::{}, $1::json)",
+            self.name, self.name
+        );
+        txn.execute(&insert_query, &[&Json(Value::Object(new_row))])
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to insert entity ({})", self.name))?;
+
+        Ok(())
+    }
+
+    /// Invalidates all data after the given cursor.
+    pub async fn invalidate(
+        &self,
+        txn: &Transaction<'_>,
+        cursor: &Option<Cursor>,
+    ) -> Result<(), SinkPostgresError> {
+        let Some(cursor) = cursor else {
+            let statement = self.prepare_cached(txn, &self.delete_all_query).await?;
+            let params = self.invalidate_params(None);
+            txn.execute(&statement, &params)
+                .await
+                .change_context(SinkPostgresError)
+                .attach_printable_lazy(|| {
+                    format!("failed to run invalidate all data query ({})", self.name)
+                })?;
+            return Ok(());
+        };
+
+        // convert to i64 because that's the tokio_postgres type that maps to bigint
+        let block_number = i64::try_from(cursor.order_key)
+            .change_context(SinkPostgresError)
+            .attach_printable("cursor order key does not fit in bigint")?;
+        let statement = self.prepare_cached(txn, &self.delete_query).await?;
+        let params = self.invalidate_params(Some(&block_number));
+        txn.execute(&statement, &params)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| {
+                format!("failed to run invalidate data query ({})", self.name)
+            })?;
+
+        if let Some(unclamp_query) = &self.unclamp_query {
+            let statement = self.prepare_cached(txn, unclamp_query).await?;
+            txn.execute(&statement, &params)
+                .await
+                .change_context(SinkPostgresError)
+                .attach_printable_lazy(|| {
+                    format!("failed to run reopen rows query ({})", self.name)
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Prepares the query, or reuses the statement already prepared on the connection.
+    async fn prepare_cached(
+        &self,
+        txn: &Transaction<'_>,
+        query: &str,
+    ) -> Result<Statement, SinkPostgresError> {
+        txn.prepare_cached(query)
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to prepare query ({}): {query}", self.name))
+    }
+
+    /// Returns the parameters of the invalidate queries.
+    fn invalidate_params<'a>(
+        &'a self,
+        block_number: Option<&'a i64>,
+    ) -> Vec<&'a (dyn ToSql + Sync)> {
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+        if let Some(block_number) = block_number {
+            params.push(block_number);
+        }
+        params.extend(
+            self.invalidate
+                .params()
+                .map(|param| param as &(dyn ToSql + Sync)),
+        );
+        params
+    }
+
+    /// Returns the condition that matches the entity passed as the first query parameter.
+    fn entity_condition(&self, entity: &Map<String, Value>) -> String {
+        let columns = entity
+            .keys()
+            .map(|column| quote_identifier(column))
+            .collect::<Vec<_>>();
+        let row_columns = columns
+            .iter()
+            .map(|column| format!("_row.{column}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let entity_columns = columns.join(", ");
+        format!(
+            "ROW({}) = (SELECT {} FROM json_populate_record(NULL::{}, $1::json))",
+            row_columns, entity_columns, self.name
+        )
+    }
+}
+
+impl CursorColumn {
+    /// Reads the type of the `_cursor` column of the given table.
+    async fn read_from_table(client: &Client, table_name: &str) -> Result<Self, SinkPostgresError> {
+        let row = client
+            .query_opt(
+                "SELECT format_type(atttypid, atttypmod) FROM pg_attribute WHERE attrelid = $1::text::regclass AND attname = '_cursor' AND NOT attisdropped",
+                &[&table_name],
+            )
+            .await
+            .change_context(SinkPostgresError)
+            .attach_printable_lazy(|| format!("failed to read _cursor column of table {table_name}"))?
+            .ok_or(SinkPostgresError)
+            .attach_printable_lazy(|| format!("table {table_name} has no _cursor column"))?;
+
+        let column_type: String = row.get(0);
+        if column_type == "int8range" {
+            Ok(CursorColumn::Range)
+        } else {
+            Ok(CursorColumn::Block)
+        }
+    }
+}
+
+/// Quotes a SQL identifier, escaping any double quote in it.
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote_identifier;
+
+    #[test]
+    pub fn test_quote_identifier() {
+        assert_eq!(quote_identifier("col"), "\"col\"");
+        assert_eq!(quote_identifier("my col"), "\"my col\"");
+        assert_eq!(
+            quote_identifier("a\"; DROP TABLE x; --"),
+            "\"a\"\"; DROP TABLE x; --\""
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use apibara_core::node::v1alpha2::{Cursor, DataFinality};
-use apibara_sink_common::{Context, CursorAction, Sink};
+use apibara_sink_common::{Context, CursorAction, Sink, SinkHealth};
 use apibara_sink_postgres::{
-    InvalidateColumn, PostgresSink, SinkPostgresError, SinkPostgresOptions,
+    ColumnOptions, InvalidateColumn, InvalidateOperator, PostgresSink, SinkPostgresError,
+    SinkPostgresOptions, TableOptions,
 };
 use error_stack::Result;
 use serde_json::{json, Value};
@@ -151,6 +152,7 @@ async fn test_handle_data() -> Result<(), SinkPostgresError> {
     let docker = clients::Cli::default();
     let postgres = docker.run(new_postgres_image());
     let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
 
     create_test_table(port).await;
 
@@ -188,7 +190,7 @@ async fn test_handle_data() -> Result<(), SinkPostgresError> {
         assert_eq!(action, CursorAction::Persist);
     }
 
-    assert_eq!(all_rows, get_all_rows(&sink.client).await);
+    assert_eq!(all_rows, get_all_rows(&client).await);
 
     Ok(())
 }
@@ -201,6 +203,7 @@ async fn test_handle_invalidate_all(
     let docker = clients::Cli::default();
     let postgres = docker.run(new_postgres_image());
     let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
 
     create_test_table(port).await;
 
@@ -230,14 +233,14 @@ async fn test_handle_invalidate_all(
         assert_eq!(action, CursorAction::Persist);
     }
 
-    assert_eq!(expected_rows, get_all_rows(&sink.client).await);
+    assert_eq!(expected_rows, get_all_rows(&client).await);
 
-    let num_rows = get_num_rows(&sink.client).await as u64;
+    let num_rows = get_num_rows(&client).await as u64;
     assert_eq!(num_rows, batch_size * num_batches);
 
     sink.handle_invalidate(invalidate_from).await?;
 
-    assert_eq!(get_num_rows(&sink.client).await, 0);
+    assert_eq!(get_num_rows(&client).await, 0);
 
     Ok(())
 }
@@ -260,6 +263,7 @@ async fn test_handle_invalidate() -> Result<(), SinkPostgresError> {
     let docker = clients::Cli::default();
     let postgres = docker.run(new_postgres_image());
     let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
 
     create_test_table(port).await;
 
@@ -289,7 +293,7 @@ async fn test_handle_invalidate() -> Result<(), SinkPostgresError> {
         assert_eq!(action, CursorAction::Persist);
     }
 
-    assert_eq!(all_rows, get_all_rows(&sink.client).await);
+    assert_eq!(all_rows, get_all_rows(&client).await);
 
     let invalidate_from = 2;
 
@@ -301,7 +305,7 @@ async fn test_handle_invalidate() -> Result<(), SinkPostgresError> {
         .filter(|row| row.cursor <= invalidate_from)
         .collect();
 
-    assert_eq!(expected_rows, get_all_rows(&sink.client).await);
+    assert_eq!(expected_rows, get_all_rows(&client).await);
 
     Ok(())
 }
@@ -312,6 +316,7 @@ async fn test_handle_invalidate_with_additional_condition() -> Result<(), SinkPo
     let docker = clients::Cli::default();
     let postgres = docker.run(new_postgres_image());
     let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
 
     create_test_table(port).await;
 
@@ -319,10 +324,12 @@ async fn test_handle_invalidate_with_additional_condition() -> Result<(), SinkPo
         InvalidateColumn {
             column: "col1".into(),
             value: "a".into(),
+            ..Default::default()
         },
         InvalidateColumn {
             column: "col2".into(),
             value: "a".into(),
+            ..Default::default()
         },
     ];
     let mut sink = new_sink_with_invalidate(port, Some(invalidate)).await;
@@ -366,10 +373,561 @@ async fn test_handle_invalidate_with_additional_condition() -> Result<(), SinkPo
 
     sink.handle_invalidate(&Some(new_cursor(2))).await?;
 
-    let rows = get_all_rows(&sink.client).await;
+    let rows = get_all_rows(&client).await;
     // 10 rows with col1 = "a" and col2 = "b"
     // 2 rows with col1 = "a" and col2 = "a"
     assert_eq!(rows.len(), 12);
 
     Ok(())
 }
+
+#[tokio::test]
+#[ignore]
+async fn test_transactional_cursor() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+
+    create_test_table(port).await;
+
+    let options = SinkPostgresOptions {
+        connection_string: Some(format!("postgresql://postgres@localhost:{}", port)),
+        table_name: Some("test".into()),
+        no_tls: Some(true),
+        transactional: Some(true),
+        ..Default::default()
+    };
+    let mut sink = PostgresSink::from_options(options).await?;
+
+    assert!(sink.is_transactional());
+    assert_eq!(sink.get_cursor().await?, None);
+
+    let batch_size = 2;
+    let num_batches = 5;
+
+    for order_key in 0..num_batches {
+        let cursor = Some(new_cursor(order_key * batch_size));
+        let end_cursor = new_cursor((order_key + 1) * batch_size);
+        let finality = DataFinality::DataStatusFinalized;
+        let batch = new_batch(&cursor, &end_cursor);
+
+        let ctx = Context {
+            cursor,
+            end_cursor: end_cursor.clone(),
+            finality,
+        };
+
+        sink.handle_data(&ctx, &batch).await?;
+        assert_eq!(sink.get_cursor().await?, Some(end_cursor));
+    }
+
+    // Pending data doesn't update the stored cursor.
+    let ctx = Context {
+        cursor: Some(new_cursor(10)),
+        end_cursor: new_cursor(12),
+        finality: DataFinality::DataStatusPending,
+    };
+    sink.handle_data(&ctx, &new_batch(&ctx.cursor, &ctx.end_cursor))
+        .await?;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(10)));
+
+    sink.handle_invalidate(&Some(new_cursor(4))).await?;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(4)));
+
+    sink.handle_invalidate(&None).await?;
+    assert_eq!(sink.get_cursor().await?, None);
+
+    Ok(())
+}
+
+async fn new_client(port: u16) -> Client {
+    let connection_string = format!("postgresql://postgres@localhost:{}", port);
+    let (client, connection) = tokio_postgres::connect(&connection_string, NoTls)
+        .await
+        .unwrap();
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    client
+}
+
+async fn get_balances(client: &Client, at_block: i64) -> Vec<(String, i64)> {
+    let rows = client
+        .query(
+            "SELECT address, balance FROM balances WHERE _cursor @> $1 ORDER BY address",
+            &[&at_block],
+        )
+        .await
+        .unwrap();
+
+    rows.into_iter()
+        .map(|row| (row.get("address"), row.get("balance")))
+        .collect()
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_multi_table_with_entities() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
+    client
+        .batch_execute(
+            "CREATE TABLE transfers(address text, amount bigint, _cursor bigint);
+            CREATE TABLE balances(address text, balance bigint, _cursor int8range);",
+        )
+        .await
+        .unwrap();
+
+    let options = SinkPostgresOptions {
+        connection_string: Some(format!("postgresql://postgres@localhost:{}", port)),
+        no_tls: Some(true),
+        tables: Some(vec![
+            TableOptions {
+                name: "transfers".into(),
+                entity_mode: None,
+                ..Default::default()
+            },
+            TableOptions {
+                name: "balances".into(),
+                entity_mode: Some(true),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    let mut sink = PostgresSink::from_options(options).await?;
+    let finality = DataFinality::DataStatusFinalized;
+
+    let batches = [
+        json!([
+            { "table": "transfers", "data": { "address": "0xA", "amount": 10 } },
+            { "table": "balances", "entity": { "address": "0xA" }, "update": { "balance": 10 } },
+        ]),
+        json!([
+            { "table": "transfers", "data": { "address": "0xA", "amount": 5 } },
+            { "table": "transfers", "data": { "address": "0xB", "amount": 3 } },
+            { "table": "balances", "entity": { "address": "0xA" }, "update": { "balance": 15 } },
+            { "table": "balances", "entity": { "address": "0xB" }, "update": { "balance": 3 } },
+            { "table": "balances", "entity": { "address": "0xB" }, "update": { "balance": 4 } },
+        ]),
+        json!([
+            { "table": "balances", "entity": { "address": "0xA" }, "update": { "balance": 20 } },
+        ]),
+    ];
+
+    for (i, batch) in batches.iter().enumerate() {
+        let ctx = Context {
+            cursor: Some(new_cursor(i as u64)),
+            end_cursor: new_cursor(i as u64 + 1),
+            finality,
+        };
+        sink.handle_data(&ctx, batch).await?;
+    }
+
+    assert_eq!(
+        get_balances(&client, 3).await,
+        vec![("0xA".to_string(), 20), ("0xB".to_string(), 4)]
+    );
+    assert_eq!(
+        get_balances(&client, 2).await,
+        vec![("0xA".to_string(), 15), ("0xB".to_string(), 4)]
+    );
+    assert_eq!(
+        get_balances(&client, 1).await,
+        vec![("0xA".to_string(), 10)]
+    );
+
+    let rows = client
+        .query("SELECT count(*) FROM transfers", &[])
+        .await
+        .unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 3);
+
+    sink.handle_invalidate(&Some(new_cursor(1))).await?;
+
+    assert_eq!(
+        get_balances(&client, 3).await,
+        vec![("0xA".to_string(), 10)]
+    );
+
+    let rows = client
+        .query("SELECT count(*) FROM transfers", &[])
+        .await
+        .unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 1);
+
+    Ok(())
+}
+
+async fn get_column_types(client: &Client, table: &str) -> Vec<(String, String)> {
+    let rows = client
+        .query(
+            "SELECT column_name::text, data_type::text FROM information_schema.columns WHERE table_name = $1 ORDER BY column_name",
+            &[&table],
+        )
+        .await
+        .unwrap();
+
+    rows.into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_manage_schema() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
+
+    let options = SinkPostgresOptions {
+        connection_string: Some(format!("postgresql://postgres@localhost:{}", port)),
+        no_tls: Some(true),
+        manage_schema: Some(true),
+        tables: Some(vec![
+            TableOptions {
+                name: "transfers".into(),
+                columns: Some(vec![ColumnOptions {
+                    name: "amount".into(),
+                    r#type: "numeric".into(),
+                }]),
+                ..Default::default()
+            },
+            TableOptions {
+                name: "balances".into(),
+                entity_mode: Some(true),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    let mut sink = PostgresSink::from_options(options).await?;
+
+    assert_eq!(
+        get_column_types(&client, "transfers").await,
+        vec![
+            ("_cursor".to_string(), "bigint".to_string()),
+            ("amount".to_string(), "numeric".to_string()),
+        ]
+    );
+
+    let ctx = Context {
+        cursor: Some(new_cursor(0)),
+        end_cursor: new_cursor(1),
+        finality: DataFinality::DataStatusFinalized,
+    };
+    let batch = json!([
+        { "table": "transfers", "data": { "address": "0xA", "amount": 10, "memo": null } },
+        { "table": "balances", "entity": { "address": "0xA" }, "update": { "balance": 10 } },
+    ]);
+    sink.handle_data(&ctx, &batch).await?;
+
+    assert_eq!(
+        get_column_types(&client, "transfers").await,
+        vec![
+            ("_cursor".to_string(), "bigint".to_string()),
+            ("address".to_string(), "text".to_string()),
+            ("amount".to_string(), "numeric".to_string()),
+        ]
+    );
+    assert_eq!(
+        get_column_types(&client, "balances").await,
+        vec![
+            ("_cursor".to_string(), "int8range".to_string()),
+            ("address".to_string(), "text".to_string()),
+            ("balance".to_string(), "bigint".to_string()),
+        ]
+    );
+
+    let ctx = Context {
+        cursor: Some(new_cursor(1)),
+        end_cursor: new_cursor(2),
+        finality: DataFinality::DataStatusFinalized,
+    };
+    let batch = json!([
+        { "table": "transfers", "data": { "address": "0xB", "amount": 3, "memo": "hello" } },
+    ]);
+    sink.handle_data(&ctx, &batch).await?;
+
+    let rows = client
+        .query("SELECT memo FROM transfers ORDER BY _cursor", &[])
+        .await
+        .unwrap();
+    let memos: Vec<Option<String>> = rows.into_iter().map(|row| row.get(0)).collect();
+    assert_eq!(memos, vec![None, Some("hello".to_string())]);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_invalidate_with_range_cursor() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
+    client
+        .batch_execute(
+            "CREATE TABLE test(block_num int, block_str varchar(10), col1 text, col2 text, _cursor int8range);",
+        )
+        .await
+        .unwrap();
+
+    let mut sink = new_sink(port).await;
+
+    let finality = DataFinality::DataStatusFinalized;
+    for order_key in 0..5 {
+        let cursor = Some(new_cursor(order_key * 2));
+        let end_cursor = new_cursor((order_key + 1) * 2);
+        let ctx = Context {
+            cursor: cursor.clone(),
+            end_cursor: end_cursor.clone(),
+            finality,
+        };
+        let batch = new_batch(&cursor, &end_cursor);
+        sink.handle_data(&ctx, &batch).await?;
+    }
+    assert_eq!(get_num_rows(&client).await, 10);
+
+    let rows = client
+        .query("SELECT count(*) FROM test WHERE _cursor @> 4::bigint", &[])
+        .await
+        .unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 4);
+
+    sink.handle_invalidate(&Some(new_cursor(4))).await?;
+    assert_eq!(get_num_rows(&client).await, 4);
+
+    let rows = client
+        .query("SELECT count(*) FROM test WHERE upper_inf(_cursor)", &[])
+        .await
+        .unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_invalidate_with_typed_conditions() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
+
+    create_test_table(port).await;
+
+    // Rows are invalidated only if block_num >= 3 and col1 is "a" or "b".
+    let invalidate = vec![
+        InvalidateColumn {
+            column: "block_num".into(),
+            value: json!(3),
+            operator: Some(InvalidateOperator::GtEq),
+        },
+        InvalidateColumn {
+            column: "col1".into(),
+            value: json!(["a", "b"]),
+            operator: None,
+        },
+    ];
+    let mut sink = new_sink_with_invalidate(port, Some(invalidate)).await;
+
+    let finality = DataFinality::DataStatusFinalized;
+    for order_key in 0..5 {
+        let cursor = Some(new_cursor(order_key * 2));
+        let end_cursor = new_cursor((order_key + 1) * 2);
+        let ctx = Context {
+            cursor: cursor.clone(),
+            end_cursor: end_cursor.clone(),
+            finality,
+        };
+
+        for col1 in ["a", "b", "c"] {
+            let batch =
+                new_batch_with_additional_columns(&cursor, &end_cursor, Some(col1.into()), None);
+            sink.handle_data(&ctx, &batch).await?;
+        }
+    }
+    assert_eq!(get_num_rows(&client).await, 30);
+
+    sink.handle_invalidate(&Some(new_cursor(2))).await?;
+
+    // Rows with col1 = "c" and rows with block_num < 3 are kept.
+    let rows = client
+        .query(
+            "SELECT col1, count(*) FROM test GROUP BY col1 ORDER BY col1",
+            &[],
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.get::<_, String>(0), row.get::<_, i64>(1)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows,
+        vec![
+            ("a".to_string(), 3),
+            ("b".to_string(), 3),
+            ("c".to_string(), 10),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_copy() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
+
+    create_test_table(port).await;
+
+    let options = SinkPostgresOptions {
+        connection_string: Some(format!("postgresql://postgres@localhost:{}", port)),
+        table_name: Some("test".into()),
+        no_tls: Some(true),
+        copy: Some(true),
+        chunk_size: Some(3),
+        ..Default::default()
+    };
+    let mut sink = PostgresSink::from_options(options).await?;
+
+    let mut all_rows = vec![];
+    for order_key in 0..5 {
+        let cursor = Some(new_cursor(order_key * 10));
+        let end_cursor = new_cursor((order_key + 1) * 10);
+        let batch = new_batch(&cursor, &end_cursor);
+
+        all_rows.extend(new_rows(&cursor, &end_cursor));
+
+        let ctx = Context {
+            cursor,
+            end_cursor,
+            finality: DataFinality::DataStatusFinalized,
+        };
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    let mut rows = get_all_rows(&client).await;
+    rows.sort_by_key(|row| row.block_num);
+    assert_eq!(all_rows, rows);
+
+    sink.handle_invalidate(&Some(new_cursor(20))).await?;
+    assert_eq!(get_num_rows(&client).await, 20);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_csv_copy() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+    let client = new_client(port).await;
+
+    create_test_table(port).await;
+
+    let options = SinkPostgresOptions {
+        connection_string: Some(format!("postgresql://postgres@localhost:{}", port)),
+        table_name: Some("test".into()),
+        no_tls: Some(true),
+        copy: Some(true),
+        copy_format: Some("csv".into()),
+        chunk_size: Some(3),
+        ..Default::default()
+    };
+    let mut sink = PostgresSink::from_options(options).await?;
+
+    let mut all_rows = vec![];
+    for order_key in 0..5 {
+        let cursor = Some(new_cursor(order_key * 10));
+        let end_cursor = new_cursor((order_key + 1) * 10);
+        let batch = new_batch(&cursor, &end_cursor);
+
+        all_rows.extend(new_rows(&cursor, &end_cursor));
+
+        let ctx = Context {
+            cursor,
+            end_cursor,
+            finality: DataFinality::DataStatusFinalized,
+        };
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    let mut rows = get_all_rows(&client).await;
+    rows.sort_by_key(|row| row.block_num);
+    assert_eq!(all_rows, rows);
+
+    sink.handle_invalidate(&Some(new_cursor(20))).await?;
+    assert_eq!(get_num_rows(&client).await, 20);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_reconnect_after_connection_closed() -> Result<(), SinkPostgresError> {
+    let docker = clients::Cli::default();
+    let postgres = docker.run(new_postgres_image());
+    let port = postgres.get_host_port_ipv4(5432);
+
+    create_test_table(port).await;
+
+    // Copying rows and storing the cursor use per-connection state, so they are
+    // set up again on the new connection.
+    let options = SinkPostgresOptions {
+        connection_string: Some(format!("postgresql://postgres@localhost:{}", port)),
+        table_name: Some("test".into()),
+        no_tls: Some(true),
+        copy: Some(true),
+        transactional: Some(true),
+        ..Default::default()
+    };
+    let mut sink = PostgresSink::from_options(options).await?;
+    assert_eq!(sink.health(), SinkHealth::Healthy);
+
+    let finality = DataFinality::DataStatusFinalized;
+    let ctx = Context {
+        cursor: Some(new_cursor(0)),
+        end_cursor: new_cursor(2),
+        finality,
+    };
+    sink.handle_data(&ctx, &new_batch(&ctx.cursor, &ctx.end_cursor))
+        .await?;
+
+    // Terminate the sink connection from another session.
+    let client = new_client(port).await;
+    client
+        .execute(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE pid <> pg_backend_pid() AND datname = 'postgres'",
+            &[],
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    let ctx = Context {
+        cursor: Some(new_cursor(2)),
+        end_cursor: new_cursor(4),
+        finality,
+    };
+    sink.handle_data(&ctx, &new_batch(&ctx.cursor, &ctx.end_cursor))
+        .await?;
+
+    assert_eq!(sink.health(), SinkHealth::Healthy);
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(4)));
+    assert_eq!(get_num_rows(&client).await, 4);
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use apibara_sink_common::SinkOptions;
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use serde::Deserialize;
+
+use crate::sink::SinkSqliteError;
+
+#[derive(Debug)]
+pub struct SinkSqliteConfiguration {
+    /// Path to the database file.
+    pub database: PathBuf,
+    pub table_name: String,
+    /// Identifier of the cursor row in the `_apibara_cursors` table.
+    pub cursor_id: String,
+}
+
+#[derive(Debug, Args, Default, SinkOptions)]
+#[sink_options(tag = "sqlite")]
+pub struct SinkSqliteOptions {
+    /// Path to the SQLite database file. The file is created if it doesn't exist.
+    #[arg(long, env = "SQLITE_DATABASE")]
+    pub database: Option<String>,
+    /// Target table name.
+    ///
+    /// The table is created if it doesn't exist, and columns are added for new fields.
+    #[arg(long, env = "SQLITE_TABLE_NAME")]
+    pub table_name: Option<String>,
+    /// Identifier of the cursor row in the `_apibara_cursors` table. Defaults to `default`.
+    ///
+    /// Indexers writing to the same database must use different identifiers.
+    #[arg(long, env = "SQLITE_CURSOR_ID")]
+    pub cursor_id: Option<String>,
+}
+
+impl SinkOptions for SinkSqliteOptions {
+    fn merge(self, other: SinkSqliteOptions) -> Self {
+        Self {
+            database: self.database.or(other.database),
+            table_name: self.table_name.or(other.table_name),
+            cursor_id: self.cursor_id.or(other.cursor_id),
+        }
+    }
+}
+
+impl SinkSqliteOptions {
+    pub fn to_sqlite_configuration(self) -> Result<SinkSqliteConfiguration, SinkSqliteError> {
+        let database = self
+            .database
+            .ok_or(SinkSqliteError)
+            .attach_printable("missing database path")?;
+        let table_name = self
+            .table_name
+            .ok_or(SinkSqliteError)
+            .attach_printable("missing table name")?;
+        if table_name.is_empty() {
+            return Err(SinkSqliteError).attach_printable("table name must not be empty");
+        }
+
+        Ok(SinkSqliteConfiguration {
+            database: PathBuf::from(database),
+            table_name,
+            cursor_id: self.cursor_id.unwrap_or_else(|| "default".to_string()),
+        })
+    }
+}
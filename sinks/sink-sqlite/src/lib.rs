@@ -0,0 +1,6 @@
+mod configuration;
+mod sink;
+mod table;
+
+pub use self::configuration::{SinkSqliteConfiguration, SinkSqliteOptions};
+pub use self::sink::{SinkSqliteError, SqliteSink};
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use apibara_core::node::v1alpha2::{Cursor, DataFinality};
+use apibara_sink_common::{Context, CursorAction, DisplayCursor, Sink, ValueExt};
+use async_trait::async_trait;
+use error_stack::{Report, Result, ResultExt};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::configuration::SinkSqliteConfiguration;
+use crate::table::Table;
+use crate::SinkSqliteOptions;
+
+/// How long writes wait for other connections, for example a shell, to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct SinkSqliteError;
+impl error_stack::Context for SinkSqliteError {}
+
+impl fmt::Display for SinkSqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sqlite sink operation failed")
+    }
+}
+
+/// Writes data to a table in a SQLite database.
+///
+/// The cursor is stored in the same database, in the same transaction as the data.
+pub struct SqliteSink {
+    connection: Arc<Mutex<Connection>>,
+    table: Table,
+    cursor_id: String,
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    type Options = SinkSqliteOptions;
+    type Error = SinkSqliteError;
+
+    async fn from_options(options: Self::Options) -> Result<Self, Self::Error> {
+        let config = options.to_sqlite_configuration()?;
+        info!(database = ?config.database, "opening database");
+
+        let cursor_id = config.cursor_id.clone();
+        let (connection, table) = run_blocking(move || open_database(&config)).await?;
+
+        info!("database opened successfully");
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            table,
+            cursor_id,
+        })
+    }
+
+    async fn handle_data(
+        &mut self,
+        ctx: &Context,
+        batch: &Value,
+    ) -> Result<CursorAction, Self::Error> {
+        debug!(ctx = %ctx, "handling data");
+
+        let Some(batch) = batch.as_array_of_objects() else {
+            warn!("data is not an array of objects, skipping");
+            return Ok(CursorAction::Persist);
+        };
+
+        if batch.is_empty() {
+            return Ok(CursorAction::Persist);
+        }
+
+        let table = self.table.clone();
+        let cursor_id = self.cursor_id.clone();
+        let ctx = ctx.clone();
+        let batch = batch.clone();
+        self.with_transaction(move |txn| {
+            let rows = batch
+                .iter()
+                // Safety: we know that the batch is an array of objects
+                .map(|item| item.as_object().expect("value is an object"))
+                .collect::<Vec<_>>();
+            table.insert(txn, &ctx.end_cursor, &rows)?;
+
+            // Pending data is invalidated by the next message, so its cursor is never stored.
+            if ctx.finality != DataFinality::DataStatusPending {
+                put_cursor(txn, &cursor_id, &ctx.end_cursor)?;
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        Ok(CursorAction::Persist)
+    }
+
+    async fn handle_invalidate(&mut self, cursor: &Option<Cursor>) -> Result<(), Self::Error> {
+        debug!(cursor = %DisplayCursor(cursor), "handling invalidate");
+
+        let table = self.table.clone();
+        let cursor_id = self.cursor_id.clone();
+        let cursor = cursor.clone();
+        self.with_transaction(move |txn| {
+            table.invalidate(txn, &cursor)?;
+            match &cursor {
+                None => delete_cursor(txn, &cursor_id),
+                Some(cursor) => put_cursor(txn, &cursor_id, cursor),
+            }
+        })
+        .await
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+
+    async fn get_cursor(&mut self) -> Result<Option<Cursor>, Self::Error> {
+        let connection = self.connection.clone();
+        let cursor_id = self.cursor_id.clone();
+        run_blocking(move || {
+            let connection = lock(&connection)?;
+            let row = connection
+                .query_row(
+                    "SELECT order_key, unique_key FROM _apibara_cursors WHERE id = ?1",
+                    params![cursor_id],
+                    |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)),
+                )
+                .optional()
+                .change_context(SinkSqliteError)
+                .attach_printable("failed to read cursor")?;
+
+            let Some((order_key, unique_key)) = row else {
+                return Ok(None);
+            };
+
+            let order_key = u64::try_from(order_key)
+                .change_context(SinkSqliteError)
+                .attach_printable("stored cursor has a negative order key")?;
+
+            Ok(Some(Cursor {
+                order_key,
+                unique_key,
+            }))
+        })
+        .await
+    }
+}
+
+impl SqliteSink {
+    /// Runs `f` in a transaction, committing it if `f` succeeds.
+    ///
+    /// SQLite calls are blocking, so they run on the blocking thread pool.
+    async fn with_transaction<F>(&self, f: F) -> Result<(), SinkSqliteError>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<(), SinkSqliteError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        run_blocking(move || {
+            let mut connection = lock(&connection)?;
+            let txn = connection
+                .transaction()
+                .change_context(SinkSqliteError)
+                .attach_printable("failed to start transaction")?;
+            // The transaction is rolled back when dropped.
+            f(&txn)?;
+            txn.commit()
+                .change_context(SinkSqliteError)
+                .attach_printable("failed to commit transaction")
+        })
+        .await
+    }
+}
+
+/// Opens the database and creates the target and cursors tables.
+fn open_database(config: &SinkSqliteConfiguration) -> Result<(Connection, Table), SinkSqliteError> {
+    let connection = Connection::open(&config.database)
+        .change_context(SinkSqliteError)
+        .attach_printable_lazy(|| format!("failed to open database {:?}", config.database))?;
+
+    connection
+        .busy_timeout(BUSY_TIMEOUT)
+        .change_context(SinkSqliteError)
+        .attach_printable("failed to set busy timeout")?;
+
+    // Write-ahead logging lets other processes read the database while the sink writes to it.
+    connection
+        .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+        .change_context(SinkSqliteError)
+        .attach_printable("failed to enable write-ahead logging")?;
+
+    connection
+        .execute(
+            "CREATE TABLE IF NOT EXISTS _apibara_cursors(id TEXT PRIMARY KEY, order_key INTEGER NOT NULL, unique_key BLOB NOT NULL)",
+            (),
+        )
+        .change_context(SinkSqliteError)
+        .attach_printable("failed to create cursors table")?;
+
+    let table = Table::prepare(&connection, &config.table_name)?;
+
+    Ok((connection, table))
+}
+
+fn put_cursor(
+    txn: &Transaction<'_>,
+    cursor_id: &str,
+    cursor: &Cursor,
+) -> Result<(), SinkSqliteError> {
+    let order_key = i64::try_from(cursor.order_key)
+        .change_context(SinkSqliteError)
+        .attach_printable("cursor order key does not fit in a 64-bit integer")?;
+    txn.execute(
+        "INSERT INTO _apibara_cursors(id, order_key, unique_key) VALUES (?1, ?2, ?3) \
+        ON CONFLICT (id) DO UPDATE SET order_key = excluded.order_key, unique_key = excluded.unique_key",
+        params![cursor_id, order_key, cursor.unique_key],
+    )
+    .change_context(SinkSqliteError)
+    .attach_printable("failed to store cursor")?;
+    Ok(())
+}
+
+fn delete_cursor(txn: &Transaction<'_>, cursor_id: &str) -> Result<(), SinkSqliteError> {
+    txn.execute(
+        "DELETE FROM _apibara_cursors WHERE id = ?1",
+        params![cursor_id],
+    )
+    .change_context(SinkSqliteError)
+    .attach_printable("failed to delete cursor")?;
+    Ok(())
+}
+
+fn lock(
+    connection: &Mutex<Connection>,
+) -> Result<std::sync::MutexGuard<'_, Connection>, SinkSqliteError> {
+    connection
+        .lock()
+        .map_err(|_| Report::new(SinkSqliteError).attach_printable("database connection poisoned"))
+}
+
+async fn run_blocking<T, F>(f: F) -> Result<T, SinkSqliteError>
+where
+    F: FnOnce() -> Result<T, SinkSqliteError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .change_context(SinkSqliteError)
+        .attach_printable("failed to join database task")?
+}
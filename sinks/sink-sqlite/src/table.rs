@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use apibara_core::node::v1alpha2::Cursor;
+use error_stack::{Result, ResultExt};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, Transaction};
+use serde_json::{Map, Value};
+
+use crate::sink::SinkSqliteError;
+
+/// The table where data is written.
+///
+/// The table is created if it doesn't exist. Since SQLite columns are dynamically typed,
+/// a column without a declared type is added for each new field in the data.
+#[derive(Debug, Clone)]
+pub struct Table {
+    name: String,
+    quoted_name: String,
+}
+
+impl Table {
+    /// Creates the table and its `_cursor` index, if needed.
+    pub fn prepare(conn: &Connection, name: &str) -> Result<Self, SinkSqliteError> {
+        let quoted_name = quote_identifier(name);
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {quoted_name} (_cursor INTEGER NOT NULL); \
+            CREATE INDEX IF NOT EXISTS {} ON {quoted_name} (_cursor);",
+            quote_identifier(&format!("{name}_cursor_idx")),
+        ))
+        .change_context(SinkSqliteError)
+        .attach_printable_lazy(|| format!("failed to create table {name}"))?;
+
+        let table = Table {
+            name: name.to_string(),
+            quoted_name,
+        };
+
+        if !table.columns(conn)?.contains("_cursor") {
+            return Err(SinkSqliteError)
+                .attach_printable_lazy(|| format!("table {name} has no _cursor column"));
+        }
+
+        Ok(table)
+    }
+
+    /// Appends the given rows to the table, adding columns for new fields.
+    pub fn insert(
+        &self,
+        txn: &Transaction<'_>,
+        end_cursor: &Cursor,
+        rows: &[&Map<String, Value>],
+    ) -> Result<(), SinkSqliteError> {
+        let cursor = i64::try_from(end_cursor.order_key)
+            .change_context(SinkSqliteError)
+            .attach_printable("cursor order key does not fit in a 64-bit integer")?;
+
+        let mut columns = self.columns(txn)?;
+        for row in rows {
+            for name in row.keys() {
+                if !columns.contains(name) {
+                    self.add_column(txn, name)?;
+                    columns.insert(name.clone());
+                }
+            }
+        }
+
+        for row in rows {
+            // The `_cursor` field is always replaced by the cursor of the batch.
+            let fields = row
+                .iter()
+                .filter(|(name, _)| *name != "_cursor")
+                .collect::<Vec<_>>();
+
+            let column_names = fields
+                .iter()
+                .map(|(name, _)| quote_identifier(name))
+                .chain(["_cursor".to_string()])
+                .collect::<Vec<_>>();
+            let placeholders = (1..=column_names.len())
+                .map(|index| format!("?{index}"))
+                .collect::<Vec<_>>();
+            let query = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                self.quoted_name,
+                column_names.join(", "),
+                placeholders.join(", ")
+            );
+
+            let values = fields
+                .iter()
+                .map(|(_, value)| to_sql_value(value))
+                .chain([SqlValue::Integer(cursor)]);
+
+            let mut statement = txn
+                .prepare_cached(&query)
+                .change_context(SinkSqliteError)
+                .attach_printable_lazy(|| {
+                    format!("failed to prepare insert query ({})", self.name)
+                })?;
+            statement
+                .execute(params_from_iter(values))
+                .change_context(SinkSqliteError)
+                .attach_printable_lazy(|| format!("failed to insert data ({})", self.name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Invalidates all data after the given cursor.
+    pub fn invalidate(
+        &self,
+        txn: &Transaction<'_>,
+        cursor: &Option<Cursor>,
+    ) -> Result<(), SinkSqliteError> {
+        let Some(cursor) = cursor else {
+            txn.execute(&format!("DELETE FROM {}", self.quoted_name), ())
+                .change_context(SinkSqliteError)
+                .attach_printable_lazy(|| {
+                    format!("failed to run invalidate all data query ({})", self.name)
+                })?;
+            return Ok(());
+        };
+
+        let block_number = i64::try_from(cursor.order_key)
+            .change_context(SinkSqliteError)
+            .attach_printable("cursor order key does not fit in a 64-bit integer")?;
+        txn.execute(
+            &format!("DELETE FROM {} WHERE _cursor > ?1", self.quoted_name),
+            params![block_number],
+        )
+        .change_context(SinkSqliteError)
+        .attach_printable_lazy(|| format!("failed to run invalidate data query ({})", self.name))?;
+
+        Ok(())
+    }
+
+    /// Returns the names of the table columns.
+    fn columns(&self, conn: &Connection) -> Result<HashSet<String>, SinkSqliteError> {
+        let mut statement = conn
+            .prepare(&format!("PRAGMA table_info({})", self.quoted_name))
+            .change_context(SinkSqliteError)
+            .attach_printable_lazy(|| format!("failed to read columns of table {}", self.name))?;
+        let columns = statement
+            .query_map((), |row| row.get::<_, String>("name"))
+            .and_then(|rows| rows.collect::<rusqlite::Result<HashSet<_>>>())
+            .change_context(SinkSqliteError)
+            .attach_printable_lazy(|| format!("failed to read columns of table {}", self.name))?;
+        Ok(columns)
+    }
+
+    fn add_column(&self, txn: &Transaction<'_>, name: &str) -> Result<(), SinkSqliteError> {
+        txn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {}",
+                self.quoted_name,
+                quote_identifier(name)
+            ),
+            (),
+        )
+        .change_context(SinkSqliteError)
+        .attach_printable_lazy(|| format!("failed to add column {name} to table {}", self.name))?;
+        Ok(())
+    }
+}
+
+/// Converts a JSON value to the value stored in the database.
+///
+/// Arrays and objects are stored as JSON text, so they can be queried with the SQLite
+/// JSON functions.
+fn to_sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(value) => SqlValue::Integer(i64::from(*value)),
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => SqlValue::Integer(number),
+            // Integers that don't fit in an i64 are stored as text to avoid losing precision.
+            None if number.is_u64() => SqlValue::Text(number.to_string()),
+            None => SqlValue::Real(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(value) => SqlValue::Text(value.clone()),
+        value => SqlValue::Text(value.to_string()),
+    }
+}
+
+/// Quotes a SQL identifier, escaping any double quote in it.
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::Value as SqlValue;
+    use serde_json::json;
+
+    use super::to_sql_value;
+
+    #[test]
+    pub fn test_to_sql_value() {
+        assert_eq!(to_sql_value(&json!(null)), SqlValue::Null);
+        assert_eq!(to_sql_value(&json!(true)), SqlValue::Integer(1));
+        assert_eq!(to_sql_value(&json!(-42)), SqlValue::Integer(-42));
+        assert_eq!(
+            to_sql_value(&json!(u64::MAX)),
+            SqlValue::Text(u64::MAX.to_string())
+        );
+        assert_eq!(to_sql_value(&json!(1.5)), SqlValue::Real(1.5));
+        assert_eq!(
+            to_sql_value(&json!("0xabc")),
+            SqlValue::Text("0xabc".to_string())
+        );
+        assert_eq!(
+            to_sql_value(&json!({ "amount": [1, 2] })),
+            SqlValue::Text(r#"{"amount":[1,2]}"#.to_string())
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::Path;
+
+use apibara_core::node::v1alpha2::{Cursor, DataFinality};
+use apibara_sink_common::{Context, CursorAction, Sink};
+use apibara_sink_sqlite::{SinkSqliteError, SinkSqliteOptions, SqliteSink};
+use error_stack::Result;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+fn new_cursor(order_key: u64) -> Cursor {
+    Cursor {
+        order_key,
+        unique_key: order_key.to_be_bytes().to_vec(),
+    }
+}
+
+fn new_batch(start_cursor: &Option<Cursor>, end_cursor: &Cursor) -> Value {
+    let start_block_num = match start_cursor {
+        Some(cursor) => cursor.order_key,
+        None => 0,
+    };
+
+    let batch = (start_block_num..end_cursor.order_key)
+        .map(|i| {
+            json!({
+                "block_num": i,
+                "block_str": format!("block_{}", i),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!(batch)
+}
+
+fn new_context(start: u64, end: u64, finality: DataFinality) -> Context {
+    Context {
+        cursor: Some(new_cursor(start)),
+        end_cursor: new_cursor(end),
+        finality,
+    }
+}
+
+async fn new_sink(database: &Path) -> SqliteSink {
+    let options = SinkSqliteOptions {
+        database: Some(database.to_string_lossy().to_string()),
+        table_name: Some("test".into()),
+        ..Default::default()
+    };
+    SqliteSink::from_options(options).await.unwrap()
+}
+
+/// Returns the `(_cursor, block_num, block_str)` rows of the test table.
+fn get_all_rows(database: &Path) -> Vec<(i64, i64, String)> {
+    let conn = Connection::open(database).unwrap();
+    let mut statement = conn
+        .prepare("SELECT _cursor, block_num, block_str FROM test ORDER BY block_num")
+        .unwrap();
+    let rows = statement
+        .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+#[tokio::test]
+async fn test_handle_data() -> Result<(), SinkSqliteError> {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("test.db");
+    let mut sink = new_sink(&database).await;
+
+    let mut expected_rows = Vec::new();
+    for order_key in 0..5 {
+        let ctx = new_context(
+            order_key * 2,
+            (order_key + 1) * 2,
+            DataFinality::DataStatusFinalized,
+        );
+        let batch = new_batch(&ctx.cursor, &ctx.end_cursor);
+        for i in order_key * 2..(order_key + 1) * 2 {
+            expected_rows.push(((order_key as i64 + 1) * 2, i as i64, format!("block_{i}")));
+        }
+
+        let action = sink.handle_data(&ctx, &batch).await?;
+        assert_eq!(action, CursorAction::Persist);
+
+        let action = sink
+            .handle_data(&ctx, &json!([0, { "key": "value" }]))
+            .await?;
+        assert_eq!(action, CursorAction::Persist);
+
+        let action = sink.handle_data(&ctx, &json!([])).await?;
+        assert_eq!(action, CursorAction::Persist);
+    }
+
+    assert_eq!(get_all_rows(&database), expected_rows);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_data_adds_columns() -> Result<(), SinkSqliteError> {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("test.db");
+    let mut sink = new_sink(&database).await;
+
+    let ctx = new_context(0, 1, DataFinality::DataStatusFinalized);
+    let batch = json!([
+        { "block_num": 0, "block_str": "block_0", "amount": "1000", "_cursor": 42 },
+        { "block_num": 0, "block_str": "block_0", "transfer": { "from": "0x1", "to": "0x2" } },
+    ]);
+    sink.handle_data(&ctx, &batch).await?;
+
+    let conn = Connection::open(&database).unwrap();
+    let (cursor, amount, from): (i64, Option<String>, Option<String>) = conn
+        .query_row(
+            "SELECT max(_cursor), max(amount), max(json_extract(transfer, '$.from')) FROM test",
+            (),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(cursor, 1);
+    assert_eq!(amount.as_deref(), Some("1000"));
+    assert_eq!(from.as_deref(), Some("0x1"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handle_invalidate() -> Result<(), SinkSqliteError> {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("test.db");
+    let mut sink = new_sink(&database).await;
+
+    for order_key in 0..5 {
+        let ctx = new_context(
+            order_key * 2,
+            (order_key + 1) * 2,
+            DataFinality::DataStatusFinalized,
+        );
+        let batch = new_batch(&ctx.cursor, &ctx.end_cursor);
+        sink.handle_data(&ctx, &batch).await?;
+    }
+
+    sink.handle_invalidate(&Some(new_cursor(4))).await?;
+    let rows = get_all_rows(&database);
+    assert_eq!(rows.len(), 4);
+    assert!(rows.iter().all(|(cursor, _, _)| *cursor <= 4));
+
+    sink.handle_invalidate(&Some(new_cursor(0))).await?;
+    assert!(get_all_rows(&database).is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cursor() -> Result<(), SinkSqliteError> {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("test.db");
+    let mut sink = new_sink(&database).await;
+
+    assert!(sink.is_transactional());
+    assert_eq!(sink.get_cursor().await?, None);
+
+    for order_key in 0..5 {
+        let ctx = new_context(
+            order_key * 2,
+            (order_key + 1) * 2,
+            DataFinality::DataStatusFinalized,
+        );
+        let batch = new_batch(&ctx.cursor, &ctx.end_cursor);
+        sink.handle_data(&ctx, &batch).await?;
+        assert_eq!(sink.get_cursor().await?, Some(ctx.end_cursor));
+    }
+
+    // Pending data doesn't update the stored cursor.
+    let ctx = new_context(10, 12, DataFinality::DataStatusPending);
+    sink.handle_data(&ctx, &new_batch(&ctx.cursor, &ctx.end_cursor))
+        .await?;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(10)));
+
+    sink.handle_invalidate(&Some(new_cursor(4))).await?;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(4)));
+
+    // The cursor is read back after restarting.
+    drop(sink);
+    let mut sink = new_sink(&database).await;
+    assert_eq!(sink.get_cursor().await?, Some(new_cursor(4)));
+    assert_eq!(get_all_rows(&database).len(), 4);
+
+    sink.handle_invalidate(&None).await?;
+    assert_eq!(sink.get_cursor().await?, None);
+    assert!(get_all_rows(&database).is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use error_stack::{Result, ResultExt};
+use hmac::{Hmac, Mac};
+use http::{HeaderName, HeaderValue};
+use sha2::Sha256;
+
+use crate::sink::SinkWebhookError;
+
+/// Default name of the header containing the request signature.
+pub const DEFAULT_SIGNATURE_HEADER: &str = "x-apibara-signature";
+
+/// Signs request bodies with HMAC-SHA256.
+///
+/// The signature header has the format `t=<timestamp>,v1=<signature>`, where the
+/// signature is the hex-encoded HMAC of `<timestamp>.<body>` and the timestamp is in
+/// seconds since the unix epoch. Receivers should reject requests with an old timestamp
+/// to prevent replays.
+#[derive(Debug, Clone)]
+pub struct RequestSigner {
+    pub secret: Vec<u8>,
+    pub header: HeaderName,
+}
+
+impl RequestSigner {
+    /// Returns the name and value of the signature header for the given body.
+    pub fn sign(&self, body: &[u8]) -> Result<(HeaderName, HeaderValue), SinkWebhookError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .change_context(SinkWebhookError)
+            .attach_printable("system time is before unix epoch")?
+            .as_secs();
+        let value = self.signature_header_value(timestamp, body)?;
+        Ok((self.header.clone(), value))
+    }
+
+    fn signature_header_value(
+        &self,
+        timestamp: u64,
+        body: &[u8],
+    ) -> Result<HeaderValue, SinkWebhookError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .change_context(SinkWebhookError)
+            .attach_printable("invalid signing secret")?;
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        format!("t={timestamp},v1={signature}")
+            .parse::<HeaderValue>()
+            .change_context(SinkWebhookError)
+            .attach_printable("failed to create signature header")
+    }
+}
+
+/// A bearer token read from a file.
+///
+/// The file is read again whenever its modification time or size changes, so that the
+/// token can be rotated without restarting the sink.
+#[derive(Debug)]
+pub struct BearerTokenFile {
+    path: PathBuf,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    modified: SystemTime,
+    len: u64,
+    value: HeaderValue,
+}
+
+impl BearerTokenFile {
+    pub fn new(path: PathBuf) -> Self {
+        BearerTokenFile {
+            path,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the value of the `Authorization` header.
+    pub async fn authorization(&self) -> Result<HeaderValue, SinkWebhookError> {
+        let metadata = tokio::fs::metadata(&self.path)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to read token file {:?}", self.path))?;
+        let modified = metadata
+            .modified()
+            .change_context(SinkWebhookError)
+            .attach_printable("failed to read token file modification time")?;
+        let len = metadata.len();
+
+        if let Some(cached) = self.cached.lock().expect("token cache lock").as_ref() {
+            if cached.modified == modified && cached.len == len {
+                return Ok(cached.value.clone());
+            }
+        }
+
+        let token = tokio::fs::read_to_string(&self.path)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to read token file {:?}", self.path))?;
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(SinkWebhookError)
+                .attach_printable_lazy(|| format!("token file {:?} is empty", self.path));
+        }
+
+        let mut value = format!("Bearer {token}")
+            .parse::<HeaderValue>()
+            .change_context(SinkWebhookError)
+            .attach_printable("bearer token is not a valid header value")?;
+        value.set_sensitive(true);
+
+        *self.cached.lock().expect("token cache lock") = Some(CachedToken {
+            modified,
+            len,
+            value: value.clone(),
+        });
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestSigner, DEFAULT_SIGNATURE_HEADER};
+
+    #[test]
+    pub fn test_signature_header_value() {
+        let signer = RequestSigner {
+            secret: b"secret".to_vec(),
+            header: DEFAULT_SIGNATURE_HEADER.parse().unwrap(),
+        };
+
+        let value = signer
+            .signature_header_value(1692057600, br#"{"data":1}"#)
+            .unwrap();
+        assert_eq!(
+            value.to_str().unwrap(),
+            "t=1692057600,v1=91672dee97bf91df1ca7487341c948715a00def7e1ba4f3ed228f76d731713a0"
+        );
+
+        // The timestamp is part of the signed payload.
+        let other = signer
+            .signature_header_value(1692057601, br#"{"data":1}"#)
+            .unwrap();
+        assert!(!other
+            .to_str()
+            .unwrap()
+            .ends_with("v1=91672dee97bf91df1ca7487341c948715a00def7e1ba4f3ed228f76d731713a0"));
+    }
+}
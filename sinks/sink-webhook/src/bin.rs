@@ -1,13 +1,15 @@
+use std::path::PathBuf;
 use std::process::ExitCode;
 
 use apibara_sink_common::{
     apibara_cli_style, initialize_sink, run_sink_connector, OptionsFromCli, ReportExt,
     SinkConnectorError,
 };
-use apibara_sink_webhook::{SinkWebhookOptions, WebhookSink};
+use apibara_sink_webhook::{replay_dead_letters, SinkWebhookOptions, WebhookSink};
 use clap::{Args, Parser, Subcommand};
-use error_stack::Result;
+use error_stack::{Result, ResultExt};
 use tokio_util::sync::CancellationToken;
+use tracing::info;
 
 #[cfg(not(windows))]
 #[global_allocator]
@@ -23,6 +25,8 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Command {
     Run(RunArgs),
+    /// Send the requests stored in the dead-letter queue again.
+    ReplayDeadLetters(ReplayDeadLettersArgs),
 }
 
 #[derive(Args, Debug)]
@@ -35,6 +39,14 @@ struct RunArgs {
     common: OptionsFromCli,
 }
 
+#[derive(Args, Debug)]
+struct ReplayDeadLettersArgs {
+    /// The dead-letter file, or the directory containing the dead-letter files.
+    path: PathBuf,
+    #[command(flatten)]
+    webhook: SinkWebhookOptions,
+}
+
 #[tokio::main]
 async fn main() -> ExitCode {
     let args = Cli::parse();
@@ -49,5 +61,25 @@ async fn run_with_args(args: Cli) -> Result<(), SinkConnectorError> {
         Command::Run(args) => {
             run_sink_connector::<WebhookSink>(&args.script, args.common, args.webhook, ct).await
         }
+        Command::ReplayDeadLetters(args) => {
+            let config = args
+                .webhook
+                .to_webhook_configuration()
+                .change_context(SinkConnectorError::Configuration)?;
+            let sink = WebhookSink::new(config);
+            let summary = replay_dead_letters(&sink, &args.path)
+                .await
+                .change_context(SinkConnectorError::Fatal)?;
+            info!(
+                delivered = summary.delivered,
+                failed = summary.failed,
+                "dead letters replayed"
+            );
+            if summary.failed > 0 {
+                return Err(SinkConnectorError::Temporary)
+                    .attach_printable("some dead letters could not be delivered");
+            }
+            Ok(())
+        }
     }
 }
@@ -1,16 +1,80 @@
+use std::path::PathBuf;
+
 use apibara_sink_common::SinkOptions;
 use clap::Args;
 use error_stack::{Result, ResultExt};
 use http::{HeaderMap, HeaderName, HeaderValue, Uri};
 use serde::Deserialize;
 
+use crate::auth::{RequestSigner, DEFAULT_SIGNATURE_HEADER};
+use crate::retry::DEFAULT_RETRY_STATUS_CODES;
 use crate::sink::SinkWebhookError;
 
+/// Number of times a request is retried before it's stored in the dead-letter queue, if
+/// not configured.
+const DEFAULT_DEAD_LETTER_RETRIES: u32 = 3;
+
 #[derive(Debug)]
 pub struct SinkWebhookConfiguration {
     pub target_url: Uri,
     pub headers: HeaderMap,
     pub raw: bool,
+    /// Send each item as described by its `{ url, method, headers, body }` envelope.
+    ///
+    /// Implies raw mode.
+    pub envelope: bool,
+    /// Sign the request body, if set.
+    pub signer: Option<RequestSigner>,
+    /// Send the bearer token stored in this file, if set.
+    pub bearer_token_file: Option<PathBuf>,
+    /// Client error status codes that are retried. Server errors are always retried.
+    pub retry_status_codes: Vec<u16>,
+    /// How items are delivered in raw mode.
+    pub delivery: DeliveryConfiguration,
+    /// Store requests that can't be delivered in this directory, if set.
+    pub dead_letter_dir: Option<PathBuf>,
+}
+
+/// How the items returned by the transform script are delivered in raw mode.
+#[derive(Debug, Clone)]
+pub struct DeliveryConfiguration {
+    /// Maximum number of concurrent requests.
+    pub concurrency: usize,
+    /// Start requests in the order of the items.
+    ///
+    /// With a concurrency of 1, items are delivered strictly in order.
+    pub ordered: bool,
+    /// Number of times a request is retried before failing the whole batch.
+    pub retries: u32,
+    /// Number of items sent in each request. If greater than 1, items are sent as an array.
+    pub items_per_request: usize,
+}
+
+impl Default for DeliveryConfiguration {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            ordered: true,
+            retries: 0,
+            items_per_request: 1,
+        }
+    }
+}
+
+impl Default for SinkWebhookConfiguration {
+    fn default() -> Self {
+        Self {
+            target_url: Uri::default(),
+            headers: HeaderMap::default(),
+            raw: false,
+            envelope: false,
+            signer: None,
+            bearer_token_file: None,
+            retry_status_codes: DEFAULT_RETRY_STATUS_CODES.to_vec(),
+            delivery: DeliveryConfiguration::default(),
+            dead_letter_dir: None,
+        }
+    }
 }
 
 #[derive(Debug, Args, Default, SinkOptions)]
@@ -29,6 +93,65 @@ pub struct SinkWebhookOptions {
     /// Use this to interact with any API like Discord or Telegram.
     #[arg(long, action, env = "WEBHOOK_RAW")]
     raw: Option<bool>,
+
+    /// Send each item returned by the transform step as described by its
+    /// `{ url, method, headers, body }` envelope.
+    ///
+    /// Implies raw mode. The target url and headers are used as defaults.
+    #[arg(long, action, env = "WEBHOOK_ENVELOPE")]
+    envelope: Option<bool>,
+
+    /// Sign the request body with HMAC-SHA256 using this secret.
+    #[arg(long, env = "WEBHOOK_SIGNING_SECRET")]
+    signing_secret: Option<String>,
+
+    /// The header containing the request signature.
+    ///
+    /// Defaults to `x-apibara-signature`.
+    #[arg(long, env = "WEBHOOK_SIGNATURE_HEADER")]
+    signature_header: Option<String>,
+
+    /// Send the bearer token stored in this file in the `Authorization` header.
+    ///
+    /// The file is read again when it changes.
+    #[arg(long, env = "WEBHOOK_BEARER_TOKEN_FILE")]
+    bearer_token_file: Option<String>,
+
+    /// Client error (4xx) status codes that are retried.
+    ///
+    /// Server errors (5xx) are always retried, other client errors stop the indexer.
+    /// Defaults to 408, 425 and 429.
+    #[arg(long, value_delimiter = ',', env = "WEBHOOK_RETRY_STATUS_CODES")]
+    retry_status_codes: Option<Vec<u16>>,
+
+    /// Maximum number of concurrent requests in raw mode.
+    #[arg(long, env = "WEBHOOK_CONCURRENCY")]
+    concurrency: Option<usize>,
+
+    /// Start requests in any order in raw mode.
+    ///
+    /// By default, requests are started in the order of the items.
+    #[arg(long, action, env = "WEBHOOK_UNORDERED")]
+    unordered: Option<bool>,
+
+    /// Number of times each request is retried in raw mode before failing the batch.
+    ///
+    /// Defaults to 0, or to 3 when the dead-letter queue is enabled.
+    #[arg(long, env = "WEBHOOK_ITEM_RETRIES")]
+    item_retries: Option<u32>,
+
+    /// Number of items sent in each request in raw mode.
+    ///
+    /// If greater than 1, the items are sent as an array.
+    #[arg(long, env = "WEBHOOK_ITEMS_PER_REQUEST")]
+    items_per_request: Option<usize>,
+
+    /// Store requests that can't be delivered in this directory instead of stopping
+    /// the indexer.
+    ///
+    /// Requests are stored after they're retried `item-retries` times.
+    #[arg(long, env = "WEBHOOK_DEAD_LETTER_DIR")]
+    dead_letter_dir: Option<String>,
 }
 
 impl SinkOptions for SinkWebhookOptions {
@@ -37,6 +160,16 @@ impl SinkOptions for SinkWebhookOptions {
             target_url: self.target_url.or(other.target_url),
             header: self.header.or(other.header),
             raw: self.raw.or(other.raw),
+            envelope: self.envelope.or(other.envelope),
+            signing_secret: self.signing_secret.or(other.signing_secret),
+            signature_header: self.signature_header.or(other.signature_header),
+            bearer_token_file: self.bearer_token_file.or(other.bearer_token_file),
+            retry_status_codes: self.retry_status_codes.or(other.retry_status_codes),
+            concurrency: self.concurrency.or(other.concurrency),
+            unordered: self.unordered.or(other.unordered),
+            item_retries: self.item_retries.or(other.item_retries),
+            items_per_request: self.items_per_request.or(other.items_per_request),
+            dead_letter_dir: self.dead_letter_dir.or(other.dead_letter_dir),
         }
     }
 }
@@ -56,10 +189,82 @@ impl SinkWebhookOptions {
             Some(headers) => parse_headers(&headers)?,
         };
 
+        let signer = match self.signing_secret {
+            None => None,
+            Some(secret) if secret.is_empty() => {
+                return Err(SinkWebhookError).attach_printable("signing secret is empty");
+            }
+            Some(secret) => {
+                let header = self
+                    .signature_header
+                    .as_deref()
+                    .unwrap_or(DEFAULT_SIGNATURE_HEADER)
+                    .parse::<HeaderName>()
+                    .change_context(SinkWebhookError)
+                    .attach_printable("failed to parse signature header name")?;
+                Some(RequestSigner {
+                    secret: secret.into_bytes(),
+                    header,
+                })
+            }
+        };
+
+        let retry_status_codes = self
+            .retry_status_codes
+            .unwrap_or_else(|| DEFAULT_RETRY_STATUS_CODES.to_vec());
+        if let Some(code) = retry_status_codes
+            .iter()
+            .find(|code| !(400..500).contains(*code))
+        {
+            return Err(SinkWebhookError).attach_printable_lazy(|| {
+                format!("retry status code {code} is not a client error")
+            });
+        }
+
+        let dead_letter_dir = self.dead_letter_dir.map(PathBuf::from);
+
+        let default_delivery = DeliveryConfiguration::default();
+        // Without retries, a transient error would skip the request.
+        let default_retries = if dead_letter_dir.is_some() {
+            DEFAULT_DEAD_LETTER_RETRIES
+        } else {
+            default_delivery.retries
+        };
+        let delivery = DeliveryConfiguration {
+            concurrency: self.concurrency.unwrap_or(default_delivery.concurrency),
+            ordered: !self.unordered.unwrap_or(!default_delivery.ordered),
+            retries: self.item_retries.unwrap_or(default_retries),
+            items_per_request: self
+                .items_per_request
+                .unwrap_or(default_delivery.items_per_request),
+        };
+        if delivery.concurrency == 0 {
+            return Err(SinkWebhookError).attach_printable("concurrency must be positive");
+        }
+        if delivery.items_per_request == 0 {
+            return Err(SinkWebhookError).attach_printable("items per request must be positive");
+        }
+        if dead_letter_dir.is_some() && delivery.retries == 0 {
+            return Err(SinkWebhookError)
+                .attach_printable("item retries must be positive with a dead-letter directory");
+        }
+
+        let envelope = self.envelope.unwrap_or(false);
+        if envelope && delivery.items_per_request > 1 {
+            return Err(SinkWebhookError)
+                .attach_printable("envelopes can't be sent with more than one item per request");
+        }
+
         Ok(SinkWebhookConfiguration {
             target_url,
             headers,
-            raw: self.raw.unwrap_or(false),
+            raw: envelope || self.raw.unwrap_or(false),
+            envelope,
+            signer,
+            bearer_token_file: self.bearer_token_file.map(PathBuf::from),
+            retry_status_codes,
+            delivery,
+            dead_letter_dir,
         })
     }
 }
@@ -73,14 +278,7 @@ fn parse_headers(headers: &[String]) -> Result<HeaderMap, SinkWebhookError> {
                     .attach_printable("header not in the `key: value` format")
             }
             Some((name, value)) => {
-                let name = name
-                    .parse::<HeaderName>()
-                    .change_context(SinkWebhookError)
-                    .attach_printable("failed to parse header name")?;
-                let value = value
-                    .parse::<HeaderValue>()
-                    .change_context(SinkWebhookError)
-                    .attach_printable("failed to parse header value")?;
+                let (name, value) = parse_header(name, value)?;
                 new_headers.append(name, value);
             }
         }
@@ -88,3 +286,18 @@ fn parse_headers(headers: &[String]) -> Result<HeaderMap, SinkWebhookError> {
 
     Ok(new_headers)
 }
+
+pub(crate) fn parse_header(
+    name: &str,
+    value: &str,
+) -> Result<(HeaderName, HeaderValue), SinkWebhookError> {
+    let name = name
+        .parse::<HeaderName>()
+        .change_context(SinkWebhookError)
+        .attach_printable("failed to parse header name")?;
+    let value = value
+        .parse::<HeaderValue>()
+        .change_context(SinkWebhookError)
+        .attach_printable("failed to parse header value")?;
+    Ok((name, value))
+}
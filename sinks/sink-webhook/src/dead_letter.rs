@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use apibara_core::node::v1alpha2::Cursor;
+use error_stack::{AttachmentKind, FrameKind, Report, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::sink::{SinkWebhookError, WebhookSink};
+
+/// Extension of the files containing dead letters.
+const DEAD_LETTER_FILE_EXTENSION: &str = "jsonl";
+
+/// A request that could not be delivered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The cursor of the data that generated the request.
+    pub cursor: Option<Cursor>,
+    pub end_cursor: Option<Cursor>,
+    /// The request body.
+    pub payload: Value,
+    /// Why the request failed.
+    pub error: String,
+}
+
+/// Stores requests that could not be delivered, one JSON object per line.
+///
+/// Each push writes a new file in the dead-letter directory. Files are written under a
+/// temporary name and renamed once complete, and the sink never changes them afterwards,
+/// so that the directory can be replayed while the sink is running.
+pub struct DeadLetterQueue {
+    dir: PathBuf,
+    /// Prefix of the files written by this run of the sink.
+    prefix: String,
+    /// Sequence number of the next file.
+    next_file: AtomicUsize,
+}
+
+/// Result of replaying dead letters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub delivered: usize,
+    pub failed: usize,
+}
+
+impl DeadLetterQueue {
+    pub fn new(dir: &Path) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_millis())
+            .unwrap_or_default();
+        DeadLetterQueue {
+            dir: dir.to_path_buf(),
+            prefix: format!("dead-letters-{timestamp}"),
+            next_file: AtomicUsize::new(0),
+        }
+    }
+
+    /// Stores the dead letters in a new file of the queue.
+    ///
+    /// The data is synced to disk before returning, so that the stream can move on.
+    pub async fn push(&self, dead_letters: &[DeadLetter]) -> Result<(), SinkWebhookError> {
+        if dead_letters.is_empty() {
+            return Ok(());
+        }
+
+        // Sequence numbers are padded so that files sort in the order they were written.
+        let sequence = self.next_file.fetch_add(1, Ordering::SeqCst);
+        let path = self.dir.join(format!(
+            "{}-{sequence:06}.{DEAD_LETTER_FILE_EXTENSION}",
+            self.prefix
+        ));
+
+        warn!(
+            count = dead_letters.len(),
+            path = ?path,
+            "storing undeliverable requests in dead-letter queue"
+        );
+
+        fs::create_dir_all(&self.dir)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to create directory {:?}", self.dir))?;
+
+        let data = to_jsonl(dead_letters)?;
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to create {tmp_path:?}"))?;
+        file.write_all(&data)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to write {tmp_path:?}"))?;
+        file.sync_data()
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to sync {tmp_path:?}"))?;
+
+        fs::rename(&tmp_path, &path)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to rename {tmp_path:?} to {path:?}"))?;
+
+        Ok(())
+    }
+}
+
+/// Sends the dead letters stored in a file, or in all files of a directory, again.
+///
+/// Files are removed once all their dead letters are delivered. Dead letters that
+/// still can't be delivered are kept in the file.
+pub async fn replay_dead_letters(
+    sink: &WebhookSink,
+    path: &Path,
+) -> Result<ReplaySummary, SinkWebhookError> {
+    let metadata = fs::metadata(path)
+        .await
+        .change_context(SinkWebhookError)
+        .attach_printable_lazy(|| format!("failed to read {path:?}"))?;
+
+    let files = if metadata.is_dir() {
+        dead_letter_files(path).await?
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut summary = ReplaySummary::default();
+    for file in files {
+        let file_summary = replay_file(sink, &file).await?;
+        info!(
+            path = ?file,
+            delivered = file_summary.delivered,
+            failed = file_summary.failed,
+            "replayed dead letters"
+        );
+        summary.delivered += file_summary.delivered;
+        summary.failed += file_summary.failed;
+    }
+
+    Ok(summary)
+}
+
+async fn replay_file(sink: &WebhookSink, path: &Path) -> Result<ReplaySummary, SinkWebhookError> {
+    let content = fs::read_to_string(path)
+        .await
+        .change_context(SinkWebhookError)
+        .attach_printable_lazy(|| format!("failed to read {path:?}"))?;
+
+    let mut summary = ReplaySummary::default();
+    let mut failed = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut dead_letter = serde_json::from_str::<DeadLetter>(line)
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("invalid dead letter at {path:?}:{}", index + 1))?;
+
+        let result = match sink.item_request(&dead_letter.payload) {
+            Ok(request) => sink.send_with_retries(&request).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(_) => summary.delivered += 1,
+            Err(err) => {
+                warn!(err = ?err, "failed to replay dead letter");
+                dead_letter.error = error_message(&err);
+                failed.push(dead_letter);
+            }
+        }
+    }
+
+    summary.failed = failed.len();
+    if failed.is_empty() {
+        fs::remove_file(path)
+            .await
+            .change_context(SinkWebhookError)
+            .attach_printable_lazy(|| format!("failed to remove {path:?}"))?;
+        return Ok(summary);
+    }
+
+    // Replace the file atomically so that dead letters are never lost.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, to_jsonl(&failed)?)
+        .await
+        .change_context(SinkWebhookError)
+        .attach_printable_lazy(|| format!("failed to write {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path)
+        .await
+        .change_context(SinkWebhookError)
+        .attach_printable_lazy(|| format!("failed to replace {path:?}"))?;
+
+    Ok(summary)
+}
+
+/// Returns the dead-letter files in the directory, oldest first.
+async fn dead_letter_files(dir: &Path) -> Result<Vec<PathBuf>, SinkWebhookError> {
+    let mut entries = fs::read_dir(dir)
+        .await
+        .change_context(SinkWebhookError)
+        .attach_printable_lazy(|| format!("failed to read directory {dir:?}"))?;
+
+    let mut files = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .change_context(SinkWebhookError)
+        .attach_printable_lazy(|| format!("failed to read directory {dir:?}"))?
+    {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some(DEAD_LETTER_FILE_EXTENSION) {
+            files.push(path);
+        }
+    }
+
+    // Files are named after the time the sink started, then by sequence number.
+    files.sort();
+    Ok(files)
+}
+
+fn to_jsonl(dead_letters: &[DeadLetter]) -> Result<Vec<u8>, SinkWebhookError> {
+    let mut data = Vec::new();
+    for dead_letter in dead_letters {
+        serde_json::to_writer(&mut data, dead_letter)
+            .change_context(SinkWebhookError)
+            .attach_printable("failed to serialize dead letter")?;
+        data.push(b'\n');
+    }
+    Ok(data)
+}
+
+/// Returns the error message stored in dead letters, including the printable attachments.
+pub fn error_message(err: &Report<SinkWebhookError>) -> String {
+    // Frames are iterated from the most recent one, show them in the order they were added.
+    let mut messages = err
+        .frames()
+        .filter_map(|frame| match frame.kind() {
+            FrameKind::Context(context) => Some(context.to_string()),
+            FrameKind::Attachment(AttachmentKind::Printable(attachment)) => {
+                Some(attachment.to_string())
+            }
+            FrameKind::Attachment(_) => None,
+        })
+        .collect::<Vec<_>>();
+    messages.reverse();
+    messages.join(": ")
+}
@@ -1,5 +1,14 @@
+mod auth;
 mod configuration;
+mod dead_letter;
+mod request;
+mod retry;
 mod sink;
 
-pub use self::configuration::{SinkWebhookConfiguration, SinkWebhookOptions};
+pub use self::auth::{RequestSigner, DEFAULT_SIGNATURE_HEADER};
+pub use self::configuration::{
+    DeliveryConfiguration, SinkWebhookConfiguration, SinkWebhookOptions,
+};
+pub use self::dead_letter::{replay_dead_letters, DeadLetter, ReplaySummary};
+pub use self::retry::DEFAULT_RETRY_STATUS_CODES;
 pub use self::sink::{SinkWebhookError, WebhookSink};
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use error_stack::{Result, ResultExt};
+use http::{HeaderMap, Method, Uri};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::configuration::parse_header;
+use crate::sink::SinkWebhookError;
+
+/// A request ready to be sent.
+#[derive(Debug, Clone)]
+pub struct WebhookRequest {
+    pub method: Method,
+    pub url: Uri,
+    pub headers: HeaderMap,
+    /// The JSON-encoded body, if any.
+    pub body: Option<Vec<u8>>,
+}
+
+/// Describes the request used to deliver an item in envelope mode.
+///
+/// All fields are optional, the sink configuration is used for missing fields.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Envelope {
+    pub url: Option<String>,
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// The request body, sent as JSON. The request has no body if missing.
+    pub body: Option<Value>,
+}
+
+impl Envelope {
+    pub fn from_value(item: &Value) -> Result<Self, SinkWebhookError> {
+        Envelope::deserialize(item)
+            .change_context(SinkWebhookError)
+            .attach_printable("item is not a valid `{ url, method, headers, body }` envelope")
+    }
+
+    /// Returns the request described by the envelope.
+    ///
+    /// The headers in the envelope replace the default headers with the same name.
+    pub fn into_request(
+        self,
+        default_url: &Uri,
+        default_headers: &HeaderMap,
+    ) -> Result<WebhookRequest, SinkWebhookError> {
+        let url = match self.url {
+            None => default_url.clone(),
+            Some(url) => url
+                .parse::<Uri>()
+                .change_context(SinkWebhookError)
+                .attach_printable_lazy(|| format!("malformed envelope url {url}"))?,
+        };
+
+        let method = match self.method {
+            None => Method::POST,
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes())
+                .change_context(SinkWebhookError)
+                .attach_printable_lazy(|| format!("invalid envelope method {method}"))?,
+        };
+
+        let mut headers = default_headers.clone();
+        for (name, value) in &self.headers {
+            let (name, value) = parse_header(name, value)?;
+            headers.insert(name, value);
+        }
+
+        let body = self
+            .body
+            .map(|body| serde_json::to_vec(&body))
+            .transpose()
+            .change_context(SinkWebhookError)
+            .attach_printable("failed to serialize envelope body")?;
+
+        Ok(WebhookRequest {
+            method,
+            url,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Returns `true` if the two urls have the same scheme, host and port.
+pub fn is_same_origin(url: &Uri, other: &Uri) -> bool {
+    url.scheme() == other.scheme() && url.authority() == other.authority()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue, Method, Uri};
+    use serde_json::json;
+
+    use super::{is_same_origin, Envelope};
+
+    #[test]
+    pub fn test_envelope_into_request() {
+        let default_url = "https://example.org/webhook".parse::<Uri>().unwrap();
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("x-api-key", HeaderValue::from_static("key"));
+        default_headers.insert("x-source", HeaderValue::from_static("apibara"));
+
+        let envelope = Envelope::from_value(&json!({})).unwrap();
+        let request = envelope
+            .into_request(&default_url, &default_headers)
+            .unwrap();
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.url, default_url);
+        assert_eq!(request.headers, default_headers);
+        assert!(request.body.is_none());
+
+        let envelope = Envelope::from_value(&json!({
+            "url": "https://discord.com/api/webhooks/1/abc",
+            "method": "patch",
+            "headers": { "x-api-key": "other" },
+            "body": { "content": "hello" },
+        }))
+        .unwrap();
+        let request = envelope
+            .into_request(&default_url, &default_headers)
+            .unwrap();
+        assert_eq!(request.method, Method::PATCH);
+        assert_eq!(request.url, "https://discord.com/api/webhooks/1/abc");
+        assert_eq!(request.headers.get("x-api-key").unwrap(), "other");
+        assert_eq!(request.headers.get("x-source").unwrap(), "apibara");
+        assert_eq!(request.body.unwrap(), br#"{"content":"hello"}"#);
+
+        assert!(Envelope::from_value(&json!({ "content": "hello" })).is_err());
+        assert!(Envelope::from_value(&json!([])).is_err());
+        assert!(Envelope::from_value(&json!({ "method": "NOT A METHOD" }))
+            .unwrap()
+            .into_request(&default_url, &default_headers)
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_is_same_origin() {
+        let url = "https://example.org/webhook".parse::<Uri>().unwrap();
+        assert!(is_same_origin(
+            &url,
+            &"https://example.org/other?q=1".parse().unwrap()
+        ));
+        assert!(!is_same_origin(
+            &url,
+            &"http://example.org/webhook".parse().unwrap()
+        ));
+        assert!(!is_same_origin(
+            &url,
+            &"https://example.org:8443/webhook".parse().unwrap()
+        ));
+        assert!(!is_same_origin(
+            &url,
+            &"https://discord.com/webhook".parse().unwrap()
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{Duration, SystemTime};
+
+use http::{header, HeaderMap, StatusCode};
+
+/// Client error status codes that are retried by default.
+///
+/// Server errors are always retried.
+pub const DEFAULT_RETRY_STATUS_CODES: &[u16] = &[408, 425, 429];
+
+/// Maximum time to wait because of a `Retry-After` header.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum time to wait before retrying a request.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Returns how long to wait before retrying a request that failed `attempt` times.
+pub fn retry_delay(attempt: u32) -> Duration {
+    let delay = Duration::from_millis(500).saturating_mul(2u32.saturating_pow(attempt));
+    delay.min(MAX_RETRY_DELAY)
+}
+
+/// Returns `true` if a request that failed with the given status should be retried.
+pub fn is_retryable_status(status: StatusCode, retry_status_codes: &[u16]) -> bool {
+    status.is_server_error() || retry_status_codes.contains(&status.as_u16())
+}
+
+/// Returns how long to wait before sending the next request, as requested by the
+/// `Retry-After` header.
+///
+/// The header contains either a number of seconds or a HTTP date.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            date.duration_since(SystemTime::now()).unwrap_or_default()
+        }
+    };
+
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use http::{header, HeaderMap, HeaderValue, StatusCode};
+
+    use super::{is_retryable_status, parse_retry_after, retry_delay, DEFAULT_RETRY_STATUS_CODES};
+
+    #[test]
+    pub fn test_is_retryable_status() {
+        let codes = DEFAULT_RETRY_STATUS_CODES;
+        assert!(is_retryable_status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            codes
+        ));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE, codes));
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS, codes));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST, codes));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND, codes));
+        assert!(is_retryable_status(StatusCode::NOT_FOUND, &[404]));
+    }
+
+    #[test]
+    pub fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(500));
+        assert_eq!(retry_delay(1), Duration::from_secs(1));
+        assert_eq!(retry_delay(3), Duration::from_secs(4));
+        assert_eq!(retry_delay(10), Duration::from_secs(30));
+        assert_eq!(retry_delay(100), Duration::from_secs(30));
+    }
+
+    #[test]
+    pub fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(
+            parse_retry_after(&headers),
+            Some(Duration::from_secs(60 * 60))
+        );
+
+        headers.insert(
+            header::RETRY_AFTER,
+            HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(600));
+        headers.insert(header::RETRY_AFTER, date.parse().unwrap());
+        let delay = parse_retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(590) && delay <= Duration::from_secs(600));
+
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+}
@@ -1,16 +1,26 @@
+use std::collections::HashSet;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
 use apibara_core::node::v1alpha2::Cursor;
-use apibara_sink_common::{Context, CursorAction, Sink};
+use apibara_sink_common::{is_fatal_error, Context, CursorAction, Sink, SinkConnectorError};
 use async_trait::async_trait;
-use error_stack::{Result, ResultExt};
-use http::HeaderMap;
+use error_stack::{Report, Result, ResultExt};
+use futures::stream::{self, StreamExt};
+use http::{header, HeaderMap, HeaderValue, Method, Uri};
 use reqwest::Client;
 use serde::ser::Serialize;
 use serde_json::{json, Value};
-use tracing::{debug, instrument, warn};
+use tokio::time::Instant;
+use tracing::{debug, info, instrument, warn};
 
-use crate::{configuration::SinkWebhookOptions, SinkWebhookConfiguration};
+use crate::auth::{BearerTokenFile, RequestSigner};
+use crate::configuration::{DeliveryConfiguration, SinkWebhookOptions};
+use crate::dead_letter::{error_message, DeadLetter, DeadLetterQueue};
+use crate::request::{is_same_origin, Envelope, WebhookRequest};
+use crate::retry::{is_retryable_status, parse_retry_after, retry_delay};
+use crate::SinkWebhookConfiguration;
 
 #[derive(Debug)]
 pub struct SinkWebhookError;
@@ -24,43 +34,280 @@ impl fmt::Display for SinkWebhookError {
 
 pub struct WebhookSink {
     client: Client,
-    target_url: String,
+    target_url: Uri,
     headers: HeaderMap,
     raw: bool,
+    envelope: bool,
+    signer: Option<RequestSigner>,
+    bearer_token: Option<BearerTokenFile>,
+    retry_status_codes: Vec<u16>,
+    /// Don't send requests before this instant, as requested by the `Retry-After` header.
+    retry_after: Mutex<Option<Instant>>,
+    delivery: DeliveryConfiguration,
+    /// Requests of the last batch that were delivered before the batch failed.
+    partial_delivery: Option<PartialDelivery>,
+    dead_letters: Option<DeadLetterQueue>,
+}
+
+/// Requests of a batch delivered before one of the other requests failed.
+///
+/// The connector sends the same batch again after a failure, so this is used to avoid
+/// sending the delivered requests twice.
+struct PartialDelivery {
+    end_cursor: Cursor,
+    batch: Value,
+    delivered: HashSet<usize>,
 }
 
 impl WebhookSink {
     pub fn new(config: SinkWebhookConfiguration) -> Self {
         Self {
             client: Client::new(),
-            target_url: config.target_url.to_string(),
+            target_url: config.target_url,
             headers: config.headers,
-            raw: config.raw,
+            raw: config.raw || config.envelope,
+            envelope: config.envelope,
+            signer: config.signer,
+            bearer_token: config.bearer_token_file.map(BearerTokenFile::new),
+            retry_status_codes: config.retry_status_codes,
+            retry_after: Mutex::new(None),
+            delivery: config.delivery,
+            partial_delivery: None,
+            dead_letters: config.dead_letter_dir.as_deref().map(DeadLetterQueue::new),
         }
     }
 
-    #[instrument(skip(self, body), err(Debug))]
-    async fn send<B: Serialize + ?Sized>(&self, body: &B) -> Result<(), SinkWebhookError> {
-        let response = self
+    /// Returns a request to the target url with the given JSON body.
+    fn json_request<B: Serialize + ?Sized>(
+        &self,
+        body: &B,
+    ) -> Result<WebhookRequest, SinkWebhookError> {
+        let body = serde_json::to_vec(body)
+            .change_context(SinkWebhookError)
+            .attach_printable("failed to serialize json data")?;
+
+        Ok(WebhookRequest {
+            method: Method::POST,
+            url: self.target_url.clone(),
+            headers: self.headers.clone(),
+            body: Some(body),
+        })
+    }
+
+    /// Returns the request used to deliver the item returned by the transform script.
+    pub(crate) fn item_request(&self, item: &Value) -> Result<WebhookRequest, SinkWebhookError> {
+        if !self.envelope {
+            return self.json_request(item);
+        }
+
+        // An invalid envelope won't become valid by sending it again.
+        Envelope::from_value(item)
+            .and_then(|envelope| envelope.into_request(&self.target_url, &self.headers))
+            .attach(SinkConnectorError::Fatal)
+    }
+
+    #[instrument(skip(self, request), fields(method = %request.method), err(Debug))]
+    async fn send(&self, request: &WebhookRequest) -> Result<(), SinkWebhookError> {
+        let mut headers = request.headers.clone();
+        if request.body.is_some() {
+            headers
+                .entry(header::CONTENT_TYPE)
+                .or_insert(HeaderValue::from_static("application/json"));
+        }
+
+        // Don't leak credentials to other services called in envelope mode.
+        if is_same_origin(&request.url, &self.target_url) {
+            if let Some(bearer_token) = &self.bearer_token {
+                headers.insert(header::AUTHORIZATION, bearer_token.authorization().await?);
+            }
+            // The signature covers the exact bytes sent.
+            if let Some(signer) = &self.signer {
+                let body = request.body.as_deref().unwrap_or_default();
+                let (name, value) = signer.sign(body)?;
+                headers.insert(name, value);
+            }
+        }
+
+        self.wait_retry_after().await;
+
+        let mut builder = self
             .client
-            .post(&self.target_url)
-            .headers(self.headers.clone())
-            .json(body)
+            .request(request.method.clone(), request.url.to_string())
+            .headers(headers);
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+
+        let response = builder
             .send()
             .await
             .change_context(SinkWebhookError)
-            .attach_printable("failed to POST json data")?;
+            .attach_printable_lazy(|| format!("failed to send {} request", request.method))?;
+
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            match response.text().await {
+                Ok(text) => {
+                    debug!(response = ?text, "call success");
+                }
+                Err(err) => {
+                    warn!(err = ?err, "error reading response");
+                }
+            }
+
+            return Ok(());
+        }
+
+        let retry_after = parse_retry_after(response.headers());
+        let text = response.text().await.unwrap_or_default();
+
+        if !is_retryable_status(status, &self.retry_status_codes) {
+            return Err(SinkWebhookError)
+                .attach_printable_lazy(|| format!("request failed with status {status}"))
+                .attach_printable_lazy(|| format!("response: {text}"))
+                .attach(SinkConnectorError::Fatal);
+        }
+
+        if let Some(retry_after) = retry_after {
+            debug!(retry_after = ?retry_after, "server requested retry after");
+            *self.retry_after.lock().expect("retry after lock") =
+                Some(Instant::now() + retry_after);
+        }
+
+        Err(SinkWebhookError)
+            .attach_printable_lazy(|| format!("request failed with status {status}"))
+            .attach_printable_lazy(|| format!("response: {text}"))
+    }
+
+    /// Sends the request, retrying it as configured if it fails.
+    pub(crate) async fn send_with_retries(
+        &self,
+        request: &WebhookRequest,
+    ) -> Result<(), SinkWebhookError> {
+        let mut attempt = 0;
+        loop {
+            match self.send(request).await {
+                Ok(_) => return Ok(()),
+                Err(err) if attempt >= self.delivery.retries || is_fatal_error(&err) => {
+                    return Err(err);
+                }
+                Err(err) => {
+                    let delay = retry_delay(attempt);
+                    warn!(err = ?err, attempt, delay = ?delay, "request failed, retrying");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Sends the items returned by the transform script in raw mode.
+    ///
+    /// Requests already in `delivered` are skipped. Returns the requests delivered
+    /// together with the body and error of the failed requests.
+    ///
+    /// With ordered delivery and `stop_on_error`, no request is started after a request
+    /// fails, but the requests in flight are completed and recorded as delivered.
+    async fn send_items(
+        &self,
+        items: &[Value],
+        mut delivered: HashSet<usize>,
+        stop_on_error: bool,
+    ) -> (HashSet<usize>, Vec<(Value, Report<SinkWebhookError>)>) {
+        let items_per_request = self.delivery.items_per_request;
+        let pending = items
+            .chunks(items_per_request)
+            .enumerate()
+            .filter(|(index, _)| !delivered.contains(index))
+            .map(|(index, chunk)| {
+                let body = if items_per_request == 1 {
+                    chunk[0].clone()
+                } else {
+                    Value::Array(chunk.to_vec())
+                };
+                (index, body)
+            })
+            .collect::<Vec<_>>();
+        // Stop sending later items so that, with a concurrency of 1, no item is delivered
+        // before the failed one.
+        let stop_on_error = stop_on_error && self.delivery.ordered;
+        let stopped = AtomicBool::new(false);
+        let stopped = &stopped;
+        let requests = pending.into_iter().map(|(index, body)| async move {
+            if stopped.load(Ordering::SeqCst) {
+                return (index, body, None);
+            }
 
-        match response.text().await {
-            Ok(text) => {
-                debug!(response = ?text, "call success");
+            let result = match self.item_request(&body) {
+                Ok(request) => self.send_with_retries(&request).await,
+                Err(err) => Err(err),
+            };
+            if result.is_err() && stop_on_error {
+                stopped.store(true, Ordering::SeqCst);
             }
+            (index, body, Some(result))
+        });
+
+        let requests = stream::iter(requests);
+        let mut responses = if self.delivery.ordered {
+            requests.buffered(self.delivery.concurrency).boxed()
+        } else {
+            requests.buffer_unordered(self.delivery.concurrency).boxed()
+        };
+
+        let mut failed = Vec::new();
+        while let Some((index, body, result)) = responses.next().await {
+            match result {
+                // Not sent because a previous request failed.
+                None => {}
+                Some(Ok(_)) => {
+                    delivered.insert(index);
+                }
+                Some(Err(err)) => {
+                    failed.push((body, err));
+                }
+            }
+        }
+
+        (delivered, failed)
+    }
+
+    /// Sends a request, storing it in the dead-letter queue if it fails and the queue
+    /// is enabled.
+    async fn send_or_dead_letter(
+        &self,
+        ctx: &Context,
+        body: &Value,
+    ) -> Result<(), SinkWebhookError> {
+        let request = self.json_request(body)?;
+        let Some(dead_letters) = &self.dead_letters else {
+            return self.send(&request).await;
+        };
+
+        match self.send_with_retries(&request).await {
+            Ok(_) => Ok(()),
             Err(err) => {
-                warn!(err = ?err, "error reading response");
+                let dead_letter = DeadLetter {
+                    cursor: ctx.cursor.clone(),
+                    end_cursor: Some(ctx.end_cursor.clone()),
+                    payload: body.clone(),
+                    error: error_message(&err),
+                };
+                dead_letters.push(&[dead_letter]).await
             }
         }
+    }
+
+    /// Waits until the instant requested by the last `Retry-After` header.
+    async fn wait_retry_after(&self) {
+        let Some(deadline) = *self.retry_after.lock().expect("retry after lock") else {
+            return;
+        };
 
-        Ok(())
+        if deadline > Instant::now() {
+            info!(delay = ?(deadline - Instant::now()), "waiting before retrying request");
+            tokio::time::sleep_until(deadline).await;
+        }
     }
 }
 
@@ -83,17 +330,52 @@ impl Sink for WebhookSink {
         debug!(ctx = %ctx, "calling with data");
 
         if self.raw {
-            // Send each item returned by the transform script as a separate request
-            let Some(batch) = batch.as_array() else {
+            // Send the items returned by the transform script as separate requests
+            let Some(items) = batch.as_array() else {
                 warn!("raw mode: batch is not an array");
                 return Ok(CursorAction::Persist);
             };
 
-            for item in batch {
-                self.send(&item).await?;
+            let delivered = match self.partial_delivery.take() {
+                Some(partial)
+                    if partial.end_cursor == ctx.end_cursor && &partial.batch == batch =>
+                {
+                    partial.delivered
+                }
+                _ => HashSet::default(),
+            };
+
+            let stop_on_error = self.dead_letters.is_none();
+            let (delivered, mut failed) = self.send_items(items, delivered, stop_on_error).await;
+            if failed.is_empty() {
+                return Ok(CursorAction::Persist);
             }
+
+            // The connector sends the batch again if storing the dead letters fails too.
+            self.partial_delivery = Some(PartialDelivery {
+                end_cursor: ctx.end_cursor.clone(),
+                batch: batch.clone(),
+                delivered,
+            });
+
+            let Some(dead_letters) = &self.dead_letters else {
+                let (_, err) = failed.swap_remove(0);
+                return Err(err);
+            };
+
+            let failed = failed
+                .into_iter()
+                .map(|(payload, err)| DeadLetter {
+                    cursor: ctx.cursor.clone(),
+                    end_cursor: Some(ctx.end_cursor.clone()),
+                    payload,
+                    error: error_message(&err),
+                })
+                .collect::<Vec<_>>();
+            dead_letters.push(&failed).await?;
+            self.partial_delivery = None;
         } else {
-            let body = &json!({
+            let body = json!({
                 "data": {
                     "cursor": ctx.cursor,
                     "end_cursor": ctx.end_cursor,
@@ -101,7 +383,7 @@ impl Sink for WebhookSink {
                     "batch": batch,
                 },
             });
-            self.send(&body).await?;
+            self.send_or_dead_letter(ctx, &body).await?;
         }
 
         Ok(CursorAction::Persist)
@@ -125,6 +407,9 @@ impl Sink for WebhookSink {
             },
         });
 
-        self.send(&body).await
+        // Invalidations are never stored in the dead-letter queue: replaying them after
+        // newer data was delivered would corrupt the receiver.
+        let request = self.json_request(&body)?;
+        self.send(&request).await
     }
 }
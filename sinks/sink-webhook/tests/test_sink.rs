@@ -1,9 +1,18 @@
 use apibara_core::node::v1alpha2::{Cursor, DataFinality};
-use apibara_sink_common::{Context, Sink};
-use apibara_sink_webhook::{SinkWebhookConfiguration, SinkWebhookError, WebhookSink};
+use apibara_sink_common::{is_fatal_error, Context, Sink};
+use apibara_sink_webhook::{
+    replay_dead_letters, DeadLetter, DeliveryConfiguration, RequestSigner,
+    SinkWebhookConfiguration, SinkWebhookError, SinkWebhookOptions, WebhookSink,
+    DEFAULT_SIGNATURE_HEADER,
+};
 use error_stack::{Result, ResultExt};
+use hmac::{Hmac, Mac};
 use http::{HeaderMap, Uri};
 use serde_json::{json, Value};
+use sha2::Sha256;
+use std::time::Duration;
+use wiremock::matchers::{body_json, method};
+use wiremock::{Mock, ResponseTemplate};
 
 fn new_batch(start_cursor: &Option<Cursor>, end_cursor: &Cursor) -> Value {
     let mut batch = Vec::new();
@@ -31,10 +40,28 @@ fn new_cursor(order_key: u64) -> Cursor {
     }
 }
 
+/// Starts a server that accepts all requests.
+async fn start_server() -> wiremock::MockServer {
+    let server = wiremock::MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    server
+}
+
+fn get_header(request: &wiremock::Request, name: &str) -> Option<String> {
+    request
+        .headers
+        .iter()
+        .find(|(header, _)| header.as_str() == name)
+        .map(|(_, values)| values.last().as_str().to_string())
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_handle_data() -> Result<(), SinkWebhookError> {
-    let server = wiremock::MockServer::start().await;
+    let server = start_server().await;
 
     let config = SinkWebhookConfiguration {
         target_url: server
@@ -43,6 +70,7 @@ async fn test_handle_data() -> Result<(), SinkWebhookError> {
             .change_context(SinkWebhookError)?,
         headers: HeaderMap::new(),
         raw: false,
+        ..SinkWebhookConfiguration::default()
     };
 
     let mut sink = WebhookSink::new(config);
@@ -89,7 +117,7 @@ async fn test_handle_data() -> Result<(), SinkWebhookError> {
 #[tokio::test]
 #[ignore]
 async fn test_handle_invalidate() -> Result<(), SinkWebhookError> {
-    let server = wiremock::MockServer::start().await;
+    let server = start_server().await;
 
     let config = SinkWebhookConfiguration {
         target_url: server
@@ -98,6 +126,7 @@ async fn test_handle_invalidate() -> Result<(), SinkWebhookError> {
             .change_context(SinkWebhookError)?,
         headers: HeaderMap::new(),
         raw: false,
+        ..SinkWebhookConfiguration::default()
     };
 
     let mut sink = WebhookSink::new(config);
@@ -129,7 +158,7 @@ async fn test_handle_invalidate() -> Result<(), SinkWebhookError> {
 #[tokio::test]
 #[ignore]
 async fn test_handle_data_raw() -> Result<(), SinkWebhookError> {
-    let server = wiremock::MockServer::start().await;
+    let server = start_server().await;
 
     let config = SinkWebhookConfiguration {
         target_url: server
@@ -138,6 +167,7 @@ async fn test_handle_data_raw() -> Result<(), SinkWebhookError> {
             .change_context(SinkWebhookError)?,
         headers: HeaderMap::new(),
         raw: true,
+        ..SinkWebhookConfiguration::default()
     };
 
     let mut sink = WebhookSink::new(config);
@@ -179,7 +209,7 @@ async fn test_handle_data_raw() -> Result<(), SinkWebhookError> {
 #[tokio::test]
 #[ignore]
 async fn test_handle_invalidate_raw() -> Result<(), SinkWebhookError> {
-    let server = wiremock::MockServer::start().await;
+    let server = start_server().await;
 
     let config = SinkWebhookConfiguration {
         target_url: server
@@ -188,6 +218,7 @@ async fn test_handle_invalidate_raw() -> Result<(), SinkWebhookError> {
             .change_context(SinkWebhookError)?,
         headers: HeaderMap::new(),
         raw: true,
+        ..SinkWebhookConfiguration::default()
     };
 
     let mut sink = WebhookSink::new(config);
@@ -203,3 +234,584 @@ async fn test_handle_invalidate_raw() -> Result<(), SinkWebhookError> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_signature_and_bearer_token() -> Result<(), SinkWebhookError> {
+    let server = start_server().await;
+    let token_dir = tempfile::tempdir().change_context(SinkWebhookError)?;
+    let token_file = token_dir.path().join("token");
+    std::fs::write(&token_file, "first-token\n").change_context(SinkWebhookError)?;
+
+    let config = SinkWebhookConfiguration {
+        target_url: server
+            .uri()
+            .parse::<Uri>()
+            .change_context(SinkWebhookError)?,
+        headers: HeaderMap::new(),
+        raw: false,
+        signer: Some(RequestSigner {
+            secret: b"top-secret".to_vec(),
+            header: DEFAULT_SIGNATURE_HEADER.parse().unwrap(),
+        }),
+        bearer_token_file: Some(token_file.clone()),
+    };
+
+    let mut sink = WebhookSink::new(config);
+
+    for (order_key, token) in [(0, "first-token"), (1, "rotated-token")] {
+        if order_key > 0 {
+            std::fs::write(&token_file, token).change_context(SinkWebhookError)?;
+        }
+
+        let cursor = Some(new_cursor(order_key));
+        let end_cursor = new_cursor(order_key + 1);
+        let ctx = Context {
+            cursor: cursor.clone(),
+            end_cursor: end_cursor.clone(),
+            finality: DataFinality::DataStatusFinalized,
+        };
+        sink.handle_data(&ctx, &new_batch(&cursor, &end_cursor))
+            .await?;
+
+        let requests = server.received_requests().await.unwrap();
+        let request = requests.last().unwrap();
+
+        let authorization = get_header(request, "authorization").unwrap();
+        assert_eq!(authorization, format!("Bearer {token}"));
+
+        let signature = get_header(request, DEFAULT_SIGNATURE_HEADER).unwrap();
+        let (timestamp, signature) = signature.split_once(',').unwrap();
+        let timestamp = timestamp.strip_prefix("t=").unwrap();
+        let signature = signature.strip_prefix("v1=").unwrap();
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"top-secret").unwrap();
+        mac.update(format!("{timestamp}.").as_bytes());
+        mac.update(&request.body);
+        assert_eq!(signature, hex::encode(mac.finalize().into_bytes()));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_error_status() -> Result<(), SinkWebhookError> {
+    let server = wiremock::MockServer::start().await;
+
+    let config = SinkWebhookConfiguration {
+        target_url: server
+            .uri()
+            .parse::<Uri>()
+            .change_context(SinkWebhookError)?,
+        headers: HeaderMap::new(),
+        raw: false,
+        ..SinkWebhookConfiguration::default()
+    };
+
+    let mut sink = WebhookSink::new(config);
+
+    let cursor = Some(new_cursor(0));
+    let end_cursor = new_cursor(1);
+    let ctx = Context {
+        cursor: cursor.clone(),
+        end_cursor: end_cursor.clone(),
+        finality: DataFinality::DataStatusFinalized,
+    };
+    let batch = new_batch(&cursor, &end_cursor);
+
+    // Server errors are retried.
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    let err = sink.handle_data(&ctx, &batch).await.unwrap_err();
+    assert!(!is_fatal_error(&err));
+
+    // The next request waits for the time requested by the server.
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    let err = sink.handle_data(&ctx, &batch).await.unwrap_err();
+    assert!(!is_fatal_error(&err));
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    let start = std::time::Instant::now();
+    sink.handle_data(&ctx, &batch).await?;
+    assert!(start.elapsed() >= std::time::Duration::from_millis(900));
+
+    // Other client errors are fatal.
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400).set_body_string("invalid payload"))
+        .mount(&server)
+        .await;
+    let err = sink.handle_data(&ctx, &batch).await.unwrap_err();
+    assert!(is_fatal_error(&err));
+    assert!(format!("{err:?}").contains("invalid payload"));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_raw_with_delivery() -> Result<(), SinkWebhookError> {
+    let server = wiremock::MockServer::start().await;
+    // The first request fails and is retried.
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let config = SinkWebhookConfiguration {
+        target_url: server
+            .uri()
+            .parse::<Uri>()
+            .change_context(SinkWebhookError)?,
+        headers: HeaderMap::new(),
+        raw: true,
+        delivery: DeliveryConfiguration {
+            concurrency: 4,
+            ordered: false,
+            retries: 1,
+            items_per_request: 3,
+        },
+        ..SinkWebhookConfiguration::default()
+    };
+
+    let mut sink = WebhookSink::new(config);
+
+    let cursor = Some(new_cursor(0));
+    let end_cursor = new_cursor(20);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality: DataFinality::DataStatusFinalized,
+    };
+
+    sink.handle_data(&ctx, &batch).await?;
+
+    // 20 items in 7 requests, plus the retried request.
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 8);
+
+    let mut received = Vec::new();
+    for request in &requests {
+        let items = request
+            .body_json::<Vec<Value>>()
+            .change_context(SinkWebhookError)?;
+        assert!(!items.is_empty() && items.len() <= 3);
+        received.extend(items);
+    }
+    received.sort_by_key(|item| item["block_num"].as_u64().unwrap());
+    received.dedup();
+    assert_eq!(&Value::Array(received), &batch);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_raw_ordered_with_error() -> Result<(), SinkWebhookError> {
+    let server = wiremock::MockServer::start().await;
+
+    let cursor = Some(new_cursor(0));
+    let end_cursor = new_cursor(8);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality: DataFinality::DataStatusFinalized,
+    };
+
+    // The first item fails while the next requests are still in flight.
+    Mock::given(method("POST"))
+        .and(body_json(&batch[0]))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&server)
+        .await;
+
+    let config = SinkWebhookConfiguration {
+        target_url: server
+            .uri()
+            .parse::<Uri>()
+            .change_context(SinkWebhookError)?,
+        headers: HeaderMap::new(),
+        raw: true,
+        delivery: DeliveryConfiguration {
+            concurrency: 4,
+            ordered: true,
+            retries: 0,
+            items_per_request: 1,
+        },
+        ..SinkWebhookConfiguration::default()
+    };
+
+    let mut sink = WebhookSink::new(config);
+
+    assert!(sink.handle_data(&ctx, &batch).await.is_err());
+
+    // No request is started after the failure, the requests in flight complete.
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 4);
+
+    server.reset().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    sink.handle_data(&ctx, &batch).await?;
+
+    // The items delivered by the requests in flight are not sent again.
+    let mut received = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            request
+                .body_json::<Value>()
+                .change_context(SinkWebhookError)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    received.sort_by_key(|item| item["block_num"].as_u64().unwrap());
+    assert_eq!(
+        received,
+        vec![
+            batch[0].clone(),
+            batch[4].clone(),
+            batch[5].clone(),
+            batch[6].clone(),
+            batch[7].clone(),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_raw_with_dead_letters() -> Result<(), SinkWebhookError> {
+    let server = wiremock::MockServer::start().await;
+    let dead_letter_dir = tempfile::tempdir().change_context(SinkWebhookError)?;
+
+    let cursor = Some(new_cursor(0));
+    let end_cursor = new_cursor(5);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor: cursor.clone(),
+        end_cursor: end_cursor.clone(),
+        finality: DataFinality::DataStatusFinalized,
+    };
+
+    // The receiver rejects one of the items.
+    let rejected = batch[3].clone();
+    Mock::given(method("POST"))
+        .and(body_json(&rejected))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let config = SinkWebhookConfiguration {
+        target_url: server
+            .uri()
+            .parse::<Uri>()
+            .change_context(SinkWebhookError)?,
+        headers: HeaderMap::new(),
+        raw: true,
+        dead_letter_dir: Some(dead_letter_dir.path().to_path_buf()),
+        ..SinkWebhookConfiguration::default()
+    };
+
+    let mut sink = WebhookSink::new(config);
+
+    // The stream moves on even if an item can't be delivered.
+    sink.handle_data(&ctx, &batch).await?;
+    assert_eq!(server.received_requests().await.unwrap().len(), 5);
+
+    let files = std::fs::read_dir(dead_letter_dir.path())
+        .change_context(SinkWebhookError)?
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 1);
+
+    let content = std::fs::read_to_string(&files[0]).change_context(SinkWebhookError)?;
+    let dead_letters = content
+        .lines()
+        .map(|line| serde_json::from_str::<DeadLetter>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].cursor, cursor);
+    assert_eq!(dead_letters[0].end_cursor, Some(end_cursor));
+    assert_eq!(dead_letters[0].payload, rejected);
+    assert!(dead_letters[0].error.contains("400"));
+
+    // Replay once the receiver accepts the item.
+    server.reset().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let summary = replay_dead_letters(&sink, dead_letter_dir.path()).await?;
+    assert_eq!(summary.delivered, 1);
+    assert_eq!(summary.failed, 0);
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0]
+            .body_json::<Value>()
+            .change_context(SinkWebhookError)?,
+        rejected
+    );
+    assert!(!files[0].exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_dead_letters_after_retries() -> Result<(), SinkWebhookError> {
+    let server = wiremock::MockServer::start().await;
+    let dead_letter_dir = tempfile::tempdir().change_context(SinkWebhookError)?;
+
+    // The receiver is briefly unavailable.
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let new_options = |sink_options: Value| {
+        serde_json::from_value::<SinkWebhookOptions>(json!({
+            "sinkType": "webhook",
+            "sinkOptions": sink_options,
+        }))
+        .change_context(SinkWebhookError)
+    };
+
+    // Item retries default to a positive value with a dead-letter directory.
+    let options = new_options(json!({
+        "targetUrl": server.uri(),
+        "raw": true,
+        "deadLetterDir": dead_letter_dir.path(),
+    }))?;
+    let mut sink = WebhookSink::from_options(options).await?;
+
+    let cursor = Some(new_cursor(0));
+    let end_cursor = new_cursor(1);
+    let batch = new_batch(&cursor, &end_cursor);
+    let ctx = Context {
+        cursor,
+        end_cursor,
+        finality: DataFinality::DataStatusFinalized,
+    };
+    sink.handle_data(&ctx, &batch).await?;
+
+    // The request is delivered on the second attempt and not stored.
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    let files = std::fs::read_dir(dead_letter_dir.path())
+        .change_context(SinkWebhookError)?
+        .count();
+    assert_eq!(files, 0);
+
+    // Storing requests without retrying them is rejected.
+    let options = new_options(json!({
+        "targetUrl": server.uri(),
+        "raw": true,
+        "itemRetries": 0,
+        "deadLetterDir": dead_letter_dir.path(),
+    }))?;
+    assert!(WebhookSink::from_options(options).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_replay_dead_letters_while_running() -> Result<(), SinkWebhookError> {
+    let server = wiremock::MockServer::start().await;
+    let dead_letter_dir = tempfile::tempdir().change_context(SinkWebhookError)?;
+
+    let new_config = |dead_letter_dir: Option<&std::path::Path>| -> Result<_, SinkWebhookError> {
+        Ok(SinkWebhookConfiguration {
+            target_url: server
+                .uri()
+                .parse::<Uri>()
+                .change_context(SinkWebhookError)?,
+            headers: HeaderMap::new(),
+            raw: true,
+            dead_letter_dir: dead_letter_dir.map(|dir| dir.to_path_buf()),
+            ..SinkWebhookConfiguration::default()
+        })
+    };
+
+    let mut sink = WebhookSink::new(new_config(Some(dead_letter_dir.path()))?);
+    let replay_sink = WebhookSink::new(new_config(None)?);
+
+    let first_batch = new_batch(&Some(new_cursor(0)), &new_cursor(1));
+    let first_ctx = Context {
+        cursor: Some(new_cursor(0)),
+        end_cursor: new_cursor(1),
+        finality: DataFinality::DataStatusFinalized,
+    };
+    let second_batch = new_batch(&Some(new_cursor(1)), &new_cursor(2));
+    let second_ctx = Context {
+        cursor: Some(new_cursor(1)),
+        end_cursor: new_cursor(2),
+        finality: DataFinality::DataStatusFinalized,
+    };
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    sink.handle_data(&first_ctx, &first_batch).await?;
+
+    // The first item is accepted slowly, so that the sink stores the second item
+    // while the dead letters are replayed.
+    server.reset().await;
+    Mock::given(method("POST"))
+        .and(body_json(&second_batch[0]))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&server)
+        .await;
+
+    let (summary, result) = tokio::join!(
+        replay_dead_letters(&replay_sink, dead_letter_dir.path()),
+        async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sink.handle_data(&second_ctx, &second_batch).await
+        }
+    );
+    result?;
+    assert_eq!(summary?.delivered, 1);
+
+    // Only the dead letter stored during the replay is left.
+    let mut dead_letters = Vec::new();
+    for entry in std::fs::read_dir(dead_letter_dir.path()).change_context(SinkWebhookError)? {
+        let path = entry.change_context(SinkWebhookError)?.path();
+        assert_eq!(path.extension().unwrap(), "jsonl");
+        let content = std::fs::read_to_string(&path).change_context(SinkWebhookError)?;
+        dead_letters.extend(
+            content
+                .lines()
+                .map(|line| serde_json::from_str::<DeadLetter>(line).unwrap()),
+        );
+    }
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].payload, second_batch[0]);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_handle_data_with_envelopes() -> Result<(), SinkWebhookError> {
+    let server = start_server().await;
+    let other_server = wiremock::MockServer::start().await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&other_server)
+        .await;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-source", "apibara".parse().unwrap());
+    let config = SinkWebhookConfiguration {
+        target_url: server
+            .uri()
+            .parse::<Uri>()
+            .change_context(SinkWebhookError)?,
+        headers,
+        envelope: true,
+        signer: Some(RequestSigner {
+            secret: b"top-secret".to_vec(),
+            header: DEFAULT_SIGNATURE_HEADER.parse().unwrap(),
+        }),
+        ..SinkWebhookConfiguration::default()
+    };
+
+    let mut sink = WebhookSink::new(config);
+
+    let batch = json!([
+        {
+            "body": { "event": "transfer" },
+        },
+        {
+            "url": format!("{}/messages/1", other_server.uri()),
+            "method": "DELETE",
+            "headers": { "x-source": "indexer" },
+        },
+    ]);
+    let ctx = Context {
+        cursor: Some(new_cursor(0)),
+        end_cursor: new_cursor(1),
+        finality: DataFinality::DataStatusFinalized,
+    };
+
+    sink.handle_data(&ctx, &batch).await?;
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method.to_string(), "POST");
+    assert_eq!(
+        requests[0]
+            .body_json::<Value>()
+            .change_context(SinkWebhookError)?,
+        json!({ "event": "transfer" })
+    );
+    assert_eq!(
+        get_header(&requests[0], "x-source").as_deref(),
+        Some("apibara")
+    );
+    assert!(get_header(&requests[0], DEFAULT_SIGNATURE_HEADER).is_some());
+
+    // Requests to other services don't include the signature.
+    let requests = other_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method.to_string(), "DELETE");
+    assert_eq!(requests[0].url.path(), "/messages/1");
+    assert!(requests[0].body.is_empty());
+    assert_eq!(
+        get_header(&requests[0], "x-source").as_deref(),
+        Some("indexer")
+    );
+    assert!(get_header(&requests[0], DEFAULT_SIGNATURE_HEADER).is_none());
+
+    // Invalid envelopes are not retried.
+    let batch = json!([{ "content": "not an envelope" }]);
+    let err = sink.handle_data(&ctx, &batch).await.unwrap_err();
+    assert!(is_fatal_error(&err));
+
+    Ok(())
+}